@@ -0,0 +1,352 @@
+//CPU block compression for the offline texture processor
+//BC7 (mode 6 only) for color and packed maps, BC4 for single channels and BC5 for two
+//mode 6 is one endpoint pair per block with 16 steps between them, not as good as a full BC7 search but far better than BC1 and quick
+
+//Gathers the 4x4 blocks of an image, edge blocks repeat the last row/column when the size isn't a multiple of 4
+fn blocks<const N: usize>(pixels: &[u8], width: u32, height: u32) -> impl Iterator<Item = [[u8; N]; 16]> + '_ {
+    let (w, h) = (width as usize, height as usize);
+    let (bw, bh) = (w.div_ceil(4), h.div_ceil(4));
+    (0..bw * bh).map(move |b| {
+        let (bx, by) = (b % bw, b / bw);
+        let mut block = [[0u8; N]; 16];
+        for (i, px) in block.iter_mut().enumerate() {
+            let x = (bx * 4 + i % 4).min(w - 1);
+            let y = (by * 4 + i / 4).min(h - 1);
+            let at = (y * w + x) * N;
+            px.copy_from_slice(&pixels[at..at + N]);
+        }
+        block
+    })
+}
+
+//BC4
+//one channel, two 8 bit endpoints and 8 steps between them
+fn bc4_block(values: [u8; 16]) -> [u8; 8] {
+    let max = *values.iter().max().unwrap_or(&0);
+    let min = *values.iter().min().unwrap_or(&0);
+    let mut out = [0u8; 8];
+    out[0] = max;
+    out[1] = min;
+    if max == min {
+        return out;
+    }
+
+    //with the first endpoint bigger the palette is the two ends plus 6 evenly spaced in between
+    let (r0, r1) = (max as u32, min as u32);
+    let palette = [r0, r1, (6 * r0 + r1) / 7, (5 * r0 + 2 * r1) / 7, (4 * r0 + 3 * r1) / 7, (3 * r0 + 4 * r1) / 7, (2 * r0 + 5 * r1) / 7, (r0 + 6 * r1) / 7];
+    let mut bits = 0u64;
+    for (i, v) in values.iter().enumerate() {
+        let index = (0..8).min_by_key(|&p| (palette[p] as i32 - *v as i32).abs()).unwrap_or(0);
+        bits |= (index as u64) << (3 * i);
+    }
+    out[2..].copy_from_slice(&bits.to_le_bytes()[..6]);
+    out
+}
+
+//single channel, tightly packed pixels
+pub fn encode_bc4(pixels: &[u8], width: u32, height: u32) -> Vec<u8> {
+    blocks::<1>(pixels, width, height)
+        .flat_map(|block| bc4_block(block.map(|p| p[0])))
+        .collect()
+}
+
+//two channels, each one gets compressed like BC4
+pub fn encode_bc5(pixels: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let mut out = Vec::new();
+    for block in blocks::<2>(pixels, width, height) {
+        out.extend_from_slice(&bc4_block(block.map(|p| p[0])));
+        out.extend_from_slice(&bc4_block(block.map(|p| p[1])));
+    }
+    out
+}
+
+//BC7
+const BC7_WEIGHTS: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+fn interpolate(e0: [u8; 4], e1: [u8; 4], index: usize) -> [u8; 4] {
+    let w = BC7_WEIGHTS[index];
+    let mut out = [0u8; 4];
+    for c in 0..4 {
+        out[c] = (((64 - w) * e0[c] as u32 + w * e1[c] as u32 + 32) >> 6) as u8;
+    }
+    out
+}
+
+fn distance(a: [u8; 4], b: [u8; 4]) -> u32 {
+    a.iter().zip(b.iter()).map(|(x, y)| (*x as i32 - *y as i32).pow(2) as u32).sum()
+}
+
+//mode 6 endpoints are 7 bits a channel plus one shared low bit (the p-bit) per endpoint, picks whichever p-bit lands closer
+fn quantize(endpoint: [f32; 4]) -> ([u8; 4], u8) {
+    let mut best = ([0u8; 4], 0u8, u32::MAX);
+    for p in 0..2u8 {
+        let mut q = [0u8; 4];
+        let mut err = 0;
+        for c in 0..4 {
+            let v7 = ((endpoint[c] - p as f32) / 2.0).round().clamp(0.0, 127.0) as u8;
+            q[c] = v7;
+            let back = ((v7 << 1) | p) as f32;
+            err += ((back - endpoint[c]) * (back - endpoint[c])) as u32;
+        }
+        if err < best.2 {
+            best = (q, p, err);
+        }
+    }
+    (best.0, best.1)
+}
+
+fn expand(q: [u8; 4], p: u8) -> [u8; 4] {
+    q.map(|v| (v << 1) | p)
+}
+
+//picks the closest palette entry for every pixel, returns them and the total squared error
+fn fit_indices(block: &[[u8; 4]; 16], e0: [u8; 4], e1: [u8; 4]) -> ([usize; 16], u32) {
+    let palette = (0..16).map(|i| interpolate(e0, e1, i)).collect::<Vec<_>>();
+    let mut indices = [0usize; 16];
+    let mut total = 0;
+    for (i, px) in block.iter().enumerate() {
+        let (index, err) = palette.iter().enumerate().map(|(j, c)| (j, distance(*c, *px))).min_by_key(|(_, e)| *e).unwrap_or((0, 0));
+        indices[i] = index;
+        total += err;
+    }
+    (indices, total)
+}
+
+//endpoints along the direction the block's colors vary the most
+fn principal_endpoints(block: &[[u8; 4]; 16]) -> ([f32; 4], [f32; 4]) {
+    let px = block.map(|p| p.map(|v| v as f32));
+    let mut mean = [0.0f32; 4];
+    for p in px.iter() {
+        for c in 0..4 {
+            mean[c] += p[c] / 16.0;
+        }
+    }
+    let mut cov = [[0.0f32; 4]; 4];
+    for p in px.iter() {
+        for a in 0..4 {
+            for b in 0..4 {
+                cov[a][b] += (p[a] - mean[a]) * (p[b] - mean[b]);
+            }
+        }
+    }
+
+    //power iteration, a handful of steps is plenty for a 4x4 matrix
+    let mut axis = [1.0f32, 1.0, 1.0, 1.0];
+    for _ in 0..8 {
+        let mut next = [0.0f32; 4];
+        for a in 0..4 {
+            for b in 0..4 {
+                next[a] += cov[a][b] * axis[b];
+            }
+        }
+        let len = next.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if len < 1e-6 {
+            return (mean, mean);
+        }
+        axis = next.map(|v| v / len);
+    }
+
+    let project = |p: &[f32; 4]| (0..4).map(|c| (p[c] - mean[c]) * axis[c]).sum::<f32>();
+    let (mut lo, mut hi) = (f32::MAX, f32::MIN);
+    for p in px.iter() {
+        let t = project(p);
+        lo = lo.min(t);
+        hi = hi.max(t);
+    }
+    let at = |t: f32| [0, 1, 2, 3].map(|c| (mean[c] + axis[c] * t).clamp(0.0, 255.0));
+    (at(lo), at(hi))
+}
+
+//given which palette step each pixel uses, the endpoints that fit them best (least squares)
+fn refine_endpoints(block: &[[u8; 4]; 16], indices: &[usize; 16]) -> Option<([f32; 4], [f32; 4])> {
+    let (mut aa, mut bb, mut ab) = (0.0f32, 0.0f32, 0.0f32);
+    let mut ax = [0.0f32; 4];
+    let mut bx = [0.0f32; 4];
+    for (px, index) in block.iter().zip(indices.iter()) {
+        let b = BC7_WEIGHTS[*index] as f32 / 64.0;
+        let a = 1.0 - b;
+        aa += a * a;
+        bb += b * b;
+        ab += a * b;
+        for c in 0..4 {
+            ax[c] += a * px[c] as f32;
+            bx[c] += b * px[c] as f32;
+        }
+    }
+    let det = aa * bb - ab * ab;
+    if det.abs() < 1e-6 {
+        return None;
+    }
+    let e0 = [0, 1, 2, 3].map(|c| ((ax[c] * bb - bx[c] * ab) / det).clamp(0.0, 255.0));
+    let e1 = [0, 1, 2, 3].map(|c| ((bx[c] * aa - ax[c] * ab) / det).clamp(0.0, 255.0));
+    Some((e0, e1))
+}
+
+fn bc7_block(block: [[u8; 4]; 16]) -> [u8; 16] {
+    let (start0, start1) = principal_endpoints(&block);
+    let (mut q0, mut p0) = quantize(start0);
+    let (mut q1, mut p1) = quantize(start1);
+    let (mut indices, err) = fit_indices(&block, expand(q0, p0), expand(q1, p1));
+
+    //one round of least squares usually gets a bit more out of it, only kept if it helps
+    if let Some((r0, r1)) = refine_endpoints(&block, &indices) {
+        let (rq0, rp0) = quantize(r0);
+        let (rq1, rp1) = quantize(r1);
+        let (r_indices, r_err) = fit_indices(&block, expand(rq0, rp0), expand(rq1, rp1));
+        if r_err < err {
+            q0 = rq0;
+            p0 = rp0;
+            q1 = rq1;
+            p1 = rp1;
+            indices = r_indices;
+        }
+    }
+
+    //the first pixel's index only gets 3 bits, so its top bit has to be 0, swapping the endpoints flips every index
+    if indices[0] >= 8 {
+        std::mem::swap(&mut q0, &mut q1);
+        std::mem::swap(&mut p0, &mut p1);
+        for i in indices.iter_mut() {
+            *i = 15 - *i;
+        }
+    }
+
+    let mut bits = 0u128;
+    let mut pos = 0;
+    let mut put = |value: u128, count: u32| {
+        bits |= value << pos;
+        pos += count;
+    };
+    //mode 6 is six 0 bits then a 1
+    put(1 << 6, 7);
+    for c in 0..4 {
+        put(q0[c] as u128, 7);
+        put(q1[c] as u128, 7);
+    }
+    put(p0 as u128, 1);
+    put(p1 as u128, 1);
+    for (i, index) in indices.iter().enumerate() {
+        put(*index as u128, if i == 0 { 3 } else { 4 });
+    }
+    bits.to_le_bytes()
+}
+
+//RGBA8, tightly packed, sRGB data can go straight in since the format only changes how the GPU decodes it
+pub fn encode_bc7(pixels: &[u8], width: u32, height: u32) -> Vec<u8> {
+    blocks::<4>(pixels, width, height).flat_map(bc7_block).collect()
+}
+
+//reading BC7 mode 6 back, used to check what the encoder did
+pub fn decode_bc7_mode6(block: &[u8; 16]) -> Option<[[u8; 4]; 16]> {
+    let bits = u128::from_le_bytes(*block);
+    if bits & 0x7f != 1 << 6 {
+        return None;
+    }
+    let mut pos = 7;
+    let mut take = |count: u32| {
+        let v = (bits >> pos) & ((1u128 << count) - 1);
+        pos += count;
+        v as u8
+    };
+    //endpoints are stored a channel at a time, R0 R1 G0 G1...
+    let raw = [0u8; 8].map(|_| take(7));
+    let q0 = [raw[0], raw[2], raw[4], raw[6]];
+    let q1 = [raw[1], raw[3], raw[5], raw[7]];
+    let (p0, p1) = (take(1), take(1));
+    let (e0, e1) = (expand(q0, p0), expand(q1, p1));
+    let mut out = [[0u8; 4]; 16];
+    for (i, px) in out.iter_mut().enumerate() {
+        *px = interpolate(e0, e1, take(if i == 0 { 3 } else { 4 }) as usize);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::*;
+
+    //the palette a BC4 block decodes to, only the max > min layout since that's all bc4_block writes
+    fn decode_bc4(block: &[u8]) -> [u8; 16] {
+        let (r0, r1) = (block[0] as u32, block[1] as u32);
+        let palette = [r0, r1, (6 * r0 + r1) / 7, (5 * r0 + 2 * r1) / 7, (4 * r0 + 3 * r1) / 7, (3 * r0 + 4 * r1) / 7, (2 * r0 + 5 * r1) / 7, (r0 + 6 * r1) / 7];
+        let mut bits = [0u8; 8];
+        bits[..6].copy_from_slice(&block[2..8]);
+        let bits = u64::from_le_bytes(bits);
+        let mut out = [0u8; 16];
+        for (i, v) in out.iter_mut().enumerate() {
+            *v = palette[(bits >> (3 * i) & 7) as usize] as u8;
+        }
+        out
+    }
+
+    fn max_error(a: &[u8], b: &[u8]) -> u8 {
+        a.iter().zip(b.iter()).map(|(x, y)| (*x as i32 - *y as i32).unsigned_abs() as u8).max().unwrap_or(0)
+    }
+
+    #[test]
+    fn bc7_gradient_round_trips() {
+        //a gradient along one line in color space is exactly what mode 6 is good at
+        let pixels = (0..16u32).flat_map(|i| [(i * 16) as u8, 40 + (i * 8) as u8, 200 - (i * 10) as u8, 255]).collect::<Vec<_>>();
+        let encoded = encode_bc7(&pixels, 4, 4);
+        assert_eq!(encoded.len(), 16);
+        let decoded = decode_bc7_mode6(&encoded.try_into().unwrap()).expect("encoder writes mode 6");
+        let decoded = decoded.iter().flatten().copied().collect::<Vec<_>>();
+        assert!(max_error(&pixels, &decoded) <= 6, "max error {}", max_error(&pixels, &decoded));
+    }
+
+    #[test]
+    fn bc7_solid_and_noisy_blocks_stay_close() {
+        let solid = [90u8, 130, 170, 255].repeat(16);
+        let encoded = encode_bc7(&solid, 4, 4);
+        let decoded = decode_bc7_mode6(&encoded.try_into().unwrap()).unwrap();
+        assert!(max_error(&solid, &decoded.iter().flatten().copied().collect::<Vec<_>>()) <= 2);
+
+        //a gradient with some noise on it isn't on a line so it can't be exact, but it should stay about as close as the noise
+        let noisy = (0..64u32).map(|i| ((i / 4) * 12 + (i * 97 + 13) % 9) as u8).collect::<Vec<_>>();
+        let encoded = encode_bc7(&noisy, 4, 4);
+        let decoded = decode_bc7_mode6(&encoded.try_into().unwrap()).unwrap();
+        assert!(max_error(&noisy, &decoded.iter().flatten().copied().collect::<Vec<_>>()) <= 10);
+    }
+
+    #[test]
+    fn decode_bc7_rejects_other_modes() {
+        let mut block = [0u8; 16];
+        block[0] = 1;
+        assert!(decode_bc7_mode6(&block).is_none());
+    }
+
+    #[test]
+    fn bc4_endpoints_and_indices() {
+        let values = (0..16u32).map(|i| (20 + i * 12) as u8).collect::<Vec<_>>();
+        let encoded = encode_bc4(&values, 4, 4);
+        assert_eq!(encoded.len(), 8);
+        assert_eq!(encoded[0], 200);
+        assert_eq!(encoded[1], 20);
+        //the steps are 180 / 7 apart so nothing can be further than half of one from its value
+        assert!(max_error(&values, &decode_bc4(&encoded)) <= 13);
+
+        //a flat block is just the two endpoints and every index 0
+        let flat = encode_bc4(&[77; 16], 4, 4);
+        assert_eq!(flat, vec![77, 77, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn bc5_is_two_bc4_blocks() {
+        let pixels = (0..16u32).flat_map(|i| [(i * 15) as u8, 255 - (i * 3) as u8]).collect::<Vec<_>>();
+        let encoded = encode_bc5(&pixels, 4, 4);
+        assert_eq!(encoded.len(), 16);
+        let red = pixels.iter().step_by(2).copied().collect::<Vec<_>>();
+        let green = pixels.iter().skip(1).step_by(2).copied().collect::<Vec<_>>();
+        assert_eq!(&encoded[..8], &encode_bc4(&red, 4, 4)[..]);
+        assert_eq!(&encoded[8..], &encode_bc4(&green, 4, 4)[..]);
+    }
+
+    #[test]
+    fn odd_sizes_round_up_to_whole_blocks() {
+        assert_eq!(encode_bc4(&[0; 5 * 3], 5, 3).len(), 2 * 8);
+        assert_eq!(encode_bc7(&[0; 5 * 3 * 4], 5, 3).len(), 2 * 16);
+        assert_eq!(encode_bc7(&[0; 4], 1, 1).len(), 16);
+    }
+}
@@ -0,0 +1,534 @@
+use cgmath::{Deg, Point3};
+use vulkano::swapchain::PresentMode;
+
+use project_g::{Background, Camera, Displacement, NormalConvention, Parallax, ShadowSettings, Tonemapper, VulkanOptions};
+use project_g::material::MaterialSet;
+
+pub const USAGE: &str = "\
+Usage: project_g [OPTIONS] [MODEL]
+       project_g process <DIR|BASE> [-o <DIR>] [--normal <gl|dx>] [--normal-strength <F>]
+
+MODEL is an OBJ, glTF or GLB file, or the name of a built in shape
+(sphere, icosphere, cube, plane, torus, cylinder). Defaults to sphere.obj.
+
+Options:
+  -m, --model <PATH>          same as passing MODEL
+  -t, --material <DIR|BASE>   texture set for OBJs and shapes, either a folder of maps
+                              (ambientCG/Poly Haven naming) or the shared start of the
+                              file names, a bare name is looked for in ./assets
+                              (default ./assets/MetalPlates006_1K)
+  -s, --size <WxH>            window size in pixels, e.g. 1920x1080
+      --present-mode <MODE>   fifo (vsync, default), mailbox, immediate or relaxed
+      --device <INDEX>        which GPU to use, see --list-devices
+      --list-devices          print the GPUs Vulkan can see and exit
+      --msaa <SAMPLES>        1 (off, default), 2, 4, 8, 16, 32 or 64
+      --tonemapper <NAME>     reinhard (default), aces, uncharted2 or none
+      --camera-pos <X,Y,Z>    where the camera sits (default 1,1,1)
+      --camera-target <X,Y,Z> what the camera looks at (default 0,0,0)
+      --fov <DEGREES>         vertical field of view (default 90)
+      --no-optimize           upload OBJs and shapes without running the mesh optimizer
+      --normal <gl|dx>        which way up the normal map's green channel is, normally worked
+                              out from the file name (NormalGL/NormalDX, plain Normal is gl)
+      --normal-strength <F>   scales how strong the normal map's bumps are (default 1)
+      --parallax-scale <F>    how deep the displacement map's parallax goes in UV units,
+                              0 turns it off (default 0.05)
+      --parallax-steps <MIN,MAX>  layers the parallax steps through, MIN looking straight
+                              at the surface and MAX at grazing angles (default 8,32)
+      --no-parallax-shadows   don't let the displacement map's bumps shadow each other
+      --displacement <MODE>   parallax (default) or tessellation, which splits the mesh up
+                              on the GPU and really moves it so silhouettes change, the
+                              parallax scale is then in world units
+      --tess-edge <PIXELS>    how long tessellated edges end up on screen (default 8)
+      --env <PATH>            equirectangular HDR/EXR (or any image) to light the scene with,
+                              without one the ambient light is a flat dim grey
+      --env-intensity <F>     scales the environment's light (default 1)
+      --env-rotation <DEGREES>  turns the environment around the vertical axis (default 0)
+      --background <MODE>     what's behind the model: env (the environment, default),
+                              a color R,G,B or a gradient TOP/BOTTOM like 0.5,0.6,0.8/0.2,0.2,0.2,
+                              colors are 0-1 and show up as they are
+      --background-blur <F>   0 (sharp, default) to 1 (as blurry as the roughest reflection)
+      --background-intensity <F>  scales the environment background on top of --env-intensity
+      --lights <RIG>          three-point (key, fill and rim placed around the camera, default),
+                              single (one white directional light) or none (just the environment)
+      --no-shadows            turn the shadow maps off
+      --shadow-resolution <N> size of each directional light's shadow maps in texels (default 2048)
+      --local-shadow-resolution <N>  the same for each spot light and each side of a point
+                              light's cube (default 1024)
+      --shadow-cascades <N>   how many slices the view gets cut in to for each directional
+                              light's shadows, 1 to 4 (default 4)
+      --shadow-distance <F>   how far from the camera shadows reach (default 20)
+      --shadow-softness <F>   radius of the shadow filter in texels, 0 is hard (default 1.5)
+  -h, --help                  print this and exit
+
+process turns a texture set (same lookup as --material) in to BC compressed KTX2 files
+with their mips already made and AO/roughness/metalness packed in to one ORM texture,
+plus a manifest.json. Pass the output folder to --material to use it.
+  -o, --out <DIR>             where to write it (default ./processed/<material name>)
+      --normal, --normal-strength  same as above, the result is always stored as gl
+
+Exit codes:
+  0  closed normally
+  2  bad command line
+  3  a model or texture couldn't be loaded
+  4  no usable GPU or Vulkan setup failed
+  5  rendering failed after the window opened";
+
+//Everything the command line can set
+pub struct Args {
+    pub model: String,
+    pub material: String,
+    pub vulkan: VulkanOptions,
+    pub tonemapper: Tonemapper,
+    pub camera: Camera,
+    pub optimize: bool,
+    pub normal: NormalOptions,
+    pub parallax: Parallax,
+    pub displacement: Displacement,
+    pub environment: Option<String>,
+    pub environment_intensity: f32,
+    pub environment_rotation: Deg<f32>,
+    pub background: Background,
+    pub lights: LightRig,
+    pub shadows: ShadowSettings,
+}
+
+//which lights main.rs sets up, see three_point_rig in renderer.rs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LightRig {
+    ThreePoint,
+    Single,
+    None,
+}
+
+//--normal and --normal-strength, they win over whatever the material's file names say
+#[derive(Default)]
+pub struct NormalOptions {
+    pub convention: Option<NormalConvention>,
+    pub strength: Option<f32>,
+}
+
+impl NormalOptions {
+    pub fn apply(&self, set: &mut MaterialSet) {
+        if let Some(convention) = self.convention {
+            set.normal_convention = convention;
+        }
+        if let Some(strength) = self.strength {
+            set.normal_strength = strength;
+        }
+    }
+
+    //true if flag was one of ours
+    fn parse(&mut self, flag: &str, value: &mut dyn FnMut() -> Result<String, String>) -> Result<bool, String> {
+        match flag {
+            "--normal" => self.convention = Some(parse_normal_convention(&value()?)?),
+            "--normal-strength" => {
+                let strength: f32 = parse_number(flag, &value()?)?;
+                if !strength.is_finite() || strength < 0.0 {
+                    return Err(format!("--normal-strength can't be negative, got {}", strength));
+                }
+                self.strength = Some(strength);
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+pub enum Command {
+    Run(Box<Args>),
+    Help,
+    ListDevices,
+    //texture set to process and where to put it
+    Process { material: String, out: Option<String>, normal: NormalOptions },
+}
+
+//Parses everything after the program name, the error is a message for the user
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut parsed = Args {
+        model: "sphere.obj".to_string(),
+        material: "./assets/MetalPlates006_1K".to_string(),
+        vulkan: VulkanOptions::default(),
+        tonemapper: Tonemapper::default(),
+        camera: Camera::default(),
+        optimize: true,
+        normal: NormalOptions::default(),
+        parallax: Parallax::default(),
+        displacement: Displacement::default(),
+        environment: None,
+        environment_intensity: 1.0,
+        environment_rotation: Deg(0.0),
+        background: Background::default(),
+        lights: LightRig::ThreePoint,
+        shadows: ShadowSettings::default(),
+    };
+    let mut tessellate = false;
+    let mut edge_pixels: f32 = 8.0;
+    //a color or gradient, or None for the environment which takes the blur and intensity
+    let mut background = None;
+    let mut background_blur: f32 = 0.0;
+    let mut background_intensity: f32 = 1.0;
+    let mut model_given = false;
+
+    let mut args = args.into_iter().peekable();
+    if args.peek().map(|a| a.as_str()) == Some("process") {
+        args.next();
+        return parse_process(args);
+    }
+    while let Some(arg) = args.next() {
+        //both --flag value and --flag=value work
+        let (flag, inline) = match arg.split_once('=') {
+            Some((f, v)) if f.starts_with("--") => (f.to_string(), Some(v.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline.clone().or_else(|| args.next()).ok_or_else(|| format!("{} needs a value", flag))
+        };
+
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--list-devices" => return Ok(Command::ListDevices),
+            "-m" | "--model" => {
+                parsed.model = value()?;
+                model_given = true;
+            }
+            "-t" | "--material" => parsed.material = value()?,
+            "-s" | "--size" => parsed.vulkan.window_size = Some(parse_size(&value()?)?),
+            "--present-mode" => parsed.vulkan.present_mode = parse_present_mode(&value()?)?,
+            "--device" => parsed.vulkan.device_index = Some(parse_number(&flag, &value()?)?),
+            "--msaa" => parsed.vulkan.samples = parse_msaa(&value()?)?,
+            "--tonemapper" => parsed.tonemapper = parse_tonemapper(&value()?)?,
+            "--camera-pos" => parsed.camera.eye = parse_point(&flag, &value()?)?,
+            "--camera-target" => parsed.camera.target = parse_point(&flag, &value()?)?,
+            "--fov" => {
+                let fov: f32 = parse_number(&flag, &value()?)?;
+                if !(fov > 0.0 && fov < 180.0) {
+                    return Err(format!("--fov has to be between 0 and 180 degrees, got {}", fov));
+                }
+                parsed.camera.fov_y = Deg(fov).into();
+            }
+            "--no-optimize" => parsed.optimize = false,
+            "--parallax-scale" => {
+                let scale: f32 = parse_number(&flag, &value()?)?;
+                if !scale.is_finite() || scale < 0.0 {
+                    return Err(format!("--parallax-scale can't be negative, got {}", scale));
+                }
+                parsed.parallax.height_scale = scale;
+            }
+            "--parallax-steps" => {
+                let (min, max) = parse_steps(&value()?)?;
+                parsed.parallax.min_steps = min;
+                parsed.parallax.max_steps = max;
+            }
+            "--no-parallax-shadows" => parsed.parallax.self_shadow = false,
+            "--displacement" => tessellate = parse_displacement(&value()?)?,
+            "--tess-edge" => {
+                edge_pixels = parse_number(&flag, &value()?)?;
+                if !edge_pixels.is_finite() || edge_pixels < 1.0 {
+                    return Err(format!("--tess-edge has to be at least 1 pixel, got {}", edge_pixels));
+                }
+            }
+            "--env" => parsed.environment = Some(value()?),
+            "--env-intensity" => {
+                let intensity: f32 = parse_number(&flag, &value()?)?;
+                if !intensity.is_finite() || intensity < 0.0 {
+                    return Err(format!("--env-intensity can't be negative, got {}", intensity));
+                }
+                parsed.environment_intensity = intensity;
+            }
+            "--env-rotation" => {
+                let degrees: f32 = parse_number(&flag, &value()?)?;
+                if !degrees.is_finite() {
+                    return Err(format!("--env-rotation has to be a number of degrees, got {}", degrees));
+                }
+                parsed.environment_rotation = Deg(degrees);
+            }
+            "--lights" => parsed.lights = parse_light_rig(&value()?)?,
+            "--no-shadows" => parsed.shadows.enabled = false,
+            "--shadow-resolution" => {
+                let resolution: u32 = parse_number(&flag, &value()?)?;
+                if !(16..=16384).contains(&resolution) {
+                    return Err(format!("--shadow-resolution has to be between 16 and 16384, got {}", resolution));
+                }
+                parsed.shadows.resolution = resolution;
+            }
+            "--local-shadow-resolution" => {
+                let resolution: u32 = parse_number(&flag, &value()?)?;
+                if !(16..=16384).contains(&resolution) {
+                    return Err(format!("--local-shadow-resolution has to be between 16 and 16384, got {}", resolution));
+                }
+                parsed.shadows.local_resolution = resolution;
+            }
+            "--shadow-cascades" => {
+                let cascades: u32 = parse_number(&flag, &value()?)?;
+                if !(1..=4).contains(&cascades) {
+                    return Err(format!("--shadow-cascades has to be between 1 and 4, got {}", cascades));
+                }
+                parsed.shadows.cascades = cascades;
+            }
+            "--shadow-distance" => {
+                let distance: f32 = parse_number(&flag, &value()?)?;
+                if !distance.is_finite() || distance <= 0.0 {
+                    return Err(format!("--shadow-distance has to be more than 0, got {}", distance));
+                }
+                parsed.shadows.distance = distance;
+            }
+            "--shadow-softness" => {
+                let softness: f32 = parse_number(&flag, &value()?)?;
+                if !softness.is_finite() || softness < 0.0 {
+                    return Err(format!("--shadow-softness can't be negative, got {}", softness));
+                }
+                parsed.shadows.softness = softness;
+            }
+            "--background" => background = parse_background(&value()?)?,
+            "--background-blur" => {
+                background_blur = parse_number(&flag, &value()?)?;
+                if !(0.0..=1.0).contains(&background_blur) {
+                    return Err(format!("--background-blur has to be between 0 and 1, got {}", background_blur));
+                }
+            }
+            "--background-intensity" => {
+                background_intensity = parse_number(&flag, &value()?)?;
+                if !background_intensity.is_finite() || background_intensity < 0.0 {
+                    return Err(format!("--background-intensity can't be negative, got {}", background_intensity));
+                }
+            }
+            f if parsed.normal.parse(f, &mut value)? => {}
+            f if f.starts_with('-') && f.len() > 1 => return Err(format!("unknown option {}", f)),
+            _ if !model_given => {
+                parsed.model = arg;
+                model_given = true;
+            }
+            _ => return Err(format!("only one model can be shown at a time, got {:?} and {:?}", parsed.model, arg)),
+        }
+    }
+
+    if tessellate {
+        parsed.displacement = Displacement::Tessellation { edge_pixels };
+    }
+    parsed.background = background.unwrap_or(Background::Environment { blur: background_blur, intensity: background_intensity });
+    Ok(Command::Run(Box::new(parsed)))
+}
+
+//the arguments after "process"
+fn parse_process<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+    let mut material = None;
+    let mut out = None;
+    let mut normal = NormalOptions::default();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((f, v)) if f.starts_with("--") => (f.to_string(), Some(v.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline.clone().or_else(|| args.next()).ok_or_else(|| format!("{} needs a value", flag))
+        };
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-o" | "--out" => out = Some(value()?),
+            f if normal.parse(f, &mut value)? => {}
+            f if f.starts_with('-') && f.len() > 1 => return Err(format!("unknown option {} for process", f)),
+            _ if material.is_none() => material = Some(arg),
+            _ => return Err(format!("process takes one material at a time, got {:?} and {:?}", material.unwrap_or_default(), arg)),
+        }
+    }
+    let material = material.ok_or("process needs a material folder or name")?;
+    Ok(Command::Process { material, out, normal })
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, s: &str) -> Result<T, String> {
+    s.trim().parse().map_err(|_| format!("{} expects a number, got {:?}", flag, s))
+}
+
+fn parse_size(s: &str) -> Result<[u32; 2], String> {
+    let err = || format!("--size expects WIDTHxHEIGHT, got {:?}", s);
+    let (w, h) = s.split_once(['x', 'X']).ok_or_else(err)?;
+    let w: u32 = w.trim().parse().map_err(|_| err())?;
+    let h: u32 = h.trim().parse().map_err(|_| err())?;
+    if w == 0 || h == 0 {
+        return Err(err());
+    }
+    Ok([w, h])
+}
+
+fn parse_present_mode(s: &str) -> Result<PresentMode, String> {
+    match s.to_lowercase().as_str() {
+        "fifo" | "vsync" => Ok(PresentMode::Fifo),
+        "mailbox" => Ok(PresentMode::Mailbox),
+        "immediate" => Ok(PresentMode::Immediate),
+        "relaxed" | "fifo-relaxed" => Ok(PresentMode::Relaxed),
+        _ => Err(format!("unknown present mode {:?}, expected fifo, mailbox, immediate or relaxed", s)),
+    }
+}
+
+fn parse_msaa(s: &str) -> Result<u32, String> {
+    let samples: u32 = parse_number("--msaa", s)?;
+    if samples == 0 || samples > 64 || !samples.is_power_of_two() {
+        return Err(format!("--msaa has to be 1, 2, 4, 8, 16, 32 or 64, got {}", samples));
+    }
+    Ok(samples)
+}
+
+fn parse_tonemapper(s: &str) -> Result<Tonemapper, String> {
+    match s.to_lowercase().as_str() {
+        "reinhard" => Ok(Tonemapper::Reinhard),
+        "aces" => Ok(Tonemapper::Aces),
+        "uncharted2" | "filmic" | "hable" => Ok(Tonemapper::Uncharted2),
+        "none" | "clamp" => Ok(Tonemapper::None),
+        _ => Err(format!("unknown tonemapper {:?}, expected reinhard, aces, uncharted2 or none", s)),
+    }
+}
+
+fn parse_normal_convention(s: &str) -> Result<NormalConvention, String> {
+    match s.to_lowercase().as_str() {
+        "gl" | "opengl" => Ok(NormalConvention::OpenGl),
+        "dx" | "directx" => Ok(NormalConvention::DirectX),
+        _ => Err(format!("unknown normal map convention {:?}, expected gl or dx", s)),
+    }
+}
+
+//true for tessellation
+fn parse_displacement(s: &str) -> Result<bool, String> {
+    match s.to_lowercase().as_str() {
+        "parallax" | "pom" => Ok(false),
+        "tessellation" | "tess" => Ok(true),
+        _ => Err(format!("unknown displacement mode {:?}, expected parallax or tessellation", s)),
+    }
+}
+
+fn parse_light_rig(s: &str) -> Result<LightRig, String> {
+    match s.to_lowercase().as_str() {
+        "three-point" | "3-point" | "studio" => Ok(LightRig::ThreePoint),
+        "single" | "one" => Ok(LightRig::Single),
+        "none" | "off" => Ok(LightRig::None),
+        _ => Err(format!("unknown light rig {:?}, expected three-point, single or none", s)),
+    }
+}
+
+//None for the environment
+fn parse_background(s: &str) -> Result<Option<Background>, String> {
+    match s.to_lowercase().as_str() {
+        "env" | "environment" => return Ok(None),
+        _ => {}
+    }
+    match s.split_once('/') {
+        Some((top, bottom)) => Ok(Some(Background::Gradient { top: parse_color(top)?, bottom: parse_color(bottom)? })),
+        None => Ok(Some(Background::Color(parse_color(s)?))),
+    }
+}
+
+fn parse_color(s: &str) -> Result<[f32; 3], String> {
+    let err = || format!("--background expects env, R,G,B or R,G,B/R,G,B with each value from 0 to 1, got {:?}", s);
+    let parts = s.split(',').map(|p| p.trim().parse::<f32>().map_err(|_| err())).collect::<Result<Vec<_>, _>>()?;
+    match parts.as_slice() {
+        [r, g, b] if parts.iter().all(|c| (0.0..=1.0).contains(c)) => Ok([*r, *g, *b]),
+        _ => Err(err()),
+    }
+}
+
+fn parse_steps(s: &str) -> Result<(u32, u32), String> {
+    let err = || format!("--parallax-steps expects MIN,MAX with 1 <= MIN <= MAX <= 256, got {:?}", s);
+    let (min, max) = s.split_once(',').ok_or_else(err)?;
+    let min: u32 = min.trim().parse().map_err(|_| err())?;
+    let max: u32 = max.trim().parse().map_err(|_| err())?;
+    if min == 0 || min > max || max > 256 {
+        return Err(err());
+    }
+    Ok((min, max))
+}
+
+fn parse_point(flag: &str, s: &str) -> Result<Point3<f32>, String> {
+    let parts = s.split(',').map(|p| parse_number::<f32>(flag, p)).collect::<Result<Vec<_>, _>>()?;
+    match parts.as_slice() {
+        [x, y, z] => Ok(Point3::new(*x, *y, *z)),
+        _ => Err(format!("{} expects X,Y,Z, got {:?}", flag, s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(args: &[&str]) -> Result<Args, String> {
+        match parse(args.iter().map(|a| a.to_string()))? {
+            Command::Run(args) => Ok(*args),
+            _ => Err("not a run command".to_string()),
+        }
+    }
+
+    #[test]
+    fn flags_take_values_either_way() {
+        let a = run(&["--msaa", "4", "--tonemapper=aces", "-s", "800x600", "cube"]).unwrap();
+        assert_eq!(a.vulkan.samples, 4);
+        assert_eq!(a.tonemapper, Tonemapper::Aces);
+        assert_eq!(a.vulkan.window_size, Some([800, 600]));
+        assert_eq!(a.model, "cube");
+
+        let a = run(&["--model=torus", "--fov=60", "--normal=dx"]).unwrap();
+        assert_eq!(a.model, "torus");
+        assert_eq!(a.normal.convention, Some(NormalConvention::DirectX));
+        assert!(run(&["--msaa"]).is_err());
+    }
+
+    #[test]
+    fn process_gets_its_own_options() {
+        match parse(["process", "Bricks", "-o", "out", "--normal-strength=0.5"].iter().map(|a| a.to_string())).unwrap() {
+            Command::Process { material, out, normal } => {
+                assert_eq!(material, "Bricks");
+                assert_eq!(out.as_deref(), Some("out"));
+                assert_eq!(normal.strength, Some(0.5));
+            }
+            _ => panic!("expected process"),
+        }
+        assert!(parse(["process"].iter().map(|a| a.to_string())).is_err());
+        assert!(parse(["process", "a", "b"].iter().map(|a| a.to_string())).is_err());
+        assert!(parse(["process", "a", "--msaa", "4"].iter().map(|a| a.to_string())).is_err());
+    }
+
+    #[test]
+    fn backgrounds() {
+        assert_eq!(run(&[]).unwrap().background, Background::Environment { blur: 0.0, intensity: 1.0 });
+        let a = run(&["--background", "env", "--background-blur", "0.5", "--background-intensity", "2"]).unwrap();
+        assert_eq!(a.background, Background::Environment { blur: 0.5, intensity: 2.0 });
+        assert_eq!(run(&["--background=0.1,0.2,0.3"]).unwrap().background, Background::Color([0.1, 0.2, 0.3]));
+        assert_eq!(
+            run(&["--background", "1,1,1/0,0,0"]).unwrap().background,
+            Background::Gradient { top: [1.0, 1.0, 1.0], bottom: [0.0, 0.0, 0.0] }
+        );
+        assert!(run(&["--background", "1,1"]).is_err());
+        assert!(run(&["--background", "2,0,0"]).is_err());
+        assert!(run(&["--background", "1,1,1/red"]).is_err());
+    }
+
+    #[test]
+    fn parallax_steps() {
+        let a = run(&["--parallax-steps", "4,16"]).unwrap();
+        assert_eq!((a.parallax.min_steps, a.parallax.max_steps), (4, 16));
+        for bad in ["0,8", "16,4", "8,300", "8", "a,b"] {
+            assert!(run(&["--parallax-steps", bad]).is_err(), "{} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        for bad in [
+            &["--msaa", "3"][..],
+            &["--fov", "180"],
+            &["--size", "0x600"],
+            &["--parallax-scale", "-1"],
+            &["--tess-edge", "0.5"],
+            &["--env-intensity", "-2"],
+            &["--shadow-resolution", "8"],
+            &["--shadow-cascades", "5"],
+            &["--shadow-distance", "0"],
+            &["--background-blur", "1.5"],
+            &["--normal-strength", "-1"],
+            &["--present-mode", "sometimes"],
+            &["--bogus"],
+            &["one", "two"],
+        ] {
+            assert!(run(bad).is_err(), "{:?} should be rejected", bad);
+        }
+        let a = run(&["--displacement", "tess", "--tess-edge", "4"]).unwrap();
+        assert_eq!(a.displacement, Displacement::Tessellation { edge_pixels: 4.0 });
+    }
+}
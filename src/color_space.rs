@@ -0,0 +1,167 @@
+//Transfer functions and working out how a PNG's colors are encoded
+//the png crate we're on doesn't read the sRGB/gAMA/iCCP chunks so we walk the chunk list ourselves
+
+//How the color values in an image relate to actual light
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    //the standard sRGB curve, what almost every image editor writes
+    Srgb,
+    //values are light intensities already
+    Linear,
+    //a plain power curve, the number is the gAMA value so stored = linear ^ gamma
+    Gamma(f32),
+}
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+}
+
+impl Encoding {
+    //turns a stored value in [0, 1] in to linear light
+    pub fn to_linear(self, c: f32) -> f32 {
+        match self {
+            Encoding::Srgb => srgb_to_linear(c),
+            Encoding::Linear => c,
+            Encoding::Gamma(gamma) => c.powf(1.0 / gamma),
+        }
+    }
+}
+
+const PNG_SIGNATURE: &[u8] = &[137, 80, 78, 71, 13, 10, 26, 10];
+
+//Reads the color space chunks out of a PNG file, None means it didn't say
+//the PNG spec has sRGB and iCCP override gAMA, so that's the order they're checked in
+pub fn png_encoding(bytes: &[u8]) -> Option<Encoding> {
+    if !bytes.starts_with(PNG_SIGNATURE) {
+        return None;
+    }
+
+    let mut srgb = false;
+    let mut icc = None;
+    let mut gamma = None;
+
+    //every chunk is length, type, data, crc and the ones we want all come before the image data
+    let mut pos = PNG_SIGNATURE.len();
+    while pos + 8 <= bytes.len() {
+        let len = u32::from_be_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]]) as usize;
+        let kind = &bytes[pos + 4..pos + 8];
+        let data = match bytes.get(pos + 8..pos + 8 + len) {
+            Some(data) => data,
+            None => break,
+        };
+        match kind {
+            b"sRGB" => srgb = true,
+            b"iCCP" => icc = Some(icc_encoding(data)),
+            b"gAMA" if len == 4 => {
+                let scaled = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                gamma = Some(scaled as f32 / 100000.0);
+            }
+            b"IDAT" | b"IEND" => break,
+            _ => {}
+        }
+        pos += 12 + len;
+    }
+
+    if srgb {
+        return Some(Encoding::Srgb);
+    }
+    if let Some(Some(encoding)) = icc {
+        return Some(encoding);
+    }
+    match gamma {
+        //0.45455 is what writers put next to an sRGB chunk, close enough that it's meant as sRGB
+        Some(g) if (g - 0.45455).abs() < 0.01 => Some(Encoding::Srgb),
+        Some(g) if (g - 1.0).abs() < 0.01 => Some(Encoding::Linear),
+        Some(g) if g > 0.0 => Some(Encoding::Gamma(g)),
+        _ if icc.is_some() => {
+            println!("WARNING: couldn't tell what color space an embedded ICC profile is, assuming sRGB");
+            Some(Encoding::Srgb)
+        }
+        _ => None,
+    }
+}
+
+//we don't decompress and interpret the profile itself, but its name is almost always telling
+fn icc_encoding(data: &[u8]) -> Option<Encoding> {
+    let name_end = data.iter().position(|b| *b == 0)?;
+    let name = String::from_utf8_lossy(&data[..name_end]).to_lowercase();
+    if name.contains("linear") {
+        Some(Encoding::Linear)
+    } else if name.contains("srgb") || name.contains("61966") {
+        Some(Encoding::Srgb)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //a PNG with just the chunks given, the crcs aren't checked so they're left as zero
+    fn png(chunks: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut bytes = PNG_SIGNATURE.to_vec();
+        let ihdr = [0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0].to_vec();
+        for (kind, data) in [(b"IHDR", ihdr)].iter().chain(chunks.iter()).chain([(b"IDAT", vec![0])].iter()) {
+            bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&kind[..]);
+            bytes.extend_from_slice(data);
+            bytes.extend_from_slice(&[0; 4]);
+        }
+        bytes
+    }
+
+    fn gama(gamma: u32) -> (&'static [u8; 4], Vec<u8>) {
+        (b"gAMA", gamma.to_be_bytes().to_vec())
+    }
+
+    fn iccp(name: &str) -> (&'static [u8; 4], Vec<u8>) {
+        let mut data = name.as_bytes().to_vec();
+        data.extend_from_slice(&[0, 0, 0x78, 0x9c]);
+        (b"iCCP", data)
+    }
+
+    #[test]
+    fn no_chunks_says_nothing() {
+        assert_eq!(png_encoding(&png(&[])), None);
+        assert_eq!(png_encoding(b"not a png"), None);
+    }
+
+    #[test]
+    fn gamma_alone() {
+        assert_eq!(png_encoding(&png(&[gama(45455)])), Some(Encoding::Srgb));
+        assert_eq!(png_encoding(&png(&[gama(100000)])), Some(Encoding::Linear));
+        assert_eq!(png_encoding(&png(&[gama(55556)])), Some(Encoding::Gamma(0.55556)));
+        assert_eq!(png_encoding(&png(&[gama(0)])), None);
+    }
+
+    #[test]
+    fn srgb_beats_everything() {
+        let srgb = || (b"sRGB", vec![0]);
+        assert_eq!(png_encoding(&png(&[srgb()])), Some(Encoding::Srgb));
+        assert_eq!(png_encoding(&png(&[gama(100000), srgb()])), Some(Encoding::Srgb));
+        assert_eq!(png_encoding(&png(&[iccp("Linear Rec709"), srgb()])), Some(Encoding::Srgb));
+    }
+
+    #[test]
+    fn icc_beats_gamma_when_it_can_be_read() {
+        assert_eq!(png_encoding(&png(&[iccp("Linear Rec709"), gama(45455)])), Some(Encoding::Linear));
+        assert_eq!(png_encoding(&png(&[iccp("IEC 61966-2.1"), gama(100000)])), Some(Encoding::Srgb));
+        //a profile we can't name falls back to gAMA, and to sRGB without one
+        assert_eq!(png_encoding(&png(&[iccp("Camera RGB"), gama(100000)])), Some(Encoding::Linear));
+        assert_eq!(png_encoding(&png(&[iccp("Camera RGB")])), Some(Encoding::Srgb));
+    }
+
+    #[test]
+    fn chunks_after_the_image_data_are_ignored() {
+        let mut bytes = png(&[]);
+        bytes.extend_from_slice(&[0, 0, 0, 1]);
+        bytes.extend_from_slice(b"sRGB");
+        bytes.extend_from_slice(&[0, 0, 0, 0, 0]);
+        assert_eq!(png_encoding(&bytes), None);
+    }
+}
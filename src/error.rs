@@ -0,0 +1,198 @@
+use std::{fmt, io, path::PathBuf};
+
+use vulkano::{
+    command_buffer::{
+        AutoCommandBufferBuilderContextError, BeginRenderPassError, BuildError, CommandBufferExecError, DrawError, DrawIndexedError,
+    },
+    descriptor::descriptor_set::{PersistentDescriptorSetBuildError, PersistentDescriptorSetError},
+    device::DeviceCreationError,
+    format::Format,
+    image::{view::ImageViewCreationError, ImageCreationError},
+    instance::InstanceCreationError,
+    memory::DeviceMemoryAllocError,
+    pipeline::GraphicsPipelineCreationError,
+    render_pass::{FramebufferCreationError, RenderPassCreationError},
+    sampler::SamplerCreationError,
+    swapchain::{AcquireError, CapabilitiesError, SwapchainCreationError},
+    sync::FlushError,
+    OomError,
+};
+
+use crate::gltf_import::GltfError;
+use crate::mesh::MeshError;
+
+//Everything that can go wrong between reading files off disk and getting the first frame on screen
+//bad user input (missing files, broken models, no usable GPU) comes back as one of these instead of a panic
+#[derive(Debug)]
+pub enum RendererError {
+    //a file we needed couldn't be read
+    Io { path: PathBuf, source: io::Error },
+    //an image file was there but couldn't be decoded
+    Decode { path: PathBuf, source: Box<dyn std::error::Error + Send + Sync> },
+    Mesh(MeshError),
+    Gltf(GltfError),
+    //no GPU or queue that can do what we need
+    DeviceSelection(String),
+    Instance(InstanceCreationError),
+    Surface(vulkano_win::CreationError),
+    Device(DeviceCreationError),
+    Capabilities(CapabilitiesError),
+    Swapchain(SwapchainCreationError),
+    RenderPass(RenderPassCreationError),
+    Framebuffer(FramebufferCreationError),
+    Shader(OomError),
+    Pipeline(GraphicsPipelineCreationError),
+    Image(ImageCreationError),
+    ImageView(ImageViewCreationError),
+    Sampler(SamplerCreationError),
+    Memory(DeviceMemoryAllocError),
+    //recording or submitting the copy that puts a texture on the GPU failed
+    Upload(String),
+    //the GPU can't sample a texture in this format or anything we know how to convert it to
+    TextureFormat(Format),
+    //couldn't get an image from the swapchain to draw on
+    Acquire(AcquireError),
+    //recording a frame's commands failed, these mean something got bound that doesn't match what the shaders want
+    CommandBuffer(OomError),
+    DescriptorSet(PersistentDescriptorSetError),
+    DescriptorSetBuild(PersistentDescriptorSetBuildError),
+    BeginRenderPass(BeginRenderPassError),
+    EndRenderPass(AutoCommandBufferBuilderContextError),
+    Draw(DrawError),
+    DrawIndexed(DrawIndexedError),
+    BuildCommandBuffer(BuildError),
+    Execute(CommandBufferExecError),
+    //the frame couldn't be submitted to the GPU
+    Flush(FlushError),
+    //an id passed to the Renderer doesn't belong to anything it has
+    UnknownHandle(String),
+}
+
+impl RendererError {
+    pub fn io<P: Into<PathBuf>>(path: P, source: io::Error) -> Self {
+        RendererError::Io { path: path.into(), source }
+    }
+
+    pub fn decode<P: Into<PathBuf>, E: Into<Box<dyn std::error::Error + Send + Sync>>>(path: P, source: E) -> Self {
+        RendererError::Decode { path: path.into(), source: source.into() }
+    }
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RendererError::Io { path, source } => write!(f, "couldn't read {:?}: {}", path, source),
+            RendererError::Decode { path, source } => write!(f, "couldn't decode {:?}: {}", path, source),
+            RendererError::Mesh(e) => write!(f, "{}", e),
+            RendererError::Gltf(e) => write!(f, "{}", e),
+            RendererError::DeviceSelection(msg) => write!(f, "no usable GPU: {}", msg),
+            RendererError::Instance(e) => write!(f, "failed to create Vulkan instance: {}", e),
+            RendererError::Surface(e) => write!(f, "failed to create window surface: {}", e),
+            RendererError::Device(e) => write!(f, "failed to create logical device: {}", e),
+            RendererError::Capabilities(e) => write!(f, "failed to query surface capabilities: {}", e),
+            RendererError::Swapchain(e) => write!(f, "failed to create swapchain: {}", e),
+            RendererError::RenderPass(e) => write!(f, "failed to create render pass: {}", e),
+            RendererError::Framebuffer(e) => write!(f, "failed to create framebuffer: {}", e),
+            RendererError::Shader(e) => write!(f, "failed to load shader: {}", e),
+            RendererError::Pipeline(e) => write!(f, "failed to create graphics pipeline: {}", e),
+            RendererError::Image(e) => write!(f, "failed to create image: {}", e),
+            RendererError::ImageView(e) => write!(f, "failed to create image view: {}", e),
+            RendererError::Sampler(e) => write!(f, "failed to create sampler: {}", e),
+            RendererError::Memory(e) => write!(f, "failed to allocate GPU memory: {}", e),
+            RendererError::Upload(msg) => write!(f, "failed to upload texture: {}", msg),
+            RendererError::TextureFormat(format) => write!(f, "this GPU can't sample {:?} textures", format),
+            RendererError::Acquire(e) => write!(f, "failed to acquire swapchain image: {}", e),
+            RendererError::CommandBuffer(e) => write!(f, "failed to start command buffer: {}", e),
+            RendererError::DescriptorSet(e) => write!(f, "failed to add to descriptor set: {}", e),
+            RendererError::DescriptorSetBuild(e) => write!(f, "failed to build descriptor set: {}", e),
+            RendererError::BeginRenderPass(e) => write!(f, "failed to begin render pass: {}", e),
+            RendererError::EndRenderPass(e) => write!(f, "failed to end render pass: {}", e),
+            RendererError::Draw(e) => write!(f, "failed to record draw: {}", e),
+            RendererError::DrawIndexed(e) => write!(f, "failed to record indexed draw: {}", e),
+            RendererError::BuildCommandBuffer(e) => write!(f, "failed to build command buffer: {}", e),
+            RendererError::Execute(e) => write!(f, "failed to execute command buffer: {}", e),
+            RendererError::Flush(e) => write!(f, "failed to submit frame: {}", e),
+            RendererError::UnknownHandle(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for RendererError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RendererError::Io { source, .. } => Some(source),
+            RendererError::Decode { source, .. } => Some(source.as_ref()),
+            RendererError::Mesh(e) => Some(e),
+            RendererError::Gltf(e) => Some(e),
+            RendererError::DeviceSelection(_) => None,
+            RendererError::Instance(e) => Some(e),
+            RendererError::Surface(e) => Some(e),
+            RendererError::Device(e) => Some(e),
+            RendererError::Capabilities(e) => Some(e),
+            RendererError::Swapchain(e) => Some(e),
+            RendererError::RenderPass(e) => Some(e),
+            RendererError::Framebuffer(e) => Some(e),
+            RendererError::Shader(e) => Some(e),
+            RendererError::Pipeline(e) => Some(e),
+            RendererError::Image(e) => Some(e),
+            RendererError::ImageView(e) => Some(e),
+            RendererError::Sampler(e) => Some(e),
+            RendererError::Memory(e) => Some(e),
+            RendererError::Upload(_) => None,
+            RendererError::TextureFormat(_) => None,
+            RendererError::Acquire(e) => Some(e),
+            RendererError::CommandBuffer(e) => Some(e),
+            RendererError::DescriptorSet(e) => Some(e),
+            RendererError::DescriptorSetBuild(e) => Some(e),
+            RendererError::BeginRenderPass(e) => Some(e),
+            RendererError::EndRenderPass(e) => Some(e),
+            RendererError::Draw(e) => Some(e),
+            RendererError::DrawIndexed(e) => Some(e),
+            RendererError::BuildCommandBuffer(e) => Some(e),
+            RendererError::Execute(e) => Some(e),
+            RendererError::Flush(e) => Some(e),
+            RendererError::UnknownHandle(_) => None,
+        }
+    }
+}
+
+//lets ? turn any of the wrapped errors in to a RendererError
+macro_rules! impl_from {
+    ($($variant:ident($ty:ty)),* $(,)?) => {
+        $(
+            impl From<$ty> for RendererError {
+                fn from(e: $ty) -> Self {
+                    RendererError::$variant(e)
+                }
+            }
+        )*
+    };
+}
+
+impl_from!(
+    Mesh(MeshError),
+    Gltf(GltfError),
+    Instance(InstanceCreationError),
+    Surface(vulkano_win::CreationError),
+    Device(DeviceCreationError),
+    Capabilities(CapabilitiesError),
+    Swapchain(SwapchainCreationError),
+    RenderPass(RenderPassCreationError),
+    Framebuffer(FramebufferCreationError),
+    Shader(OomError),
+    Pipeline(GraphicsPipelineCreationError),
+    Image(ImageCreationError),
+    ImageView(ImageViewCreationError),
+    Sampler(SamplerCreationError),
+    Memory(DeviceMemoryAllocError),
+    Acquire(AcquireError),
+    DescriptorSet(PersistentDescriptorSetError),
+    DescriptorSetBuild(PersistentDescriptorSetBuildError),
+    BeginRenderPass(BeginRenderPassError),
+    EndRenderPass(AutoCommandBufferBuilderContextError),
+    Draw(DrawError),
+    DrawIndexed(DrawIndexedError),
+    BuildCommandBuffer(BuildError),
+    Execute(CommandBufferExecError),
+    Flush(FlushError),
+);
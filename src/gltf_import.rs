@@ -0,0 +1,476 @@
+use std::{collections::HashMap, fmt, path::Path, sync::Arc};
+
+use cgmath::{Matrix, Matrix3, Matrix4, SquareMatrix, Vector3, Vector4};
+use vulkano::{device::Queue, format::Format};
+
+use crate::color_space::{linear_to_srgb, srgb_to_linear};
+use crate::error::RendererError;
+use crate::mesh_gen::generate_normals;
+use crate::mipmaps::MipFilter;
+use crate::render_helpers::{solid_texture, upload_texture, IndexData, ObjectData, Texture};
+use crate::renderer::{Channel, Parallax, TextureChannel};
+use crate::vertex::Vertex;
+use crate::tangents::generate_tangents;
+
+//Everything that can go wrong pulling draw items out of a glTF file
+#[derive(Debug)]
+pub enum GltfError {
+    Gltf(gltf::Error),
+    MissingPositions { mesh: String },
+    //an attribute accessor with a different count than the positions, reading it per vertex would run off the end
+    AttributeCount { mesh: String, attribute: &'static str, count: usize, positions: usize },
+    //an index pointing past the last vertex
+    IndexOutOfRange { mesh: String, index: u32, positions: usize },
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GltfError::Gltf(e) => write!(f, "failed to load glTF: {}", e),
+            GltfError::MissingPositions { mesh } => write!(f, "glTF mesh {:?} has no positions", mesh),
+            GltfError::AttributeCount { mesh, attribute, count, positions } => write!(
+                f,
+                "glTF mesh {:?} has {} {} values but {} positions",
+                mesh, count, attribute, positions
+            ),
+            GltfError::IndexOutOfRange { mesh, index, positions } => write!(
+                f,
+                "glTF mesh {:?} has index {} but only {} vertices",
+                mesh, index, positions
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GltfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GltfError::Gltf(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<gltf::Error> for GltfError {
+    fn from(e: gltf::Error) -> Self {
+        GltfError::Gltf(e)
+    }
+}
+
+//Loads a .gltf or .glb file and turns every triangle primitive in the default scene in to something the event loop can draw
+//the node transforms get baked in to the vertices since the renderer only has one model matrix
+pub fn load_gltf<P: AsRef<Path>>(path: P, queue: Arc<Queue>) -> Result<Vec<ObjectData>, RendererError> {
+    //import pulls in the buffers and images too, whether they are embedded in the file or sitting next to it
+    let (document, buffers, images) = gltf::import(path.as_ref()).map_err(GltfError::from)?;
+
+    let scene = match document.default_scene().or_else(|| document.scenes().next()) {
+        Some(scene) => scene,
+        None => {
+            println!("{:?} has no scenes, nothing to load", path.as_ref());
+            return Ok(Vec::new());
+        }
+    };
+
+    let mut objects = Vec::new();
+    let mut textures = TextureCache { queue, images: &images, uploaded: HashMap::new() };
+    for node in scene.nodes() {
+        load_node(&node, Matrix4::identity(), &buffers, &mut textures, &mut objects)?;
+    }
+
+    Ok(objects)
+}
+
+//Every texture uploaded while loading one file, so meshes and materials that share an image only send it to the GPU once
+//the factors get baked in to the pixels, so they're part of the key along with the image and what it's used as
+//solid fallbacks have no image and get shared the same way
+type TextureKey = (Option<usize>, &'static str, [u32; 4]);
+
+struct TextureCache<'a> {
+    queue: Arc<Queue>,
+    images: &'a [gltf::image::Data],
+    uploaded: HashMap<TextureKey, Texture>,
+}
+
+impl TextureCache<'_> {
+    fn get<F>(&mut self, image: Option<usize>, role: &'static str, factors: [f32; 4], upload: F) -> Result<Texture, RendererError>
+    where
+        F: FnOnce(Option<&gltf::image::Data>, Arc<Queue>) -> Result<Texture, RendererError>,
+    {
+        let key = (image, role, factors.map(f32::to_bits));
+        if let Some(texture) = self.uploaded.get(&key) {
+            return Ok(texture.share());
+        }
+        let texture = upload(image.map(|i| &self.images[i]), self.queue.clone())?;
+        //the first handle waits for the upload, the one kept here never gets waited on
+        self.uploaded.insert(key, texture.share());
+        Ok(texture)
+    }
+}
+
+//we only read the first UV set, a texture on another one would come out scrambled so at least say so
+fn check_tex_coord(material: &gltf::Material, what: &str, set: u32) {
+    if set != 0 {
+        println!(
+            "WARNING: glTF material {:?} reads its {} texture from UV set {}, only set 0 is loaded so it will be mapped wrong",
+            material.name().unwrap_or("unnamed"), what, set
+        );
+    }
+}
+
+//walks down the node tree accumulating transforms, loading any meshes it runs in to
+fn load_node(
+    node: &gltf::Node,
+    parent: Matrix4<f32>,
+    buffers: &[gltf::buffer::Data],
+    textures: &mut TextureCache,
+    objects: &mut Vec<ObjectData>,
+) -> Result<(), RendererError> {
+    let transform = parent * Matrix4::from(node.transform().matrix());
+
+    if let Some(mesh) = node.mesh() {
+        let name = mesh.name().unwrap_or("unnamed").to_string();
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                println!("Skipping {:?} primitive in {:?}, only triangles are supported", primitive.mode(), name);
+                continue;
+            }
+
+            let (vert_data, index_data) = load_primitive(&primitive, &name, transform, buffers)?;
+            let material = primitive.material();
+
+            //roughness and metalness get read straight out of the packed texture, G and B like the spec says
+            let mut data = vec![
+                metallic_roughness_texture(&material, textures)?,
+                opacity_texture(&material, textures)?,
+            ];
+            let ao = if occlusion_is_packed(&material) {
+                TextureChannel::new(0, Channel::R)
+            } else {
+                data.push(occlusion_texture(&material, textures)?);
+                TextureChannel::new(2, Channel::R)
+            };
+
+            let vert_count = vert_data.len();
+            objects.push(ObjectData {
+                vert_data,
+                index_data: IndexData::compact(index_data, vert_count),
+                albedo: albedo_texture(&material, textures)?,
+                normalmap: normal_texture(&material, textures)?,
+                //the normal texture's scale is the same thing as our strength
+                normal_strength: material.normal_texture().map_or(1.0, |n| n.scale()),
+                emission: emissive_texture(&material, textures)?,
+                data,
+                roughness: TextureChannel::new(0, Channel::G),
+                metalness: TextureChannel::new(0, Channel::B),
+                ao,
+                opacity: TextureChannel::new(1, Channel::R),
+                //glTF has no height maps
+                height: None,
+                parallax: Parallax::default(),
+            });
+        }
+    }
+
+    for child in node.children() {
+        load_node(&child, transform, buffers, textures, objects)?;
+    }
+
+    Ok(())
+}
+
+fn load_primitive(
+    primitive: &gltf::Primitive,
+    name: &str,
+    transform: Matrix4<f32>,
+    buffers: &[gltf::buffer::Data],
+) -> Result<(Vec<Vertex>, Vec<u32>), GltfError> {
+    let reader = primitive.reader(|buffer| Some(&*buffers[buffer.index()]));
+
+    let positions: Vec<[f32; 3]> = match reader.read_positions() {
+        Some(p) => p.collect(),
+        None => return Err(GltfError::MissingPositions { mesh: name.to_string() }),
+    };
+    //the spec says primitives without normals get flat shading, they are filled in after the indices are read
+    let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(|n| n.collect());
+    //a primitive with no UVs can still have a plain colored material, so just zero them
+    let uvs: Vec<[f32; 2]> = match reader.read_tex_coords(0) {
+        Some(t) => t.into_f32().collect(),
+        None => vec![[0.0, 0.0]; positions.len()],
+    };
+
+    //normals need the inverse transpose so non uniform scales don't skew them
+    let normal_matrix = Matrix3::from_cols(transform.x.truncate(), transform.y.truncate(), transform.z.truncate())
+        .invert()
+        .unwrap_or_else(Matrix3::identity)
+        .transpose();
+
+    let tangents: Option<Vec<[f32; 4]>> = reader.read_tangents().map(|t| t.collect());
+
+    //tangents move with the model like any other direction, flipping the handedness if the transform mirrors
+    let tangent_matrix = Matrix3::from_cols(transform.x.truncate(), transform.y.truncate(), transform.z.truncate());
+    let mirrored = tangent_matrix.determinant() < 0.0;
+
+    //the importer checks accessors are in bounds of their buffers but not that they agree with each other
+    let counts = [
+        ("NORMAL", normals.as_ref().map(Vec::len)),
+        ("TEXCOORD_0", Some(uvs.len())),
+        ("TANGENT", tangents.as_ref().map(Vec::len)),
+    ];
+    for (attribute, count) in counts.iter() {
+        if let Some(count) = *count {
+            if count != positions.len() {
+                return Err(GltfError::AttributeCount { mesh: name.to_string(), attribute, count, positions: positions.len() });
+            }
+        }
+    }
+
+    //or that the index values point at real vertices
+    let index_data: Option<Vec<u32>> = reader.read_indices().map(|i| i.into_u32().collect());
+    if let Some(&index) = index_data.iter().flatten().find(|&&i| i as usize >= positions.len()) {
+        return Err(GltfError::IndexOutOfRange { mesh: name.to_string(), index, positions: positions.len() });
+    }
+
+    let mut vert_data = positions
+        .iter()
+        .zip(uvs.iter())
+        .enumerate()
+        .map(|(i, (p, uv))| {
+            let p = transform * Vector4::new(p[0], p[1], p[2], 1.0);
+            let n = match &normals {
+                Some(normals) => normal_matrix * Vector3::from(normals[i]),
+                None => Vector3::new(0.0, 0.0, 0.0),
+            };
+            let tangent = match &tangents {
+                Some(tangents) => {
+                    let t = tangent_matrix * Vector3::new(tangents[i][0], tangents[i][1], tangents[i][2]);
+                    let w = if mirrored { -tangents[i][3] } else { tangents[i][3] };
+                    (t.x, t.y, t.z, w)
+                }
+                None => (0.0, 0.0, 0.0, 0.0),
+            };
+            Vertex {
+                position: (p.x, p.y, p.z),
+                normal: (n.x, n.y, n.z),
+                uv: (uv[0], uv[1]),
+                tangent,
+                ..Default::default()
+            }
+        })
+        .collect::<Vec<_>>();
+
+    //non indexed primitives just draw the vertices in order
+    let mut index_data = index_data.unwrap_or_else(|| (0..vert_data.len() as u32).collect());
+
+    if normals.is_none() {
+        println!("WARNING: glTF mesh {:?} has no normals, generating flat normals", name);
+        generate_normals(&mut vert_data, &mut index_data, 0.0);
+    }
+
+    //the spec says to use MikkTSpace when the file doesn't have tangents
+    if tangents.is_none() {
+        generate_tangents(&mut vert_data, &mut index_data);
+    }
+
+    Ok((vert_data, index_data))
+}
+
+//glTF images come in whatever layout the file had, flatten them all to 8 bit RGBA
+fn to_rgba8(image: &gltf::image::Data) -> Vec<u8> {
+    use gltf::image::Format as F;
+
+    //16 bit images come out of the importer as native endian u16s, we just keep the high byte
+    let hi = |px: &[u8], c: usize| (u16::from_ne_bytes([px[c * 2], px[c * 2 + 1]]) >> 8) as u8;
+
+    type Convert<'a> = &'a dyn Fn(&[u8]) -> [u8; 4];
+    let (stride, convert): (usize, Convert) = match image.format {
+        F::R8 => (1, &|px| [px[0], px[0], px[0], 255]),
+        F::R8G8 => (2, &|px| [px[0], px[1], 0, 255]),
+        F::R8G8B8 => (3, &|px| [px[0], px[1], px[2], 255]),
+        F::R8G8B8A8 => (4, &|px| [px[0], px[1], px[2], px[3]]),
+        F::B8G8R8 => (3, &|px| [px[2], px[1], px[0], 255]),
+        F::B8G8R8A8 => (4, &|px| [px[2], px[1], px[0], px[3]]),
+        F::R16 => (2, &|px| [hi(px, 0), hi(px, 0), hi(px, 0), 255]),
+        F::R16G16 => (4, &|px| [hi(px, 0), hi(px, 1), 0, 255]),
+        F::R16G16B16 => (6, &|px| [hi(px, 0), hi(px, 1), hi(px, 2), 255]),
+        F::R16G16B16A16 => (8, &|px| [hi(px, 0), hi(px, 1), hi(px, 2), hi(px, 3)]),
+    };
+
+    image.pixels.chunks_exact(stride).flat_map(|px| convert(px).to_vec()).collect()
+}
+
+//multiplies every pixel by the material factor, since the shader only reads the textures
+fn apply_factor(pixels: &mut [u8], channels: usize, factor: &[f32]) {
+    if factor.iter().all(|f| *f == 1.0) {
+        return;
+    }
+    for px in pixels.chunks_exact_mut(channels) {
+        for (c, f) in px.iter_mut().zip(factor.iter()) {
+            *c = (*c as f32 * f).round().min(255.0) as u8;
+        }
+    }
+}
+
+//same for RGBA8 sRGB textures, the factors are linear so the color gets decoded before multiplying and encoded again after
+//alpha isn't sRGB encoded so it's multiplied as is
+fn apply_srgb_factor(pixels: &mut [u8], factor: &[f32; 4]) {
+    if factor.iter().all(|f| *f == 1.0) {
+        return;
+    }
+    for px in pixels.chunks_exact_mut(4) {
+        for c in 0..3 {
+            px[c] = to_byte(linear_to_srgb(srgb_to_linear(px[c] as f32 / 255.0) * factor[c]));
+        }
+        px[3] = to_byte(px[3] as f32 / 255.0 * factor[3]);
+    }
+}
+
+fn to_byte(v: f32) -> u8 {
+    (v.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn albedo_texture(material: &gltf::Material, textures: &mut TextureCache) -> Result<Texture, RendererError> {
+    let pbr = material.pbr_metallic_roughness();
+    let factor = pbr.base_color_factor();
+
+    match pbr.base_color_texture() {
+        Some(info) => {
+            check_tex_coord(material, "base color", info.tex_coord());
+            textures.get(Some(info.texture().source().index()), "albedo", factor, |image, queue| {
+                let image = image.expect("keyed by an image index");
+                let mut pixels = to_rgba8(image);
+                apply_srgb_factor(&mut pixels, &factor);
+                upload_texture(pixels, image.width, image.height, Format::R8G8B8A8Srgb, MipFilter::Box, queue)
+            })
+        }
+        None => textures.get(None, "albedo", factor, |_, queue| {
+            //the base color factor is linear but the texture is sRGB, so encode it on the way in
+            let pixel = [to_byte(linear_to_srgb(factor[0])), to_byte(linear_to_srgb(factor[1])), to_byte(linear_to_srgb(factor[2])), to_byte(factor[3])];
+            solid_texture(&pixel, Format::R8G8B8A8Srgb, queue)
+        }),
+    }
+}
+
+//glTF packs roughness in to the green channel and metalness in to the blue channel of one texture
+//the shader reads them out of it as is, the factors just get multiplied in to their channels
+fn metallic_roughness_texture(material: &gltf::Material, textures: &mut TextureCache) -> Result<Texture, RendererError> {
+    let pbr = material.pbr_metallic_roughness();
+    let factor = [1.0, pbr.roughness_factor(), pbr.metallic_factor(), 1.0];
+    match pbr.metallic_roughness_texture() {
+        Some(info) => {
+            check_tex_coord(material, "metallic roughness", info.tex_coord());
+            textures.get(Some(info.texture().source().index()), "metallic_roughness", factor, |image, queue| {
+                let image = image.expect("keyed by an image index");
+                let mut pixels = to_rgba8(image);
+                apply_factor(&mut pixels, 4, &factor);
+                upload_texture(pixels, image.width, image.height, Format::R8G8B8A8Unorm, MipFilter::Box, queue)
+            })
+        }
+        None => textures.get(None, "metallic_roughness", factor, |_, queue| {
+            solid_texture(&[255, to_byte(factor[1]), to_byte(factor[2]), 255], Format::R8G8B8A8Unorm, queue)
+        }),
+    }
+}
+
+//ORM files point the occlusion texture at the same image as metallicRoughness, with occlusion in red
+//that only works when strength is 1, otherwise it has to be baked in to its own texture
+fn occlusion_is_packed(material: &gltf::Material) -> bool {
+    let packed = material.pbr_metallic_roughness().metallic_roughness_texture().map(|info| info.texture().source().index());
+    match material.occlusion_texture() {
+        Some(occlusion) => Some(occlusion.texture().source().index()) == packed && occlusion.strength() == 1.0,
+        None => false,
+    }
+}
+
+fn normal_texture(material: &gltf::Material, textures: &mut TextureCache) -> Result<Texture, RendererError> {
+    match material.normal_texture() {
+        Some(normal) => {
+            check_tex_coord(material, "normal", normal.tex_coord());
+            textures.get(Some(normal.texture().source().index()), "normal", [1.0; 4], |image, queue| {
+                let image = image.expect("keyed by an image index");
+                upload_texture(to_rgba8(image), image.width, image.height, Format::R8G8B8A8Unorm, MipFilter::Normal, queue)
+            })
+        }
+        //straight up in tangent space
+        None => textures.get(None, "normal", [1.0; 4], |_, queue| solid_texture(&[128, 128, 255, 255], Format::R8G8B8A8Unorm, queue)),
+    }
+}
+
+//occlusion is in the red channel, strength blends it back towards no occlusion
+fn occlusion_texture(material: &gltf::Material, textures: &mut TextureCache) -> Result<Texture, RendererError> {
+    match material.occlusion_texture() {
+        Some(occlusion) => {
+            check_tex_coord(material, "occlusion", occlusion.tex_coord());
+            let strength = occlusion.strength();
+            textures.get(Some(occlusion.texture().source().index()), "occlusion", [strength, 1.0, 1.0, 1.0], |image, queue| {
+                let image = image.expect("keyed by an image index");
+                let pixels = to_rgba8(image)
+                    .chunks_exact(4)
+                    .map(|px| to_byte(1.0 + strength * (px[0] as f32 / 255.0 - 1.0)))
+                    .collect::<Vec<_>>();
+                upload_texture(pixels, image.width, image.height, Format::R8Unorm, MipFilter::Box, queue)
+            })
+        }
+        None => textures.get(None, "occlusion", [1.0; 4], |_, queue| solid_texture(&[255], Format::R8Unorm, queue)),
+    }
+}
+
+fn emissive_texture(material: &gltf::Material, textures: &mut TextureCache) -> Result<Texture, RendererError> {
+    let factor = material.emissive_factor();
+    let factor = [factor[0], factor[1], factor[2], 1.0];
+    match material.emissive_texture() {
+        Some(info) => {
+            check_tex_coord(material, "emissive", info.tex_coord());
+            textures.get(Some(info.texture().source().index()), "emissive", factor, |image, queue| {
+                let image = image.expect("keyed by an image index");
+                let mut pixels = to_rgba8(image);
+                apply_srgb_factor(&mut pixels, &factor);
+                upload_texture(pixels, image.width, image.height, Format::R8G8B8A8Srgb, MipFilter::Box, queue)
+            })
+        }
+        None => textures.get(None, "emissive", factor, |_, queue| {
+            let pixel = [to_byte(linear_to_srgb(factor[0])), to_byte(linear_to_srgb(factor[1])), to_byte(linear_to_srgb(factor[2])), 255];
+            solid_texture(&pixel, Format::R8G8B8A8Srgb, queue)
+        }),
+    }
+}
+
+//MASK materials cut out wherever base color alpha is under the cutoff, the shader cuts at a half so we threshold here
+//BLEND materials get drawn opaque since there's no blending
+fn opacity_texture(material: &gltf::Material, textures: &mut TextureCache) -> Result<Texture, RendererError> {
+    if material.alpha_mode() != gltf::material::AlphaMode::Mask {
+        return textures.get(None, "opacity", [1.0; 4], |_, queue| solid_texture(&[255], Format::R8Unorm, queue));
+    }
+
+    //0.5 is the default the spec gives when the file leaves it out
+    let cutoff = material.alpha_cutoff().unwrap_or(0.5);
+    let pbr = material.pbr_metallic_roughness();
+    let factor = pbr.base_color_factor()[3];
+    match pbr.base_color_texture() {
+        //the base color's UV set already got checked with the albedo
+        Some(info) => textures.get(Some(info.texture().source().index()), "opacity", [factor, cutoff, 1.0, 1.0], |image, queue| {
+            let image = image.expect("keyed by an image index");
+            let pixels = to_rgba8(image)
+                .chunks_exact(4)
+                .map(|px| if px[3] as f32 / 255.0 * factor >= cutoff { 255 } else { 0 })
+                .collect::<Vec<_>>();
+            upload_texture(pixels, image.width, image.height, Format::R8Unorm, MipFilter::Box, queue)
+        }),
+        None => {
+            let lit = if factor >= cutoff { 255 } else { 0 };
+            textures.get(None, "opacity", [lit as f32, 1.0, 1.0, 1.0], |_, queue| solid_texture(&[lit], Format::R8Unorm, queue))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_factor_scales_linear_light() {
+        let mut pixels = vec![255, 255, 255, 255, 0, 0, 0, 128];
+        apply_srgb_factor(&mut pixels, &[0.5, 0.5, 1.0, 0.5]);
+        //half of white in linear light is 188 once encoded, not 128
+        assert_eq!(pixels, vec![188, 188, 255, 128, 0, 0, 0, 64]);
+    }
+}
@@ -0,0 +1,381 @@
+use std::f32::consts::PI;
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::color_space::srgb_to_linear;
+use crate::error::RendererError;
+use crate::image_formats::decode_image;
+use crate::mipmaps::mip_count;
+use crate::render_helpers::TextureRole;
+use crate::texture_format::{expand_to_rgba, format_layout, read_sample, write_sample, SampleType};
+
+//Image based lighting, all worked out on the CPU when an environment gets loaded
+//an equirectangular HDR gets turned in to a cubemap, then convolved in to a diffuse irradiance map and a specular map
+//with blurrier reflections further down its mips (one roughness per mip), plus the BRDF lookup table the split sum needs
+//faces are in Vulkan's order (+X, -X, +Y, -Y, +Z, -Z) and +Y is up
+
+//the specular map's sharpest level, mirror reflections don't need the full environment's resolution
+const SPECULAR_SIZE: u32 = 256;
+//one roughness per level, 0 at the top and 1 at the bottom
+const SPECULAR_LEVELS: u32 = 6;
+//filtered importance sampling reads blurrier mips for the spread out samples, so not many are needed
+const SPECULAR_SAMPLES: u32 = 64;
+const IRRADIANCE_SIZE: u32 = 32;
+pub const BRDF_LUT_SIZE: u32 = 128;
+const BRDF_LUT_SAMPLES: u32 = 128;
+
+//A cubemap and its mips, every level is the six faces one after the other, rows top to bottom
+pub struct CubeMap {
+    pub size: u32,
+    pub levels: Vec<Vec<[f32; 3]>>,
+}
+
+//Everything the shader needs out of one environment
+pub struct EnvironmentMaps {
+    //the environment itself with a box filtered mip chain
+    pub radiance: CubeMap,
+    //already divided by pi, so the shader multiplies it by albedo and that's the diffuse light
+    pub irradiance: CubeMap,
+    pub specular: CubeMap,
+}
+
+//direction through a point on a face, s and t go from -1 to 1 left to right and top to bottom
+fn face_dir(face: usize, s: f32, t: f32) -> Vector3<f32> {
+    let d = match face {
+        0 => Vector3::new(1.0, -t, -s),
+        1 => Vector3::new(-1.0, -t, s),
+        2 => Vector3::new(s, 1.0, t),
+        3 => Vector3::new(s, -1.0, -t),
+        4 => Vector3::new(s, -t, 1.0),
+        _ => Vector3::new(-s, -t, -1.0),
+    };
+    d.normalize()
+}
+
+//the other way around, which face a direction lands on and where
+fn dir_face(d: Vector3<f32>) -> (usize, f32, f32) {
+    let (ax, ay, az) = (d.x.abs(), d.y.abs(), d.z.abs());
+    if ax >= ay && ax >= az {
+        if d.x > 0.0 { (0, -d.z / ax, -d.y / ax) } else { (1, d.z / ax, -d.y / ax) }
+    } else if ay >= az {
+        if d.y > 0.0 { (2, d.x / ay, d.z / ay) } else { (3, d.x / ay, -d.z / ay) }
+    } else if d.z > 0.0 {
+        (4, d.x / az, -d.y / az)
+    } else {
+        (5, -d.x / az, -d.y / az)
+    }
+}
+
+//fills in every texel of a size x size cube from the direction through its middle
+//each face gets its own thread, this is the slow part of loading an environment
+fn cube_from_fn<F: Fn(Vector3<f32>) -> [f32; 3] + Sync>(size: u32, f: F) -> Vec<[f32; 3]> {
+    let size = size as usize;
+    let mut texels = vec![[0.0f32; 3]; 6 * size * size];
+    std::thread::scope(|scope| {
+        for (face, out) in texels.chunks_mut(size * size).enumerate() {
+            let f = &f;
+            scope.spawn(move || {
+                for (i, texel) in out.iter_mut().enumerate() {
+                    let s = 2.0 * ((i % size) as f32 + 0.5) / size as f32 - 1.0;
+                    let t = 2.0 * ((i / size) as f32 + 0.5) / size as f32 - 1.0;
+                    *texel = f(face_dir(face, s, t));
+                }
+            });
+        }
+    });
+    texels
+}
+
+impl CubeMap {
+    pub fn level_size(&self, level: usize) -> u32 {
+        (self.size >> level).max(1)
+    }
+
+    //bilinear inside the face the direction lands on, the edges clamp instead of reaching over to the next face
+    pub fn sample(&self, dir: Vector3<f32>, level: usize) -> Vector3<f32> {
+        let level = level.min(self.levels.len() - 1);
+        let size = self.level_size(level) as usize;
+        let texels = &self.levels[level];
+        let (face, s, t) = dir_face(dir);
+        let x = ((s + 1.0) * 0.5 * size as f32 - 0.5).clamp(0.0, (size - 1) as f32);
+        let y = ((t + 1.0) * 0.5 * size as f32 - 0.5).clamp(0.0, (size - 1) as f32);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(size - 1), (y0 + 1).min(size - 1));
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+        let at = |x: usize, y: usize| Vector3::from(texels[face * size * size + y * size + x]);
+        let top = at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx;
+        let bottom = at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    //blends between the two nearest levels like the GPU's trilinear filtering
+    pub fn sample_lod(&self, dir: Vector3<f32>, lod: f32) -> Vector3<f32> {
+        let lod = lod.clamp(0.0, (self.levels.len() - 1) as f32);
+        let level = lod.floor() as usize;
+        let f = lod - level as f32;
+        if f <= 0.0 {
+            return self.sample(dir, level);
+        }
+        self.sample(dir, level) * (1.0 - f) + self.sample(dir, level + 1) * f
+    }
+
+    //every level down to 1x1, each face is box filtered on its own
+    fn build_mips(&mut self) {
+        for level in 1..mip_count(self.size, self.size) as usize {
+            let (src, size) = (&self.levels[level - 1], self.level_size(level) as usize);
+            let src_size = self.level_size(level - 1) as usize;
+            let mut texels = Vec::with_capacity(6 * size * size);
+            for face in 0..6 {
+                let src = &src[face * src_size * src_size..(face + 1) * src_size * src_size];
+                for y in 0..size {
+                    for x in 0..size {
+                        let mut sum = [0.0f32; 3];
+                        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter() {
+                            let px = src[(y * 2 + dy).min(src_size - 1) * src_size + (x * 2 + dx).min(src_size - 1)];
+                            for c in 0..3 {
+                                sum[c] += px[c] * 0.25;
+                            }
+                        }
+                        texels.push(sum);
+                    }
+                }
+            }
+            self.levels.push(texels);
+        }
+    }
+
+    //half float RGBA, one buffer per level with all six faces in it
+    pub fn to_rgba16f(&self) -> Vec<Vec<u8>> {
+        self.levels
+            .iter()
+            .map(|level| {
+                let mut out = Vec::with_capacity(level.len() * 8);
+                for px in level.iter() {
+                    for v in px.iter().chain(std::iter::once(&1.0)) {
+                        write_sample(*v, SampleType::Float16, &mut out);
+                    }
+                }
+                out
+            })
+            .collect()
+    }
+}
+
+//Reads an image as linear RGB floats, HDR/EXR are linear already and 8 bit images are taken as sRGB
+pub fn load_equirect(path: &str) -> Result<(Vec<[f32; 3]>, u32, u32), RendererError> {
+    let image = decode_image(path, TextureRole::Color)?;
+    let (channels, sample) = format_layout(image.format).ok_or(RendererError::TextureFormat(image.format))?;
+    let rgba = expand_to_rgba(&image.pixels, channels, sample);
+    let size = sample.bytes();
+    let pixels = rgba
+        .chunks_exact(size * 4)
+        .map(|px| {
+            let mut rgb = [0.0f32; 3];
+            for (c, v) in rgb.iter_mut().enumerate() {
+                *v = read_sample(&px[c * size..(c + 1) * size], sample);
+                if sample == SampleType::Srgb8 {
+                    *v = srgb_to_linear(*v);
+                }
+            }
+            rgb
+        })
+        .collect();
+    Ok((pixels, image.width, image.height))
+}
+
+//Projects a longitude/latitude image on to a cube, the middle of the image ends up facing +X
+pub fn equirect_to_cube(pixels: &[[f32; 3]], width: u32, height: u32, size: u32) -> CubeMap {
+    let (w, h) = (width as usize, height as usize);
+    let at = |x: usize, y: usize| Vector3::from(pixels[y.min(h - 1) * w + x % w]);
+    let texels = cube_from_fn(size, |d| {
+        //u wraps around, v clamps at the poles
+        let u = 0.5 + d.z.atan2(d.x) / (2.0 * PI);
+        let v = d.y.clamp(-1.0, 1.0).acos() / PI;
+        let x = u * w as f32 - 0.5;
+        let y = (v * h as f32 - 0.5).max(0.0);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = ((x0 as isize).rem_euclid(w as isize) as usize, y0 as usize);
+        let top = at(x0, y0) * (1.0 - fx) + at(x0 + 1, y0) * fx;
+        let bottom = at(x0, y0 + 1) * (1.0 - fx) + at(x0 + 1, y0 + 1) * fx;
+        (top * (1.0 - fy) + bottom * fy).into()
+    });
+    let mut cube = CubeMap { size, levels: vec![texels] };
+    cube.build_mips();
+    cube
+}
+
+//the first 9 spherical harmonics at a direction
+fn sh9(d: Vector3<f32>) -> [f32; 9] {
+    [
+        0.282095,
+        0.488603 * d.y,
+        0.488603 * d.z,
+        0.488603 * d.x,
+        1.092548 * d.x * d.y,
+        1.092548 * d.y * d.z,
+        0.315392 * (3.0 * d.z * d.z - 1.0),
+        1.092548 * d.x * d.z,
+        0.546274 * (d.x * d.x - d.y * d.y),
+    ]
+}
+
+//Diffuse lighting for every normal, the environment is squashed down to 9 spherical harmonics first (Ramamoorthi and Hanrahan)
+//which is exact enough for something as blurry as a cosine convolution and far quicker than integrating per texel
+pub fn irradiance_map(env: &CubeMap) -> CubeMap {
+    //a 32x32 or smaller level has plenty of detail for 9 coefficients
+    let level = (0..env.levels.len()).find(|l| env.level_size(*l) <= 32).unwrap_or(env.levels.len() - 1);
+    let size = env.level_size(level) as usize;
+    let mut coeffs = [Vector3::new(0.0f32, 0.0, 0.0); 9];
+    let mut total = 0.0;
+    for (i, px) in env.levels[level].iter().enumerate() {
+        let (face, rest) = (i / (size * size), i % (size * size));
+        let s = 2.0 * ((rest % size) as f32 + 0.5) / size as f32 - 1.0;
+        let t = 2.0 * ((rest / size) as f32 + 0.5) / size as f32 - 1.0;
+        //texels near the corners of a face cover less of the sphere
+        let weight = 4.0 / ((size * size) as f32 * (1.0 + s * s + t * t).powf(1.5));
+        total += weight;
+        for (c, y) in coeffs.iter_mut().zip(sh9(face_dir(face, s, t)).iter()) {
+            *c += Vector3::from(*px) * (*y * weight);
+        }
+    }
+    //the weights are an approximation, make them add up to the whole sphere
+    let norm = 4.0 * PI / total;
+
+    //the cosine lobe's convolution per band, and a divide by pi so the shader can skip it
+    let bands = [PI, 2.0 * PI / 3.0, 2.0 * PI / 3.0, 2.0 * PI / 3.0, PI / 4.0, PI / 4.0, PI / 4.0, PI / 4.0, PI / 4.0];
+    let texels = cube_from_fn(IRRADIANCE_SIZE, |d| {
+        let mut e = Vector3::new(0.0f32, 0.0, 0.0);
+        for ((c, y), a) in coeffs.iter().zip(sh9(d).iter()).zip(bands.iter()) {
+            e += c * (norm * y * a / PI);
+        }
+        [e.x.max(0.0), e.y.max(0.0), e.z.max(0.0)]
+    });
+    CubeMap { size: IRRADIANCE_SIZE, levels: vec![texels] }
+}
+
+//low discrepancy points on the unit square, spread out better than random ones
+fn hammersley(i: u32, count: u32) -> (f32, f32) {
+    (i as f32 / count as f32, i.reverse_bits() as f32 * 2.328_306_4e-10)
+}
+
+//a half vector around +Z, more likely where the GGX distribution for this roughness is higher
+fn importance_sample_ggx(xi: (f32, f32), roughness: f32) -> Vector3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.0;
+    let cos_theta = ((1.0 - xi.1) / (1.0 + (a * a - 1.0) * xi.1)).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    Vector3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta)
+}
+
+//a tangent and bitangent to go with n, any will do since GGX is the same all the way around
+fn basis(n: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let up = if n.z.abs() < 0.999 { Vector3::unit_z() } else { Vector3::unit_x() };
+    let tangent = up.cross(n).normalize();
+    (tangent, n.cross(tangent))
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a2 = roughness.powi(4);
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d).max(1e-7)
+}
+
+//The specular half of the split sum, each level is the environment convolved with GGX at a higher roughness
+//the view direction is taken to be the normal, that's the approximation that lets it be looked up by reflection vector alone
+pub fn prefilter_specular(env: &CubeMap) -> CubeMap {
+    let base = env.size.min(SPECULAR_SIZE);
+    let levels = SPECULAR_LEVELS.min(mip_count(base, base));
+    //solid angle of one texel of the environment's top level, to compare each sample's share of the sphere to
+    let texel_angle = 4.0 * PI / (6.0 * (env.size * env.size) as f32);
+
+    let mut out = CubeMap { size: base, levels: Vec::new() };
+    for level in 0..levels {
+        let size = (base >> level).max(1);
+        let roughness = if levels > 1 { level as f32 / (levels - 1) as f32 } else { 0.0 };
+
+        //with v = n every texel uses the same samples around its own normal, so they're worked out once a level
+        //the pdf is just D / 4 then, samples that are less likely stand for more of the sphere so they read a blurrier mip
+        let samples = (0..SPECULAR_SAMPLES)
+            .filter_map(|i| {
+                let h = importance_sample_ggx(hammersley(i, SPECULAR_SAMPLES), roughness);
+                let l = h * (2.0 * h.z) - Vector3::unit_z();
+                if l.z <= 0.0 {
+                    return None;
+                }
+                let pdf = distribution_ggx(h.z, roughness) / 4.0;
+                let sample_angle = 1.0 / (SPECULAR_SAMPLES as f32 * pdf + 1e-4);
+                Some((l, 0.5 * (sample_angle / texel_angle).log2() + 1.0))
+            })
+            .collect::<Vec<_>>();
+        let weight = samples.iter().map(|(l, _)| l.z).sum::<f32>().max(1e-4);
+
+        let texels = cube_from_fn(size, |n| {
+            //mirror reflections are just the environment
+            if level == 0 {
+                let lod = (env.size as f32 / size as f32).log2();
+                return env.sample_lod(n, lod).into();
+            }
+            let (t, b) = basis(n);
+            let mut sum = Vector3::new(0.0f32, 0.0, 0.0);
+            for (l, lod) in samples.iter() {
+                sum += env.sample_lod(t * l.x + b * l.y + n * l.z, *lod) * l.z;
+            }
+            (sum / weight).into()
+        });
+        out.levels.push(texels);
+    }
+    out
+}
+
+//Smith GGX visibility with the k image based lighting uses
+fn geometry_smith_ibl(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = roughness * roughness / 2.0;
+    let g = |x: f32| x / (x * (1.0 - k) + k);
+    g(n_dot_v) * g(n_dot_l)
+}
+
+//The other half of the split sum, doesn't depend on the environment so it's only made once
+//x is n.v and y is roughness, red scales F0 and green gets added to it, the normal is +Z
+pub fn brdf_lut() -> Vec<[f32; 2]> {
+    let size = BRDF_LUT_SIZE as usize;
+    (0..size * size)
+        .map(|i| {
+            let n_dot_v = ((i % size) as f32 + 0.5) / size as f32;
+            let roughness = ((i / size) as f32 + 0.5) / size as f32;
+            let v = Vector3::new((1.0 - n_dot_v * n_dot_v).sqrt(), 0.0, n_dot_v);
+            let (mut a, mut b) = (0.0, 0.0);
+            for s in 0..BRDF_LUT_SAMPLES {
+                let h = importance_sample_ggx(hammersley(s, BRDF_LUT_SAMPLES), roughness);
+                let l = h * (2.0 * v.dot(h)) - v;
+                let (n_dot_l, n_dot_h, v_dot_h) = (l.z.max(0.0), h.z.max(0.0), v.dot(h).max(0.0));
+                if n_dot_l > 0.0 {
+                    let g_vis = geometry_smith_ibl(n_dot_v, n_dot_l, roughness) * v_dot_h / (n_dot_h * n_dot_v);
+                    let fc = (1.0 - v_dot_h).powi(5);
+                    a += (1.0 - fc) * g_vis;
+                    b += fc * g_vis;
+                }
+            }
+            [a / BRDF_LUT_SAMPLES as f32, b / BRDF_LUT_SAMPLES as f32]
+        })
+        .collect()
+}
+
+impl EnvironmentMaps {
+    //Loads an equirectangular image and works out everything from it, the cube is about a quarter of the image's width
+    pub fn load(path: &str) -> Result<EnvironmentMaps, RendererError> {
+        let (pixels, width, height) = load_equirect(path)?;
+        let size = (width / 4).next_power_of_two().clamp(16, 512);
+        let radiance = equirect_to_cube(&pixels, width, height, size);
+        let irradiance = irradiance_map(&radiance);
+        let specular = prefilter_specular(&radiance);
+        println!("Loaded environment {:?}: {}x{} cube, {} specular levels", path, size, size, specular.levels.len());
+        Ok(EnvironmentMaps { radiance, irradiance, specular })
+    }
+
+    //the same light from every direction, what the renderer starts with
+    pub fn uniform(color: [f32; 3]) -> EnvironmentMaps {
+        let cube = || CubeMap { size: 1, levels: vec![vec![color; 6]] };
+        EnvironmentMaps { radiance: cube(), irradiance: cube(), specular: cube() }
+    }
+}
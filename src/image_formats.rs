@@ -0,0 +1,139 @@
+use std::{fs, io::{BufReader, Cursor}, path::Path};
+
+use exr::prelude::{read, AnyChannels, FlatSamples, Image, Layer, ReadChannels, ReadLayers};
+use image::{codecs::hdr::HdrDecoder, DynamicImage, GenericImageView, ImageFormat};
+
+use crate::error::RendererError;
+use crate::render_helpers::{decode_png, DecodedImage, TextureRole};
+use crate::texture_format::{expand_to_rgba, format_for, write_sample, SampleType};
+
+//Picks a decoder by file extension
+//PNG, JPEG and TGA are for ordinary texture maps, HDR and EXR come in as floats for environment maps and displacement
+
+//file types decode_image knows how to read
+pub const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "tga", "hdr", "exr"];
+
+pub fn decode_image(path: &str, role: TextureRole) -> Result<DecodedImage, RendererError> {
+    let ext = Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    match ext.as_str() {
+        "png" => decode_png(path, role),
+        "jpg" | "jpeg" => decode_ldr(path, ImageFormat::Jpeg, role),
+        "tga" => decode_ldr(path, ImageFormat::Tga, role),
+        "hdr" => decode_hdr(path, role),
+        "exr" => decode_exr(path, role),
+        _ => Err(RendererError::decode(path, format!("don't know how to read .{} images", ext))),
+    }
+}
+
+//Works out the format for freshly decoded pixels
+//grey colors get spread across RGB or the shader sees them as shades of red
+pub fn finish_decode(pixels: Vec<u8>, width: u32, height: u32, channels: usize, sample: SampleType, role: TextureRole) -> DecodedImage {
+    let (pixels, channels) = if role == TextureRole::Color && channels < 3 {
+        (expand_to_rgba(&pixels, channels, sample), 4)
+    } else {
+        (pixels, channels)
+    };
+    DecodedImage {
+        pixels,
+        width,
+        height,
+        format: format_for(channels, sample).expect("no format for decoded image layout"),
+        channels,
+        bytes_per_channel: sample.bytes(),
+    }
+}
+
+//JPEG and TGA, these can't say what color space they're in so color maps are taken as sRGB
+fn decode_ldr(path: &str, format: ImageFormat, role: TextureRole) -> Result<DecodedImage, RendererError> {
+    let bytes = fs::read(path).map_err(|e| RendererError::io(path, e))?;
+    let image = image::load_from_memory_with_format(&bytes, format).map_err(|e| RendererError::decode(path, e))?;
+    let (width, height) = (image.width(), image.height());
+
+    //both of these decode to 8 bits a channel, anything unusual (BGR TGAs) just gets turned in to RGBA
+    let (pixels, channels) = match image {
+        DynamicImage::ImageLuma8(i) => (i.into_raw(), 1),
+        DynamicImage::ImageLumaA8(i) => (i.into_raw(), 2),
+        DynamicImage::ImageRgb8(i) => (i.into_raw(), 3),
+        other => (other.to_rgba8().into_raw(), 4),
+    };
+    let sample = if role == TextureRole::Color { SampleType::Srgb8 } else { SampleType::Unorm8 };
+
+    println!("Decoded {:?} as {} channel {:?}", path, channels, sample);
+    Ok(finish_decode(pixels, width, height, channels, sample, role))
+}
+
+//float pixels to the GPU layout we want for the role
+//color (environment maps) gets half floats since 32 bit RGBA gets huge fast, data keeps full precision in the one channel we sample
+fn float_image(rgba: Vec<[f32; 4]>, width: u32, height: u32, role: TextureRole) -> DecodedImage {
+    let (sample, channels) = match role {
+        TextureRole::Color => (SampleType::Float16, 4),
+        TextureRole::Data => (SampleType::Float32, 1),
+        TextureRole::Normal => (SampleType::Float32, 4),
+    };
+    let mut pixels = Vec::with_capacity(rgba.len() * channels * sample.bytes());
+    for px in rgba.iter() {
+        for v in px[..channels].iter() {
+            write_sample(*v, sample, &mut pixels);
+        }
+    }
+    finish_decode(pixels, width, height, channels, sample, role)
+}
+
+//Radiance RGBE, always linear
+fn decode_hdr(path: &str, role: TextureRole) -> Result<DecodedImage, RendererError> {
+    let file = fs::File::open(path).map_err(|e| RendererError::io(path, e))?;
+    let decoder = HdrDecoder::new(BufReader::new(file)).map_err(|e| RendererError::decode(path, e))?;
+    let meta = decoder.metadata();
+    let pixels = decoder.read_image_hdr().map_err(|e| RendererError::decode(path, e))?;
+
+    println!("Decoded {:?} as {}x{} HDR", path, meta.width, meta.height);
+    let rgba = pixels.into_iter().map(|p| [p[0], p[1], p[2], 1.0]).collect();
+    Ok(float_image(rgba, meta.width, meta.height, role))
+}
+
+//OpenEXR, always linear, only the first layer at full resolution is read
+//channels are found by name so luminance only files (just Y) work too
+fn decode_exr(path: &str, role: TextureRole) -> Result<DecodedImage, RendererError> {
+    let bytes = fs::read(path).map_err(|e| RendererError::io(path, e))?;
+    let image: Image<Layer<AnyChannels<FlatSamples>>> = read()
+        .no_deep_data()
+        .largest_resolution_level()
+        .all_channels()
+        .first_valid_layer()
+        .all_attributes()
+        .from_buffered(Cursor::new(bytes))
+        .map_err(|e| RendererError::decode(path, e))?;
+
+    let layer = image.layer_data;
+    let (width, height) = (layer.size.0, layer.size.1);
+    let channels = &layer.channel_data.list;
+    //subsampled channels (like chroma in a YC file) have fewer samples than pixels, we'd need to upsample them
+    if let Some(short) = channels.iter().find(|c| c.sample_data.len() != width * height) {
+        return Err(RendererError::decode(path, format!("EXR channel {} is subsampled, only full resolution channels are supported", short.name)));
+    }
+    let find = |names: &[&str]| {
+        channels
+            .iter()
+            .find(|c| names.iter().any(|n| c.name.eq_case_insensitive(n)))
+            .map(|c| c.sample_data.values_as_f32().collect::<Vec<f32>>())
+    };
+
+    //whatever's there, grey fills in missing colors and the first channel that isn't alpha stands in if nothing has a name we know
+    let grey = find(&["Y", "L"]).or_else(|| {
+        channels
+            .iter()
+            .find(|c| !c.name.eq_case_insensitive("A"))
+            .map(|c| c.sample_data.values_as_f32().collect())
+    });
+    let grey = grey.ok_or_else(|| RendererError::decode(path, "EXR has no color channels"))?;
+    let r = find(&["R"]).unwrap_or_else(|| grey.clone());
+    let g = find(&["G"]).unwrap_or_else(|| grey.clone());
+    let b = find(&["B"]).unwrap_or_else(|| grey.clone());
+    let a = find(&["A"]);
+
+    println!("Decoded {:?} as {}x{} EXR with {} channels", path, width, height, channels.len());
+    let rgba = (0..width * height)
+        .map(|i| [r[i], g[i], b[i], a.as_ref().map_or(1.0, |a| a[i])])
+        .collect();
+    Ok(float_image(rgba, width as u32, height as u32, role))
+}
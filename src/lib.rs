@@ -0,0 +1,29 @@
+//PBR renderer built on Vulkano
+//set up a window with render_helpers::setup_vulkano, hand the result to Renderer::new,
+//add meshes/materials/lights to it and call render_frame from your event loop. main.rs is a small example of all that.
+
+pub mod bc_encode;
+pub mod color_space;
+pub mod error;
+pub mod gltf_import;
+pub mod ibl;
+pub mod image_formats;
+pub mod material;
+pub mod mesh;
+pub mod mesh_gen;
+pub mod mipmaps;
+pub mod optimize;
+pub mod primitives;
+pub mod process;
+pub mod render_helpers;
+pub mod renderer;
+pub mod shadows;
+pub mod tangents;
+pub mod texture_containers;
+pub mod texture_format;
+pub mod vertex;
+
+pub use error::RendererError;
+pub use render_helpers::{list_devices, setup_vulkano, TextureRole, Vulkan, VulkanOptions};
+pub use renderer::{Background, Camera, Channel, Displacement, Light, LightId, LightKind, Material, MaterialId, MeshId, NormalConvention, Parallax, Renderer, TextureChannel, TextureId, Tonemapper, three_point_rig};
+pub use shadows::ShadowSettings;
@@ -1,119 +1,214 @@
-mod render_helpers;
-use std::process::exit;
+use std::{process::exit, time::Instant};
 
-use render_helpers::setup_vulkano;
+use cgmath::{Matrix4, Rad};
+use winit::{event::{Event, WindowEvent}, event_loop::{ControlFlow, EventLoop}};
 
-use crate::render_helpers::{ObjectData, prep_texture, vertex::Vertex};
+use project_g::{Light, MeshId, Renderer, RendererError, list_devices, setup_vulkano, three_point_rig};
+use project_g::gltf_import::load_gltf;
+use project_g::material::{find_material, load_material};
+use project_g::mesh::{Mesh, load_obj, merge_meshes};
+use project_g::optimize::{OptimizeOptions, optimize_mesh};
+use project_g::primitives;
+use project_g::process::process_material;
 
-mod event_loop;
-use event_loop::{run_event_loop, Vulkan};
-
-use tobj::LoadOptions;
+mod cli;
+use cli::{Args, Command, LightRig, NormalOptions};
 
+//EXIT CODES, these are listed in --help too
+const EXIT_USAGE: i32 = 2;
+const EXIT_LOAD: i32 = 3;
+const EXIT_GPU: i32 = 4;
+const EXIT_RENDER: i32 = 5;
 
 fn main() {
-    println!("Hello, world!");
-
-    //load the OBJ file
-    //normally OBJs come with seperate indexes for verts, normals, and UVs
-    //this gives us the option of compressing and reordering them in to a single index
-    //which is what opengl and vulkan wants
-    let load_opts = LoadOptions {
-        single_index: true,
-        triangulate: true,
-        ..Default::default()
+    let args = match cli::parse(std::env::args().skip(1)) {
+        Ok(Command::Run(args)) => args,
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Ok(Command::ListDevices) => {
+            match list_devices() {
+                Ok(devices) => {
+                    for (i, name) in devices.iter().enumerate() {
+                        println!("{}: {}", i, name);
+                    }
+                }
+                Err(e) => {
+                    println!("{}", e);
+                    exit(EXIT_GPU);
+                }
+            }
+            return;
+        }
+        Ok(Command::Process { material, out, normal }) => {
+            if let Err(e) = process(&material, out, &normal) {
+                println!("{}", e);
+                exit(exit_code(&e));
+            }
+            return;
+        }
+        Err(msg) => {
+            println!("{}\n\n{}", msg, cli::USAGE);
+            exit(EXIT_USAGE);
+        }
     };
 
-    let obj= tobj::load_obj("sphere.obj", &load_opts).unwrap();
-    let s = &obj.0[0]; //we only support one model per OBJ file and that is the first object it sees
-    
-    //all of the code from here forwards is just reorganizing the data in to a Vulkan freindly format
-    //I should probably wrap this in a function but whatever
-    //read the Obj spec if you actually care about what's happening here, it's really simple, just a lot of loops
-    let upper_idx_pos = s.mesh.positions.len();
-    let upper_idx_norm = s.mesh.normals.len();
-    if upper_idx_pos != upper_idx_norm {
-        println!("number of verts doesn't match number of norms???");
-        exit(1);
-    }
+    //anything that goes wrong before the window is up ends up here instead of as a panic
+    let (event_loop, renderer, ids) = match setup(&args) {
+        Ok(r) => r,
+        Err(e) => {
+            println!("{}", e);
+            exit(exit_code(&e));
+        }
+    };
 
-    struct Triple {
-        x: f32,
-        y: f32,
-        z: f32
-    }
+    run(event_loop, renderer, ids);
+}
 
-    let mut coords = Vec::<Triple>::new();
-    let mut norms = Vec::<Triple>::new();
-    let mut uvs = Vec::<Triple>::new();
+//bad files and bad GPUs get different codes so scripts can tell them apart
+fn exit_code(e: &RendererError) -> i32 {
+    match e {
+        RendererError::Io { .. } | RendererError::Decode { .. } | RendererError::Mesh(_) | RendererError::Gltf(_) => EXIT_LOAD,
+        RendererError::Acquire(_) | RendererError::Flush(_) => EXIT_RENDER,
+        _ => EXIT_GPU,
+    }
+}
 
-    let mut i = 0;
-    while i < upper_idx_pos {
-        let xp = s.mesh.positions[i];
-        let yp = s.mesh.positions[i + 1];
-        let zp = s.mesh.positions[i + 2];
-        coords.push(Triple { x: xp, y: yp, z: zp});
+//offline texture processing, no window or GPU needed, see process.rs
+fn process(material: &str, out: Option<String>, normal: &NormalOptions) -> Result<(), RendererError> {
+    let mut set = find_material(material)?;
+    normal.apply(&mut set);
+    let out = out.unwrap_or_else(|| format!("./processed/{}", set.name));
+    process_material(&set, out)?;
+    Ok(())
+}
 
-        let xn = s.mesh.normals[i];
-        let yn = s.mesh.normals[i + 1];
-        let zn = s.mesh.normals[i + 2];
-        norms.push(Triple { x: xn, y: yn, z: zn});
+fn setup(args: &Args) -> Result<(EventLoop<()>, Renderer, Vec<MeshId>), RendererError> {
+    //the model to show, either an OBJ, a glTF file, or the name of a built in shape
+    let model_path = &args.model;
+    let is_gltf = model_path.ends_with(".gltf") || model_path.ends_with(".glb");
+
+    //load every object in the OBJ file, see mesh.rs for how the data gets reorganized in to a Vulkan freindly format
+    //glTF files get loaded after vulkan is up since their textures go straight to the GPU
+    let meshes = if is_gltf {
+        Vec::new()
+    } else if let Some(shape) = primitives::by_name(model_path) {
+        vec![shape]
+    } else {
+        load_obj(model_path)?
+    };
 
-        i += 3;
+    for m in meshes.iter() {
+        println!("Loaded mesh {:?}: {} verts, {} tris", m.name, m.vertices.len(), m.indices.len() / 3);
     }
 
-    let mut j = 0;
-    while j < s.mesh.texcoords.len() {
-        let u = s.mesh.texcoords[j];
-        let v = s.mesh.texcoords[j + 1];
-        uvs.push(Triple {x: u, y: v, z: 0.0});
-        j += 2;
+    //FIRST BIG OPERATION, THIS SETS UP THE VULKAN CONTEXT
+    //SEE render_helpers.rs TO SEE WHATS GOING ON IN HERE
+    let event_loop = EventLoop::new();
+    let vk = setup_vulkano(&event_loop, &args.vulkan)?;
+    let mut renderer = Renderer::new(vk)?;
+    renderer.set_tonemapper(args.tonemapper);
+    renderer.set_camera(args.camera);
+    //not every GPU can tessellate, parallax still works everywhere
+    if let Err(e) = renderer.set_displacement(args.displacement) {
+        println!("WARNING: {}, using parallax instead", e);
     }
+    //the HDR gets convolved on the CPU, see ibl.rs, big ones take a moment
+    if let Some(path) = &args.environment {
+        renderer.load_environment(path)?;
+    }
+    renderer.set_environment_intensity(args.environment_intensity);
+    renderer.set_environment_rotation(args.environment_rotation.into());
+    renderer.set_background(args.background);
+    renderer.set_shadows(args.shadows)?;
+
+    let ids = if is_gltf {
+        //glTF brings its own materials, see gltf_import.rs
+        load_gltf(model_path, renderer.queue())?
+            .into_iter()
+            .map(|object| renderer.add_object(object))
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        vec![obj_with_textures(&meshes, &mut renderer, args)?]
+    };
 
-    let mut verts = Vec::<Vertex>::new();
-    for (pos, (norm, uv)) in coords.iter().zip(norms.iter().zip(uvs.iter())) {
-        let vert = Vertex {
-            position: (pos.x, pos.y, pos.z),
-            normal: (norm.x, norm.y, norm.z),
-            uv: (uv.x, uv.y)
-        };
-        verts.push(vert);
+    let lights = match args.lights {
+        LightRig::ThreePoint => three_point_rig(&args.camera),
+        LightRig::Single => vec![Light::default()],
+        LightRig::None => Vec::new(),
+    };
+    for light in lights {
+        renderer.add_light(light);
     }
 
-    let idxs = s.mesh.indices.to_vec();
+    println!("Drawing {} objects", ids.len());
+    Ok((event_loop, renderer, ids))
+}
 
-    //FIRST BIG OPERATION, THIS SETS UP THE VULKAN CONTEXT
-    //SEE render_helpers.rs TO SEE WHATS GOING ON IN HERE
-    let vk: Vulkan = setup_vulkano();
-    
-    //BASE NAME FOR THE TEXTURE WE ARE LOADING
-    let base = "MetalPlates006_1K";
-
-    //load each PBR texture, receiving a handle to where the texture is on the GPU
-    //and a future that resolves once the texture is on the GPU
-    let alb_path = format!("./assets/{}_Color.png", base);
-    let alb = prep_texture(&alb_path, vk.queue.clone());
-
-    let rough_path = format!("./assets/{}_Roughness.png", base);
-    let rough = prep_texture(&rough_path, vk.queue.clone());
-
-    let metalness_path = format!("./assets/{}_Metalness.png", base);
-    let metalness = prep_texture(&metalness_path, vk.queue.clone());
-
-    let normal_path = format!("./assets/{}_Normal.png", base);
-    let normal = prep_texture(&normal_path, vk.queue.clone());
-
-    //pack the OBJ data and textures in to a single object that we can pass to the event loop
-    let elements = ObjectData {
-        vert_data: verts,
-        index_data: idxs,
-        albedo: alb,
-        roughness: rough,
-        metallness: metalness,
-        normalmap: normal
-    };
+//OBJs don't come with PBR textures so we pair them with the ones in ./assets
+fn obj_with_textures(meshes: &[Mesh], renderer: &mut Renderer, args: &Args) -> Result<MeshId, RendererError> {
+    //all of the OBJ's objects share one material so everything goes in to a single buffer
+    let mut mesh = merge_meshes(meshes);
+
+    //reorder the mesh so the GPU can reuse as much vertex shader work as possible, see optimize.rs
+    if args.optimize {
+        let stats = optimize_mesh(&mut mesh.vertices, &mut mesh.indices, &OptimizeOptions::default());
+        println!(
+            "Optimized {:?}: {} -> {} verts, ACMR {:.3} -> {:.3}, ATVR {:.3} -> {:.3}",
+            mesh.name, stats.vertices_before, stats.vertices_after,
+            stats.acmr_before, stats.acmr_after, stats.atvr_before, stats.atvr_after
+        );
+    }
 
-    //start up the event loop
-    run_event_loop(vk, elements);
+    //find whichever maps the material folder has, see material.rs
+    //the renderer holds off drawing until they have all made it to the GPU
+    let mut set = find_material(&args.material)?;
+    args.normal.apply(&mut set);
+    set.parallax = args.parallax;
+    let material = load_material(renderer, &set)?;
+
+    renderer.add_mesh(&mesh, material)
+}
 
+//MAIN EVENT LOOP
+//spins everything around the Y axis and redraws as fast as the window will take frames
+fn run(event_loop: EventLoop<()>, mut renderer: Renderer, ids: Vec<MeshId>) -> ! {
+    //this is just getting the current clock value
+    let rotation_start = Instant::now();
+
+    event_loop.run(move |event, _, control_flow| {
+        //HANDLE WINDOW EVENTS
+        match event {
+            Event::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                ..
+            } => {
+                *control_flow = ControlFlow::Exit;
+            },
+            Event::WindowEvent {
+                event: WindowEvent::Resized(_),
+                ..
+            } => {
+                //when we resize the window we need to remake the swapchain
+                renderer.resize();
+            }
+            //THIS IS THE NORMAL ONE THAT RUNS EVERY FRAME
+            Event::RedrawEventsCleared => {
+                let rotation = Matrix4::from_angle_y(Rad(rotation_start.elapsed().as_secs_f32()));
+                for id in ids.iter() {
+                    renderer.set_mesh_transform(*id, rotation);
+                }
+
+                //winit never hands control back once it's running so we have to exit from in here to get the right code out
+                if let Err(e) = renderer.render_frame() {
+                    println!("{}", e);
+                    exit(EXIT_RENDER);
+                }
+            }
+            //this gets called if you hover your mouse over the window so ignore it
+            //if you wanted to rotate the camera here is where you would do it
+            _ => ()
+        }
+    })
 }
@@ -0,0 +1,431 @@
+use std::{collections::BTreeMap, io, path::{Path, PathBuf}};
+
+use vulkano::format::Format;
+
+use crate::error::RendererError;
+use crate::mipmaps::MipFilter;
+use crate::image_formats::{decode_image, IMAGE_EXTENSIONS};
+use crate::process::{has_manifest, read_manifest};
+use crate::texture_containers::{is_container, CONTAINER_EXTENSIONS};
+use crate::render_helpers::{solid_texture, upload_texture, TextureRole};
+use crate::renderer::{Channel, Material, MaterialId, NormalConvention, Parallax, Renderer, TextureChannel, TextureId};
+
+//Finds the texture maps for a material in a folder of loose images, the way ambientCG and Poly Haven ship them
+//maps are recognized by the last word of the file name, e.g. Bricks076C_1K_Color.png or brick_wall_001_diff_1k.png
+
+//Everything a material folder turned up, anything missing gets a constant when the material is loaded
+#[derive(Clone, Debug)]
+pub struct MaterialSet {
+    pub name: String,
+    pub albedo: Option<PathBuf>,
+    pub roughness: Option<PathBuf>,
+    //set when the roughness map is really a gloss map, which is roughness upside down
+    pub roughness_is_gloss: bool,
+    pub metalness: Option<PathBuf>,
+    //ambient occlusion, roughness and metalness packed in to R, G and B, fills in whichever of those three don't have their own map
+    pub orm: Option<PathBuf>,
+    pub normal: Option<PathBuf>,
+    //worked out from the file name (NormalGL/NormalDX), plain "Normal" is taken as OpenGL
+    pub normal_convention: NormalConvention,
+    //how strong the bumps are, 1 is the map as is
+    pub normal_strength: f32,
+    pub ao: Option<PathBuf>,
+    //drives parallax occlusion mapping
+    pub displacement: Option<PathBuf>,
+    pub parallax: Parallax,
+    pub emission: Option<PathBuf>,
+    pub opacity: Option<PathBuf>,
+}
+
+impl Default for MaterialSet {
+    fn default() -> Self {
+        MaterialSet {
+            name: String::new(),
+            albedo: None,
+            roughness: None,
+            roughness_is_gloss: false,
+            metalness: None,
+            orm: None,
+            normal: None,
+            normal_convention: NormalConvention::OpenGl,
+            normal_strength: 1.0,
+            ao: None,
+            displacement: None,
+            parallax: Parallax::default(),
+            emission: None,
+            opacity: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum MapKind {
+    Albedo,
+    Roughness,
+    Gloss,
+    Metalness,
+    Orm,
+    NormalGl,
+    NormalDx,
+    Ao,
+    Displacement,
+    Emission,
+    Opacity,
+}
+
+//suffix, what it is, and a rank for when a folder has more than one candidate for the same slot (lower wins)
+//the RGB copies ambientCG ships are the same data as the greyscale maps so they lose to them
+const SUFFIXES: &[(&str, MapKind, u32)] = &[
+    ("color", MapKind::Albedo, 0),
+    ("basecolor", MapKind::Albedo, 0),
+    ("base_color", MapKind::Albedo, 0),
+    ("albedo", MapKind::Albedo, 0),
+    ("diffuse", MapKind::Albedo, 1),
+    ("diff", MapKind::Albedo, 1),
+    ("col", MapKind::Albedo, 1),
+    ("roughness", MapKind::Roughness, 0),
+    ("rough", MapKind::Roughness, 0),
+    ("roughnessrgb", MapKind::Roughness, 1),
+    ("gloss", MapKind::Gloss, 2),
+    ("glossiness", MapKind::Gloss, 2),
+    ("metalness", MapKind::Metalness, 0),
+    ("metallic", MapKind::Metalness, 0),
+    ("metal", MapKind::Metalness, 0),
+    ("metalnessrgb", MapKind::Metalness, 1),
+    ("orm", MapKind::Orm, 0),
+    ("arm", MapKind::Orm, 0),
+    ("normalgl", MapKind::NormalGl, 0),
+    ("nor_gl", MapKind::NormalGl, 0),
+    ("normal", MapKind::NormalGl, 1),
+    ("nrm", MapKind::NormalGl, 1),
+    ("nor", MapKind::NormalGl, 1),
+    ("normaldx", MapKind::NormalDx, 2),
+    ("nor_dx", MapKind::NormalDx, 2),
+    ("ambientocclusion", MapKind::Ao, 0),
+    ("ao", MapKind::Ao, 0),
+    ("occlusion", MapKind::Ao, 0),
+    ("displacement", MapKind::Displacement, 0),
+    ("disp", MapKind::Displacement, 0),
+    ("height", MapKind::Displacement, 0),
+    ("emission", MapKind::Emission, 0),
+    ("emissive", MapKind::Emission, 0),
+    ("emit", MapKind::Emission, 0),
+    ("opacity", MapKind::Opacity, 0),
+    ("alpha", MapKind::Opacity, 0),
+];
+
+//1k, 2K, 4k... Poly Haven puts the resolution at the end of the name
+fn is_resolution(token: &str) -> bool {
+    token.len() > 1 && token.ends_with('k') && token[..token.len() - 1].chars().all(|c| c.is_ascii_digit())
+}
+
+//splits a file name in to the material it belongs to and what kind of map it is
+fn classify(path: &Path) -> Option<(String, MapKind, u32)> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    let container = CONTAINER_EXTENSIONS.contains(&ext.as_str());
+    if !container && !IMAGE_EXTENSIONS.contains(&ext.as_str()) {
+        return None;
+    }
+    let stem = path.file_stem()?.to_str()?;
+    let mut tokens = stem.split(['_', '-', ' ', '.']).filter(|t| !t.is_empty()).collect::<Vec<_>>();
+    if tokens.last().is_some_and(|t| is_resolution(&t.to_lowercase())) {
+        tokens.pop();
+    }
+
+    //two word suffixes like nor_gl have to be checked before the one word ones
+    for take in [2, 1] {
+        if tokens.len() <= take {
+            continue;
+        }
+        let suffix = tokens[tokens.len() - take..].join("_").to_lowercase();
+        if let Some((_, kind, rank)) = SUFFIXES.iter().find(|(s, _, _)| *s == suffix) {
+            let prefix = tokens[..tokens.len() - take].join("_");
+            //a KTX2/DDS copy of a map is the one meant to be loaded, it's already compressed and mipped
+            let rank = rank * 2 + if container { 0 } else { 1 };
+            return Some((prefix, *kind, rank));
+        }
+    }
+    None
+}
+
+//Scans a folder for texture maps, if prefix is given only files whose name starts with it are considered
+//when a folder holds several materials the first one alphabetically is used
+pub fn discover_material<P: AsRef<Path>>(dir: P, prefix: Option<&str>) -> Result<MaterialSet, RendererError> {
+    let dir = dir.as_ref();
+
+    //material name -> map kind -> (rank, path)
+    let mut found = BTreeMap::<String, BTreeMap<MapKind, (u32, PathBuf)>>::new();
+    for entry in std::fs::read_dir(dir).map_err(|e| RendererError::io(dir, e))? {
+        let path = entry.map_err(|e| RendererError::io(dir, e))?.path();
+        if !path.is_file() {
+            continue;
+        }
+        if let Some(prefix) = prefix {
+            let matches = path.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.to_lowercase().starts_with(&prefix.to_lowercase()));
+            if !matches {
+                continue;
+            }
+        }
+        if let Some((name, kind, rank)) = classify(&path) {
+            let maps = found.entry(name).or_default();
+            match maps.get(&kind) {
+                Some((best, _)) if *best <= rank => {}
+                _ => {
+                    maps.insert(kind, (rank, path));
+                }
+            }
+        }
+    }
+
+    let mut materials = found.into_iter();
+    let (name, maps) = materials.next().ok_or_else(|| {
+        RendererError::io(dir, io::Error::new(io::ErrorKind::NotFound, "no texture maps found"))
+    })?;
+    for (other, _) in materials {
+        println!("WARNING: {:?} also has a material called {:?}, only {:?} is loaded", dir, other, name);
+    }
+
+    let get = |kind: MapKind| maps.get(&kind).map(|(_, path)| path.clone());
+    let mut set = MaterialSet {
+        name,
+        albedo: get(MapKind::Albedo),
+        metalness: get(MapKind::Metalness),
+        orm: get(MapKind::Orm),
+        ao: get(MapKind::Ao),
+        displacement: get(MapKind::Displacement),
+        emission: get(MapKind::Emission),
+        opacity: get(MapKind::Opacity),
+        ..Default::default()
+    };
+
+    //prefer real roughness over gloss, and GL normals over DX ones since that's what the shader expects
+    match (get(MapKind::Roughness), get(MapKind::Gloss)) {
+        (Some(path), _) => set.roughness = Some(path),
+        (None, Some(path)) => {
+            set.roughness = Some(path);
+            set.roughness_is_gloss = true;
+        }
+        (None, None) => {}
+    }
+    match (get(MapKind::NormalGl), get(MapKind::NormalDx)) {
+        (Some(path), _) => set.normal = Some(path),
+        (None, Some(path)) => {
+            set.normal = Some(path);
+            set.normal_convention = NormalConvention::DirectX;
+        }
+        (None, None) => {}
+    }
+
+    Ok(set)
+}
+
+//Finds a material from a folder, or from the shared start of its file names (a bare name gets looked for in ./assets)
+//folders made by process_material are read from their manifest instead
+pub fn find_material(material: &str) -> Result<MaterialSet, RendererError> {
+    let path = Path::new(material);
+    if has_manifest(path) {
+        return read_manifest(path);
+    }
+    if path.is_dir() {
+        return discover_material(path, None);
+    }
+
+    let prefix = path.file_name().and_then(|n| n.to_str()).unwrap_or(material);
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => discover_material(parent, Some(prefix)),
+        _ => discover_material("./assets", Some(prefix)),
+    }
+}
+
+//Loads everything in the set on to the GPU and makes a material out of it
+//missing maps become constants: mid grey albedo, fairly rough, not metal, flat normals, no occlusion, no emission, fully opaque
+pub fn load_material(renderer: &mut Renderer, set: &MaterialSet) -> Result<MaterialId, RendererError> {
+    let mut missing = Vec::new();
+    let mut map = |renderer: &mut Renderer, path: &Option<PathBuf>, what: &str, default: &[u8], format: Format, role: TextureRole| {
+        match path {
+            Some(path) => renderer.load_texture(path, role),
+            None => {
+                missing.push(what.to_string());
+                let texture = solid_texture(default, format, renderer.queue())?;
+                Ok(renderer.add_texture(texture))
+            }
+        }
+    };
+
+    let albedo = map(renderer, &set.albedo, "albedo", &[188, 188, 188, 255], Format::R8G8B8A8Srgb, TextureRole::Color)?;
+    let normal = map(renderer, &set.normal, "normal", &[128, 128, 255, 255], Format::R8G8B8A8Unorm, TextureRole::Normal)?;
+    let emission = map(renderer, &set.emission, "emission", &[0, 0, 0, 255], Format::R8G8B8A8Srgb, TextureRole::Color)?;
+
+    //the single value maps fall back to a packed ORM map, then to one shared 1x1 texture holding all four defaults
+    //(ao, roughness, metalness, opacity), so a material with nothing but an ORM map only samples two textures for them
+    let orm = match &set.orm {
+        Some(path) => Some(renderer.load_texture(path, TextureRole::Data)?),
+        None => None,
+    };
+    let mut defaults = None;
+    let mut single = |renderer: &mut Renderer, path: &Option<PathBuf>, what: &str, channel: Channel| -> Result<TextureChannel, RendererError> {
+        if let Some(path) = path {
+            return Ok(renderer.load_texture(path, TextureRole::Data)?.into());
+        }
+        //ORM has no opacity, whatever is in its alpha isn't that
+        if let Some(orm) = orm.filter(|_| channel != Channel::A) {
+            return Ok(TextureChannel::new(orm, channel));
+        }
+        missing.push(what.to_string());
+        let texture = match defaults {
+            Some(texture) => texture,
+            None => {
+                let texture = renderer.add_texture(solid_texture(&[255, 128, 0, 255], Format::R8G8B8A8Unorm, renderer.queue())?);
+                defaults = Some(texture);
+                texture
+            }
+        };
+        Ok(TextureChannel::new(texture, channel))
+    };
+
+    let ao = single(renderer, &set.ao, "ambient occlusion", Channel::R)?;
+    let roughness = match (&set.roughness, set.roughness_is_gloss) {
+        (Some(path), true) => gloss_to_roughness(renderer, path)?.into(),
+        (path, _) => single(renderer, path, "roughness", Channel::G)?,
+    };
+    let metalness = single(renderer, &set.metalness, "metalness", Channel::B)?;
+    let opacity = single(renderer, &set.opacity, "opacity", Channel::A)?;
+    //no height map just means no parallax, so it isn't counted as missing
+    let height = match &set.displacement {
+        Some(path) => Some(renderer.load_texture(path, TextureRole::Data)?),
+        None => None,
+    };
+
+    if !missing.is_empty() {
+        println!("Material {:?} has no {} map, using defaults", set.name, missing.join("/"));
+    }
+
+    renderer.add_material(Material {
+        albedo,
+        roughness,
+        metalness,
+        normal,
+        normal_convention: set.normal_convention,
+        normal_strength: set.normal_strength,
+        ao,
+        emission,
+        opacity,
+        height,
+        parallax: set.parallax,
+    })
+}
+
+//flips every color channel, inverting each byte works for 16 bit channels too since 65535 - (hi * 256 + lo) = (255 - hi) * 256 + (255 - lo)
+fn gloss_to_roughness(renderer: &mut Renderer, path: &Path) -> Result<TextureId, RendererError> {
+    if is_container(&path.to_string_lossy()) {
+        return Err(RendererError::decode(path, "gloss maps get inverted on load, which can't be done to KTX2/DDS, convert it to roughness first"));
+    }
+    let mut image = decode_image(&path.to_string_lossy(), TextureRole::Data)?;
+    if image.bytes_per_channel > 2 {
+        //float gloss maps come in as one 32 bit channel
+        for s in image.pixels.chunks_exact_mut(4) {
+            let v = f32::from_ne_bytes([s[0], s[1], s[2], s[3]]);
+            s.copy_from_slice(&(1.0 - v).to_ne_bytes());
+        }
+        let texture = upload_texture(image.pixels, image.width, image.height, image.format, MipFilter::Box, renderer.queue())?;
+        return Ok(renderer.add_texture(texture));
+    }
+    let has_alpha = image.channels == 2 || image.channels == 4;
+    let px_size = image.channels * image.bytes_per_channel;
+    let color_bytes = if has_alpha { px_size - image.bytes_per_channel } else { px_size };
+    for px in image.pixels.chunks_exact_mut(px_size) {
+        for b in px[..color_bytes].iter_mut() {
+            *b = 255 - *b;
+        }
+    }
+    let texture = upload_texture(image.pixels, image.width, image.height, image.format, MipFilter::Box, renderer.queue())?;
+    Ok(renderer.add_texture(texture))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //a folder of empty files named like a downloaded texture set, discovery only looks at the names
+    fn folder(name: &str, files: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("project_g_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in files {
+            std::fs::write(dir.join(file), b"").unwrap();
+        }
+        dir
+    }
+
+    fn file_name(path: &Option<PathBuf>) -> Option<&str> {
+        path.as_ref().and_then(|p| p.file_name()).and_then(|n| n.to_str())
+    }
+
+    #[test]
+    fn classify_strips_resolution_and_reads_two_word_suffixes() {
+        assert_eq!(classify(Path::new("Bricks076C_1K_Color.png")), Some(("Bricks076C_1K".to_string(), MapKind::Albedo, 1)));
+        assert_eq!(classify(Path::new("brick_wall_001_nor_gl_4k.exr")), Some(("brick_wall_001".to_string(), MapKind::NormalGl, 1)));
+        assert_eq!(classify(Path::new("brick_wall_001_diff_1k.jpg")), Some(("brick_wall_001".to_string(), MapKind::Albedo, 3)));
+        //a compressed copy outranks the loose image of the same map
+        assert_eq!(classify(Path::new("Bricks_Roughness.ktx2")), Some(("Bricks".to_string(), MapKind::Roughness, 0)));
+        assert_eq!(classify(Path::new("Bricks_Color.txt")), None);
+        assert_eq!(classify(Path::new("Color.png")), None);
+        assert_eq!(classify(Path::new("Bricks_Preview.png")), None);
+    }
+
+    #[test]
+    fn ambientcg_set_prefers_gl_normals_and_greyscale_maps() {
+        let dir = folder(
+            "ambientcg",
+            &[
+                "Metal_1K_Color.png",
+                "Metal_1K_NormalDX.png",
+                "Metal_1K_NormalGL.png",
+                "Metal_1K_Roughness.png",
+                "Metal_1K_RoughnessRGB.png",
+                "Metal_1K_Metalness.png",
+                "Metal_1K_Displacement.png",
+                "Metal_1K.usda",
+            ],
+        );
+        let set = discover_material(&dir, None).unwrap();
+        assert_eq!(set.name, "Metal_1K");
+        assert_eq!(file_name(&set.albedo), Some("Metal_1K_Color.png"));
+        assert_eq!(file_name(&set.normal), Some("Metal_1K_NormalGL.png"));
+        assert_eq!(set.normal_convention, NormalConvention::OpenGl);
+        assert_eq!(file_name(&set.roughness), Some("Metal_1K_Roughness.png"));
+        assert!(!set.roughness_is_gloss);
+        assert_eq!(file_name(&set.metalness), Some("Metal_1K_Metalness.png"));
+        assert_eq!(file_name(&set.displacement), Some("Metal_1K_Displacement.png"));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn poly_haven_set_falls_back_to_dx_normals_and_gloss() {
+        let dir = folder(
+            "polyhaven",
+            &["rock_01_diff_2k.jpg", "rock_01_nor_dx_2k.exr", "rock_01_gloss_2k.png", "rock_01_arm_2k.png", "rock_01_ao_2k.png"],
+        );
+        let set = discover_material(&dir, None).unwrap();
+        assert_eq!(set.name, "rock_01");
+        assert_eq!(file_name(&set.albedo), Some("rock_01_diff_2k.jpg"));
+        assert_eq!(file_name(&set.normal), Some("rock_01_nor_dx_2k.exr"));
+        assert_eq!(set.normal_convention, NormalConvention::DirectX);
+        assert_eq!(file_name(&set.roughness), Some("rock_01_gloss_2k.png"));
+        assert!(set.roughness_is_gloss);
+        assert_eq!(file_name(&set.orm), Some("rock_01_arm_2k.png"));
+        assert_eq!(file_name(&set.ao), Some("rock_01_ao_2k.png"));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn prefix_picks_one_material_out_of_a_shared_folder() {
+        let dir = folder("shared", &["Alpha_Color.png", "Beta_Color.png", "Beta_Normal.png", "Beta_Diffuse.png"]);
+        let set = discover_material(&dir, Some("beta")).unwrap();
+        assert_eq!(set.name, "Beta");
+        //Color ranks above Diffuse when both are there
+        assert_eq!(file_name(&set.albedo), Some("Beta_Color.png"));
+        assert_eq!(file_name(&set.normal), Some("Beta_Normal.png"));
+        assert!(discover_material(&dir, Some("gamma")).is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
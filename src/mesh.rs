@@ -0,0 +1,228 @@
+use std::{fmt, path::Path};
+
+use tobj::LoadOptions;
+
+use crate::mesh_gen::{generate_normals, generate_uvs, UvProjection};
+use crate::vertex::Vertex;
+use crate::tangents::generate_tangents;
+
+//One object (or group) out of an OBJ file, already flattened in to the single index layout Vulkan wants
+#[derive(Default, Debug, Clone)]
+pub struct Mesh {
+    pub name: String,
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    //index in to the materials of the MTL file that came with the OBJ, if there was one
+    pub material_id: Option<usize>,
+}
+
+//Everything that can go wrong turning an OBJ file in to meshes
+#[derive(Debug)]
+pub enum MeshError {
+    Load(tobj::LoadError),
+    MismatchedNormals { mesh: String, positions: usize, normals: usize },
+    MismatchedTexcoords { mesh: String, positions: usize, texcoords: usize },
+    //the index count isn't a whole number of triangles
+    PartialTriangle { mesh: String, indices: usize },
+    //an index past the last vertex, the GPU would read whatever memory is after the vertex buffer
+    IndexOutOfRange { mesh: String, index: u32, vertices: usize },
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::Load(e) => write!(f, "failed to load OBJ: {}", e),
+            MeshError::MismatchedNormals { mesh, positions, normals } => write!(
+                f,
+                "mesh {:?} has {} positions but {} normals",
+                mesh, positions, normals
+            ),
+            MeshError::MismatchedTexcoords { mesh, positions, texcoords } => write!(
+                f,
+                "mesh {:?} has {} positions but {} texcoords",
+                mesh, positions, texcoords
+            ),
+            MeshError::PartialTriangle { mesh, indices } => write!(
+                f,
+                "mesh {:?} has {} indices, which isn't a whole number of triangles",
+                mesh, indices
+            ),
+            MeshError::IndexOutOfRange { mesh, index, vertices } => write!(
+                f,
+                "mesh {:?} has index {} but only {} vertices",
+                mesh, index, vertices
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MeshError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MeshError::Load(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<tobj::LoadError> for MeshError {
+    fn from(e: tobj::LoadError) -> Self {
+        MeshError::Load(e)
+    }
+}
+
+//Makes sure an index buffer is whole triangles that only point at real vertices
+//the GPU doesn't bounds check vertex fetches, so anything else has to be caught before upload
+pub fn check_indices<T: Copy + Into<u32>>(mesh: &str, indices: &[T], vertex_count: usize) -> Result<(), MeshError> {
+    if !indices.len().is_multiple_of(3) {
+        return Err(MeshError::PartialTriangle { mesh: mesh.to_string(), indices: indices.len() });
+    }
+    match indices.iter().map(|&i| i.into()).find(|&i| i as usize >= vertex_count) {
+        Some(index) => Err(MeshError::IndexOutOfRange { mesh: mesh.to_string(), index, vertices: vertex_count }),
+        None => Ok(()),
+    }
+}
+
+//What to do when an OBJ is missing data the renderer needs
+#[derive(Debug, Clone, Copy)]
+pub struct MeshOptions {
+    //faces meeting sharper than this (in degrees) get a hard edge when normals are generated
+    pub crease_angle: f32,
+    //how UVs get made up when the file has none
+    pub uv_projection: UvProjection,
+}
+
+impl Default for MeshOptions {
+    fn default() -> Self {
+        MeshOptions {
+            crease_angle: 60.0,
+            uv_projection: UvProjection::Spherical,
+        }
+    }
+}
+
+//Loads every object and group in an OBJ file, generating any missing normals and UVs with the default options
+pub fn load_obj<P: AsRef<Path>>(path: P) -> Result<Vec<Mesh>, MeshError> {
+    load_obj_with(path, &MeshOptions::default())
+}
+
+//Loads every object and group in an OBJ file
+//normally OBJs come with seperate indexes for verts, normals, and UVs
+//single_index has tobj compress and reorder them in to a single index, which is what opengl and vulkan wants
+pub fn load_obj_with<P: AsRef<Path>>(path: P, opts: &MeshOptions) -> Result<Vec<Mesh>, MeshError> {
+    let load_opts = LoadOptions {
+        single_index: true,
+        triangulate: true,
+        ..Default::default()
+    };
+
+    //we don't care about the MTL file here, just the material ids the faces point at
+    let (models, _materials) = tobj::load_obj(path.as_ref(), &load_opts)?;
+
+    models.into_iter().map(|m| mesh_from_model(m.name, m.mesh, opts)).collect()
+}
+
+//reorganizes the flat tobj arrays in to a list of Vertex structs
+fn mesh_from_model(name: String, mesh: tobj::Mesh, opts: &MeshOptions) -> Result<Mesh, MeshError> {
+    let vert_count = mesh.positions.len() / 3;
+
+    //no normals at all is fine, we make some, but only having some of them means the file is broken
+    let has_normals = !mesh.normals.is_empty();
+    if has_normals && mesh.normals.len() != mesh.positions.len() {
+        return Err(MeshError::MismatchedNormals {
+            mesh: name,
+            positions: vert_count,
+            normals: mesh.normals.len() / 3,
+        });
+    }
+
+    let has_uvs = !mesh.texcoords.is_empty();
+    if has_uvs && mesh.texcoords.len() / 2 != vert_count {
+        return Err(MeshError::MismatchedTexcoords {
+            mesh: name,
+            positions: vert_count,
+            texcoords: mesh.texcoords.len() / 2,
+        });
+    }
+
+    let mut vertices = (0..vert_count)
+        .map(|i| {
+            let mut v = Vertex {
+                position: (mesh.positions[i * 3], mesh.positions[i * 3 + 1], mesh.positions[i * 3 + 2]),
+                ..Default::default()
+            };
+            if has_normals {
+                v.normal = (mesh.normals[i * 3], mesh.normals[i * 3 + 1], mesh.normals[i * 3 + 2]);
+            }
+            if has_uvs {
+                v.uv = (mesh.texcoords[i * 2], mesh.texcoords[i * 2 + 1]);
+            }
+            v
+        })
+        .collect();
+
+    let mut indices = mesh.indices;
+
+    //normals go first so the vertices the UV seams split already have theirs
+    if !has_normals {
+        println!(
+            "WARNING: mesh {:?} has no normals, generating them with a {} degree crease angle",
+            name, opts.crease_angle
+        );
+        generate_normals(&mut vertices, &mut indices, opts.crease_angle);
+    }
+
+    if !has_uvs {
+        println!(
+            "WARNING: mesh {:?} has no texture coordinates, generating them with a {:?} projection",
+            name, opts.uv_projection
+        );
+        generate_uvs(&mut vertices, &mut indices, opts.uv_projection);
+    }
+
+    //OBJ has no way to store tangents so they always get generated
+    generate_tangents(&mut vertices, &mut indices);
+
+    Ok(Mesh {
+        name,
+        vertices,
+        indices,
+        material_id: mesh.material_id,
+    })
+}
+
+//Squashes a list of meshes in to one so they can go out in a single draw call
+//the indices of each mesh get offset past the vertices of the meshes before it
+pub fn merge_meshes(meshes: &[Mesh]) -> Mesh {
+    let mut merged = Mesh {
+        name: meshes.iter().map(|m| m.name.as_str()).collect::<Vec<_>>().join("+"),
+        ..Default::default()
+    };
+
+    for m in meshes {
+        let offset = merged.vertices.len() as u32;
+        merged.vertices.extend(m.vertices.iter().cloned());
+        merged.indices.extend(m.indices.iter().map(|i| i + offset));
+    }
+
+    //only keep the material if everything agreed on it
+    if let Some(first) = meshes.first() {
+        if meshes.iter().all(|m| m.material_id == first.material_id) {
+            merged.material_id = first.material_id;
+        }
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_indices_catches_partial_triangles_and_bad_indices() {
+        assert!(check_indices("ok", &[0u32, 1, 2, 2, 1, 3], 4).is_ok());
+        assert!(matches!(check_indices("short", &[0u32, 1, 2, 3], 4), Err(MeshError::PartialTriangle { indices: 4, .. })));
+        assert!(matches!(check_indices("bad", &[0u16, 1, 4], 4), Err(MeshError::IndexOutOfRange { index: 4, vertices: 4, .. })));
+    }
+}
@@ -0,0 +1,282 @@
+use std::{collections::HashMap, f32::consts::PI, hash::Hash};
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::vertex::Vertex;
+
+//How to come up with texture coordinates for a mesh that didn't ship with any
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UvProjection {
+    //wrap the texture around the mesh like a globe
+    Spherical,
+    //project each face along whichever axis it points down the most
+    Box,
+    //flatten the mesh on to the plane of its two largest dimensions
+    Planar,
+}
+
+fn pos(v: &Vertex) -> Vector3<f32> {
+    Vector3::new(v.position.0, v.position.1, v.position.2)
+}
+
+//Gives every corner of every triangle its own value of something (a normal, a UV)
+//first corner to claim a vertex sets the value, any corner that disagrees gets a copy of the vertex
+//key turns a value in to something hashable so corners that agree with each other share the same copy
+fn split_corners<T, K>(vertices: &mut Vec<Vertex>, indices: &mut [u32], values: &[T], get: fn(&Vertex) -> T, set: fn(&mut Vertex, T), key: fn(T) -> K)
+where
+    T: Copy + PartialEq,
+    K: Eq + Hash,
+{
+    let mut claimed = vec![false; vertices.len()];
+    let mut splits = HashMap::<(u32, K), u32>::new();
+    for (idx, value) in indices.iter_mut().zip(values.iter()) {
+        let v = *idx as usize;
+        if !claimed[v] {
+            claimed[v] = true;
+            set(&mut vertices[v], *value);
+        } else if get(&vertices[v]) != *value {
+            *idx = *splits.entry((*idx, key(*value))).or_insert_with(|| {
+                let mut split = vertices[v].clone();
+                set(&mut split, *value);
+                vertices.push(split);
+                (vertices.len() - 1) as u32
+            });
+        }
+    }
+}
+
+//Fills in every vertex's displacement direction, the sum of the different normals found at its position
+//vertices only get split where something else differs, so this is the normal the mesh would have had without the split
+//a cube corner gets the diagonal between its three faces, a UV seam on a smooth surface just gets its normal back
+pub fn displacement_normals(vertices: &mut [Vertex]) {
+    let key = |v: &Vertex| (v.position.0.to_bits(), v.position.1.to_bits(), v.position.2.to_bits());
+    let mut normals: HashMap<_, Vec<(u32, u32, u32)>> = HashMap::new();
+    for v in vertices.iter() {
+        //each distinct normal only counts once, otherwise a side split by a seam as well would get twice the pull
+        let n = (v.normal.0.to_bits(), v.normal.1.to_bits(), v.normal.2.to_bits());
+        let found = normals.entry(key(v)).or_default();
+        if !found.contains(&n) {
+            found.push(n);
+        }
+    }
+    for v in vertices.iter_mut() {
+        let sum = normals[&key(v)]
+            .iter()
+            .map(|n| Vector3::new(f32::from_bits(n.0), f32::from_bits(n.1), f32::from_bits(n.2)))
+            .fold(Vector3::new(0.0, 0.0, 0.0), |a, b| a + b);
+        let d = if sum.magnitude2() > 0.0 { sum.normalize() } else { Vector3::new(v.normal.0, v.normal.1, v.normal.2) };
+        v.displace = (d.x, d.y, d.z);
+    }
+}
+
+//Computes normals for a mesh that has none
+//faces meeting at an angle sharper than crease_angle (in degrees) keep a hard edge between them,
+//0 gives flat shading and 180 smooths everything
+//vertices that end up with different normals on different faces get split
+pub fn generate_normals(vertices: &mut Vec<Vertex>, indices: &mut [u32], crease_angle: f32) {
+    let tri_count = indices.len() / 3;
+
+    //the cross product is twice the triangle's area, so bigger faces pull the normal harder
+    let face_normals = (0..tri_count)
+        .map(|f| {
+            let a = pos(&vertices[indices[f * 3] as usize]);
+            let b = pos(&vertices[indices[f * 3 + 1] as usize]);
+            let c = pos(&vertices[indices[f * 3 + 2] as usize]);
+            (b - a).cross(c - a)
+        })
+        .collect::<Vec<_>>();
+    let unit_normals = face_normals
+        .iter()
+        .map(|n| if n.magnitude2() > 0.0 { n.normalize() } else { *n })
+        .collect::<Vec<_>>();
+
+    //single_index can hand us several vertices at the same spot (UV seams), they still need to smooth together
+    //so faces get grouped by the position of their corners rather than the index
+    let key = |v: &Vertex| [v.position.0.to_bits(), v.position.1.to_bits(), v.position.2.to_bits()];
+    let mut faces_at = HashMap::<[u32; 3], Vec<usize>>::new();
+    for (i, idx) in indices.iter().enumerate() {
+        faces_at.entry(key(&vertices[*idx as usize])).or_default().push(i / 3);
+    }
+
+    let cos_crease = crease_angle.to_radians().cos();
+    let corner_normals = indices
+        .iter()
+        .enumerate()
+        .map(|(i, idx)| {
+            let face = i / 3;
+            let sum = faces_at[&key(&vertices[*idx as usize])]
+                .iter()
+                .filter(|f| **f == face || unit_normals[face].dot(unit_normals[**f]) >= cos_crease)
+                .fold(Vector3::new(0.0, 0.0, 0.0), |acc, f| acc + face_normals[*f]);
+            let n = if sum.magnitude2() > 0.0 { sum.normalize() } else { sum };
+            (n.x, n.y, n.z)
+        })
+        .collect::<Vec<_>>();
+
+    split_corners(vertices, indices, &corner_normals, |v| v.normal, |v, n| v.normal = n, |n| [n.0.to_bits(), n.1.to_bits(), n.2.to_bits()]);
+}
+
+//Fills in the UVs with a projection fit to the mesh's bounding box
+//UVs are worked out per triangle, so vertices on a seam (the back of the sphere, the edges of the box) get split
+pub fn generate_uvs(vertices: &mut Vec<Vertex>, indices: &mut [u32], projection: UvProjection) {
+    let tri_count = indices.len() / 3;
+    if vertices.is_empty() || tri_count == 0 {
+        return;
+    }
+
+    let mut min = pos(&vertices[0]);
+    let mut max = min;
+    for v in vertices.iter() {
+        let p = pos(v);
+        min = Vector3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+        max = Vector3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+    }
+    let center = (min + max) * 0.5;
+    let size = max - min;
+    let largest = size.x.max(size.y).max(size.z).max(f32::EPSILON);
+
+    //maps a coordinate along an axis to [0, 1] across the largest side so the texture doesn't stretch
+    let along = |p: Vector3<f32>, axis: usize| (p[axis] - min[axis]) / largest;
+    //the two axes left when looking down one of them
+    let plane = |axis: usize| match axis {
+        0 => (2, 1),
+        1 => (0, 2),
+        _ => (0, 1),
+    };
+    //Planar drops the axis the mesh is thinnest along
+    let thinnest = if size.x <= size.y && size.x <= size.z {
+        0
+    } else if size.y <= size.z {
+        1
+    } else {
+        2
+    };
+
+    let corner_uvs = (0..tri_count)
+        .flat_map(|f| {
+            let corners = [0, 1, 2].map(|c| pos(&vertices[indices[f * 3 + c] as usize]));
+            let axis = match projection {
+                UvProjection::Spherical => return spherical_uvs(corners, center),
+                UvProjection::Planar => thinnest,
+                //the face's own normal so every corner of it agrees on the axis
+                UvProjection::Box => {
+                    let n = (corners[1] - corners[0]).cross(corners[2] - corners[0]);
+                    let (ax, ay, az) = (n.x.abs(), n.y.abs(), n.z.abs());
+                    if ax >= ay && ax >= az {
+                        0
+                    } else if ay >= az {
+                        1
+                    } else {
+                        2
+                    }
+                }
+            };
+            let (a, b) = plane(axis);
+            corners.map(|p| (along(p, a), along(p, b)))
+        })
+        .collect::<Vec<_>>();
+
+    split_corners(vertices, indices, &corner_uvs, |v| v.uv, |v, uv| v.uv = uv, |uv| [uv.0.to_bits(), uv.1.to_bits()]);
+}
+
+//one triangle's UVs wrapped around center like a globe
+fn spherical_uvs(corners: [Vector3<f32>; 3], center: Vector3<f32>) -> [(f32, f32); 3] {
+    let dirs = corners.map(|p| {
+        let d = p - center;
+        if d.magnitude2() > 0.0 { d.normalize() } else { Vector3::unit_y() }
+    });
+    let mut uvs = dirs.map(|d| (0.5 + d.z.atan2(d.x) / (2.0 * PI), 0.5 - d.y.clamp(-1.0, 1.0).asin() / PI));
+
+    //a triangle across the seam has corners near 1 and near 0, pushing the small ones past 1 carries on across it
+    //instead of squeezing the whole texture in backwards, the sampler repeats so u > 1 is fine
+    let lo = uvs.iter().map(|uv| uv.0).fold(f32::MAX, f32::min);
+    let hi = uvs.iter().map(|uv| uv.0).fold(f32::MIN, f32::max);
+    if hi - lo > 0.5 {
+        for uv in uvs.iter_mut() {
+            if uv.0 < 0.5 {
+                uv.0 += 1.0;
+            }
+        }
+    }
+    //every u is the same point at the poles, taking the middle of the other two corners stops the texture twisting in to it
+    for c in 0..3 {
+        if dirs[c].y.abs() > 0.9999 {
+            uvs[c].0 = (uvs[(c + 1) % 3].0 + uvs[(c + 2) % 3].0) / 2.0;
+        }
+    }
+    uvs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(x: f32, y: f32, z: f32) -> Vertex {
+        Vertex { position: (x, y, z), ..Default::default() }
+    }
+
+    fn uv_area(vertices: &[Vertex], tri: &[u32]) -> f32 {
+        let uv = |i: usize| vertices[tri[i] as usize].uv;
+        let (a, b, c) = (uv(0), uv(1), uv(2));
+        ((b.0 - a.0) * (c.1 - a.1) - (c.0 - a.0) * (b.1 - a.1)).abs() / 2.0
+    }
+
+    #[test]
+    fn spherical_seam_gets_split() {
+        //a band of 8 quads around the Y axis, one of them crosses the seam at -X
+        let mut vertices = (0..16)
+            .map(|i| {
+                let angle = (i / 2) as f32 / 8.0 * 2.0 * PI;
+                vertex(angle.cos(), if i % 2 == 0 { -0.3 } else { 0.3 }, angle.sin())
+            })
+            .collect::<Vec<_>>();
+        let mut indices = (0..8u32)
+            .flat_map(|q| {
+                let (a, b, c, d) = (q * 2, q * 2 + 1, (q * 2 + 2) % 16, (q * 2 + 3) % 16);
+                [a, c, b, b, c, d]
+            })
+            .collect::<Vec<_>>();
+        generate_uvs(&mut vertices, &mut indices, UvProjection::Spherical);
+
+        //every quad covers an eighth of the texture, none of them the whole thing backwards
+        for tri in indices.chunks_exact(3) {
+            let us = tri.iter().map(|i| vertices[*i as usize].uv.0).collect::<Vec<_>>();
+            let range = us.iter().cloned().fold(f32::MIN, f32::max) - us.iter().cloned().fold(f32::MAX, f32::min);
+            assert!((range - 0.125).abs() < 1e-4, "u range {}", range);
+        }
+        assert!(vertices.len() > 16);
+    }
+
+    #[test]
+    fn box_splits_cube_edges() {
+        //a cube with one vertex per corner, like an OBJ without normals or UVs
+        let mut vertices = (0..8).map(|i| vertex((i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32)).collect::<Vec<_>>();
+        let mut indices = vec![
+            0, 2, 1, 1, 2, 3, 4, 5, 6, 5, 7, 6, //-Z +Z
+            0, 1, 4, 1, 5, 4, 2, 6, 3, 3, 6, 7, //-Y +Y
+            0, 4, 2, 2, 4, 6, 1, 3, 5, 3, 7, 5, //-X +X
+        ];
+        generate_uvs(&mut vertices, &mut indices, UvProjection::Box);
+
+        //every face gets a whole texture, none get squashed in to a line along an edge
+        for tri in indices.chunks_exact(3) {
+            assert!((uv_area(&vertices, tri) - 0.5).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn displacement_is_shared_across_hard_edges() {
+        //two faces of a cube meeting at a hard edge, the edge vertices are split with one normal per face
+        let mut vertices = vec![
+            Vertex { position: (0.0, 0.0, 0.0), normal: (0.0, 1.0, 0.0), ..Default::default() },
+            Vertex { position: (0.0, 0.0, 0.0), normal: (1.0, 0.0, 0.0), ..Default::default() },
+            Vertex { position: (1.0, 0.0, 0.0), normal: (0.0, 1.0, 0.0), ..Default::default() },
+        ];
+        displacement_normals(&mut vertices);
+        let h = 0.5f32.sqrt();
+        for v in &vertices[..2] {
+            assert!((v.displace.0 - h).abs() < 1e-6 && (v.displace.1 - h).abs() < 1e-6 && v.displace.2 == 0.0);
+        }
+        assert_eq!(vertices[2].displace, (0.0, 1.0, 0.0));
+    }
+}
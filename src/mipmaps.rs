@@ -0,0 +1,135 @@
+use vulkano::format::Format;
+
+use crate::color_space::{linear_to_srgb, srgb_to_linear};
+use crate::texture_format::{format_layout, read_sample, write_sample, SampleType};
+
+//CPU mip chain generation
+//each level is a 2x2 box filter of the one above it, done in linear light so sRGB textures don't darken as they shrink
+//the usual trick is blitting each level down from the last on the GPU, but a blit is a plain linear filter so it can't renormalize normal maps,
+//and which formats can be blit from and to is up to the driver (compressed ones never can), so doing it here gives the same chain on every GPU
+
+//How a texture's mips should be filtered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MipFilter {
+    //plain average, sRGB formats get decoded first and re-encoded after
+    Box,
+    //averages the decoded vectors then renormalizes them, averaging normals shortens them and makes bumps fade out at a distance
+    Normal,
+}
+
+//One level of the chain, tightly packed the same way the source pixels were
+pub struct MipLevel {
+    pub pixels: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+//how many levels a full chain down to 1x1 has
+pub fn mip_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+//Builds every level below the one given, level 0 isn't included since the caller already has it
+//multi byte samples are expected in native byte order, which is what the GPU reads
+pub fn generate_mips(pixels: &[u8], width: u32, height: u32, format: Format, filter: MipFilter) -> Vec<MipLevel> {
+    let (channels, sample) = match format_layout(format) {
+        Some(layout) => layout,
+        None => return Vec::new(),
+    };
+    let srgb = sample == SampleType::Srgb8;
+
+    //everything gets filtered as floats, linear light for sRGB color channels (never alpha)
+    let is_color = |c: usize| srgb && (channels < 4 || c < 3) && (channels != 2 || c < 1);
+    let mut current = pixels
+        .chunks_exact(sample.bytes())
+        .enumerate()
+        .map(|(i, b)| {
+            let v = read_sample(b, sample);
+            if is_color(i % channels) { srgb_to_linear(v) } else { v }
+        })
+        .collect::<Vec<f32>>();
+
+    let (mut w, mut h) = (width as usize, height as usize);
+    let mut levels = Vec::new();
+    while w > 1 || h > 1 {
+        let (nw, nh) = ((w / 2).max(1), (h / 2).max(1));
+        let mut next = vec![0.0f32; nw * nh * channels];
+
+        for y in 0..nh {
+            //odd sizes just lose their last row/column, clamping keeps 1 pixel tall images working
+            let (y0, y1) = ((y * 2).min(h - 1), (y * 2 + 1).min(h - 1));
+            for x in 0..nw {
+                let (x0, x1) = ((x * 2).min(w - 1), (x * 2 + 1).min(w - 1));
+                let dst = &mut next[(y * nw + x) * channels..(y * nw + x + 1) * channels];
+                //two channel normals only store X and Y, Z gets rebuilt for each texel so the whole vectors get averaged
+                let mut z = 0.0;
+                for (sx, sy) in [(x0, y0), (x1, y0), (x0, y1), (x1, y1)] {
+                    let src = &current[(sy * w + sx) * channels..(sy * w + sx + 1) * channels];
+                    for (d, s) in dst.iter_mut().zip(src.iter()) {
+                        *d += s / 4.0;
+                    }
+                    if filter == MipFilter::Normal && channels == 2 {
+                        let (nx, ny) = (src[0] * 2.0 - 1.0, src[1] * 2.0 - 1.0);
+                        z += (1.0 - nx * nx - ny * ny).max(0.0).sqrt() / 4.0;
+                    }
+                }
+
+                //push the averaged vector back out to unit length, the map stores [-1, 1] as [0, 1]
+                if filter == MipFilter::Normal && channels >= 3 {
+                    let n = [dst[0] * 2.0 - 1.0, dst[1] * 2.0 - 1.0, dst[2] * 2.0 - 1.0];
+                    let len = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+                    if len > 1e-6 {
+                        for c in 0..3 {
+                            dst[c] = (n[c] / len) * 0.5 + 0.5;
+                        }
+                    } else {
+                        dst[..3].copy_from_slice(&[0.5, 0.5, 1.0]);
+                    }
+                }
+                if filter == MipFilter::Normal && channels == 2 {
+                    let n = [dst[0] * 2.0 - 1.0, dst[1] * 2.0 - 1.0, z];
+                    let len = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+                    if len > 1e-6 {
+                        for c in 0..2 {
+                            dst[c] = (n[c] / len) * 0.5 + 0.5;
+                        }
+                    } else {
+                        dst.copy_from_slice(&[0.5, 0.5]);
+                    }
+                }
+            }
+        }
+
+        let mut bytes = Vec::with_capacity(next.len() * sample.bytes());
+        for (i, v) in next.iter().enumerate() {
+            let v = if is_color(i % channels) { linear_to_srgb(*v) } else { *v };
+            write_sample(v, sample, &mut bytes);
+        }
+
+        levels.push(MipLevel { pixels: bytes, width: nw as u32, height: nh as u32 });
+        current = next;
+        w = nw;
+        h = nh;
+    }
+
+    levels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn two_channel_normals_get_renormalized() {
+        //two texels lean 45 degrees along X and two along Y, all with a Z of about 0.7
+        //the average vector is (0.35, 0.35, 0.71) which renormalizes to X and Y of 0.41,
+        //a plain box filter would store the 0.35 and the shader would rebuild a Z that's too big
+        let pixels = [218, 128, 218, 128, 128, 218, 128, 218];
+        let mips = generate_mips(&pixels, 2, 2, Format::R8G8Unorm, MipFilter::Normal);
+        assert_eq!(mips.len(), 1);
+        let expected = (0.41 * 0.5 + 0.5) * 255.0;
+        for c in mips[0].pixels.iter() {
+            assert!((*c as f32 - expected).abs() <= 1.0, "got {:?}", mips[0].pixels);
+        }
+    }
+}
@@ -0,0 +1,448 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::vertex::Vertex;
+
+//Which passes to run over a mesh before it goes to the GPU, all of them are on by default
+#[derive(Debug, Clone, Copy)]
+pub struct OptimizeOptions {
+    //merge vertices that are bit for bit identical
+    pub dedup: bool,
+    //reorder triangles so recently transformed vertices get reused (Forsyth's algorithm)
+    pub vertex_cache: bool,
+    //reorder clusters of triangles so the outward facing ones draw first and hide the ones behind them
+    pub overdraw: bool,
+    //reorder the vertex buffer in to the order the index buffer reads it
+    pub vertex_fetch: bool,
+    //size of the LRU cache the reordering aims for
+    pub cache_size: usize,
+}
+
+impl Default for OptimizeOptions {
+    fn default() -> Self {
+        OptimizeOptions {
+            dedup: true,
+            vertex_cache: true,
+            overdraw: true,
+            vertex_fetch: true,
+            cache_size: 32,
+        }
+    }
+}
+
+//Before and after numbers so we can see what the optimizer actually bought us
+//ACMR is cache misses per triangle (0.5 is about the best possible, 3.0 is no reuse at all)
+//ATVR is cache misses per vertex (1.0 is perfect)
+#[derive(Debug, Clone, Copy)]
+pub struct OptimizeStats {
+    pub vertices_before: usize,
+    pub vertices_after: usize,
+    pub acmr_before: f32,
+    pub acmr_after: f32,
+    pub atvr_before: f32,
+    pub atvr_after: f32,
+}
+
+//the FIFO cache size the stats are measured against, this is what most GPUs roughly behave like
+const STATS_CACHE_SIZE: usize = 16;
+
+//Runs the passes turned on in opts over the mesh in place
+//a leftover partial triangle at the end gets dropped (it would never be drawn), a mesh with indices past its vertices is left alone
+pub fn optimize_mesh(vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>, opts: &OptimizeOptions) -> OptimizeStats {
+    let vertices_before = vertices.len();
+    indices.truncate(indices.len() / 3 * 3);
+    if let Some(bad) = indices.iter().find(|i| **i as usize >= vertices.len()) {
+        println!("WARNING: index {} is past the end of the mesh's {} vertices, not optimizing it", bad, vertices.len());
+        return OptimizeStats {
+            vertices_before,
+            vertices_after: vertices_before,
+            acmr_before: 0.0,
+            acmr_after: 0.0,
+            atvr_before: 0.0,
+            atvr_after: 0.0,
+        };
+    }
+    let (acmr_before, atvr_before) = cache_stats(indices, vertices.len(), STATS_CACHE_SIZE);
+
+    if opts.dedup {
+        dedup_vertices(vertices, indices);
+    }
+    if opts.vertex_cache {
+        optimize_vertex_cache(indices, vertices.len(), opts.cache_size);
+    }
+    if opts.overdraw {
+        optimize_overdraw(indices, vertices, STATS_CACHE_SIZE);
+    }
+    if opts.vertex_fetch {
+        optimize_vertex_fetch(vertices, indices);
+    }
+
+    let (acmr_after, atvr_after) = cache_stats(indices, vertices.len(), STATS_CACHE_SIZE);
+
+    OptimizeStats {
+        vertices_before,
+        vertices_after: vertices.len(),
+        acmr_before,
+        acmr_after,
+        atvr_before,
+        atvr_after,
+    }
+}
+
+//Simulates a FIFO post transform cache and returns (ACMR, ATVR)
+pub fn cache_stats(indices: &[u32], vertex_count: usize, cache_size: usize) -> (f32, f32) {
+    if indices.is_empty() || vertex_count == 0 {
+        return (0.0, 0.0);
+    }
+
+    //timestamp of when each vertex went in to the cache, it's still there if fewer than cache_size misses happened since
+    let mut cached_at = vec![None; vertex_count];
+    let mut misses = 0usize;
+    for idx in indices {
+        let hit = match cached_at[*idx as usize] {
+            Some(t) => misses - t < cache_size,
+            None => false,
+        };
+        if !hit {
+            cached_at[*idx as usize] = Some(misses);
+            misses += 1;
+        }
+    }
+
+    let tris = (indices.len() / 3) as f32;
+    (misses as f32 / tris, misses as f32 / vertex_count as f32)
+}
+
+//Collapses identical vertices in to one and points the indices at the survivors
+pub fn dedup_vertices(vertices: &mut Vec<Vertex>, indices: &mut [u32]) {
+    let bits = |v: &Vertex| {
+        [
+            v.position.0.to_bits(), v.position.1.to_bits(), v.position.2.to_bits(),
+            v.normal.0.to_bits(), v.normal.1.to_bits(), v.normal.2.to_bits(),
+            v.uv.0.to_bits(), v.uv.1.to_bits(),
+            v.tangent.0.to_bits(), v.tangent.1.to_bits(), v.tangent.2.to_bits(), v.tangent.3.to_bits(),
+        ]
+    };
+
+    let mut seen = HashMap::<[u32; 12], u32>::new();
+    let mut unique = Vec::with_capacity(vertices.len());
+    let remap = vertices
+        .iter()
+        .map(|v| {
+            *seen.entry(bits(v)).or_insert_with(|| {
+                unique.push(v.clone());
+                (unique.len() - 1) as u32
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for idx in indices.iter_mut() {
+        *idx = remap[*idx as usize];
+    }
+    *vertices = unique;
+}
+
+//Tom Forsyth's "Linear-Speed Vertex Cache Optimisation"
+//every vertex gets a score from where it sits in a simulated LRU cache and how many triangles still need it,
+//and we greedily emit whichever triangle has the best total score
+pub fn optimize_vertex_cache(indices: &mut Vec<u32>, vertex_count: usize, cache_size: usize) {
+    const CACHE_DECAY_POWER: f32 = 1.5;
+    const LAST_TRI_SCORE: f32 = 0.75;
+    const VALENCE_BOOST_SCALE: f32 = 2.0;
+    const VALENCE_BOOST_POWER: f32 = 0.5;
+
+    //same rules as optimize_mesh, the partial triangle goes and bad indices leave the order as it was
+    indices.truncate(indices.len() / 3 * 3);
+    let tri_count = indices.len() / 3;
+    if tri_count == 0 || indices.iter().any(|i| *i as usize >= vertex_count) {
+        return;
+    }
+    let cache_size = cache_size.max(4);
+
+    let score = |cache_pos: Option<usize>, remaining: usize| -> f32 {
+        if remaining == 0 {
+            return -1.0;
+        }
+        let cache_score = match cache_pos {
+            //the three verts of the triangle we just drew get a fixed score so we don't favour one over the others
+            Some(p) if p < 3 => LAST_TRI_SCORE,
+            Some(p) if p < cache_size => {
+                let scaler = 1.0 / (cache_size - 3) as f32;
+                (1.0 - (p - 3) as f32 * scaler).powf(CACHE_DECAY_POWER)
+            }
+            _ => 0.0,
+        };
+        //vertices with only a few triangles left get a boost so we finish them off and don't leave stragglers
+        cache_score + VALENCE_BOOST_SCALE * (remaining as f32).powf(-VALENCE_BOOST_POWER)
+    };
+
+    //which triangles use each vertex
+    let mut vert_tris = vec![Vec::new(); vertex_count];
+    for (i, idx) in indices.iter().enumerate() {
+        vert_tris[*idx as usize].push(i / 3);
+    }
+    let mut remaining = vert_tris.iter().map(|t| t.len()).collect::<Vec<_>>();
+    let mut cache_pos = vec![None; vertex_count];
+    let mut vert_score = (0..vertex_count).map(|v| score(None, remaining[v])).collect::<Vec<_>>();
+
+    let tri_verts = |t: usize| [indices[t * 3] as usize, indices[t * 3 + 1] as usize, indices[t * 3 + 2] as usize];
+    let mut tri_score = (0..tri_count)
+        .map(|t| tri_verts(t).iter().map(|v| vert_score[*v]).sum::<f32>())
+        .collect::<Vec<_>>();
+    let mut emitted = vec![false; tri_count];
+
+    let mut cache: Vec<usize> = Vec::with_capacity(cache_size + 3);
+    let mut output = Vec::with_capacity(indices.len());
+    let mut next_unemitted = 0;
+    let mut best = Some((0..tri_count).fold(0, |b, t| if tri_score[t] > tri_score[b] { t } else { b }));
+
+    for _ in 0..tri_count {
+        //nothing in the cache is useful, fall back to the next triangle we haven't drawn yet
+        let tri = match best {
+            Some(t) => t,
+            None => {
+                while emitted[next_unemitted] {
+                    next_unemitted += 1;
+                }
+                next_unemitted
+            }
+        };
+
+        emitted[tri] = true;
+        let verts = tri_verts(tri);
+        for v in verts.iter() {
+            output.push(*v as u32);
+            remaining[*v] -= 1;
+            if let Some(pos) = vert_tris[*v].iter().position(|t| *t == tri) {
+                vert_tris[*v].swap_remove(pos);
+            }
+        }
+
+        //move the triangle's vertices to the front of the LRU cache
+        cache.retain(|c| !verts.contains(c));
+        for (i, v) in verts.iter().enumerate() {
+            cache.insert(i, *v);
+        }
+
+        //everything that fell off the end needs its score recalculated too
+        let evicted = if cache.len() > cache_size { cache.split_off(cache_size) } else { Vec::new() };
+        for v in evicted.iter() {
+            cache_pos[*v] = None;
+            vert_score[*v] = score(None, remaining[*v]);
+        }
+        for (i, v) in cache.iter().enumerate() {
+            cache_pos[*v] = Some(i);
+            vert_score[*v] = score(Some(i), remaining[*v]);
+        }
+
+        //only triangles touching the cache can have changed, so the next pick comes from those
+        best = None;
+        let mut best_score = -1.0;
+        for v in cache.iter().chain(evicted.iter()) {
+            for t in vert_tris[*v].iter() {
+                let s = tri_verts(*t).iter().map(|v| vert_score[*v]).sum::<f32>();
+                tri_score[*t] = s;
+                if s > best_score {
+                    best_score = s;
+                    best = Some(*t);
+                }
+            }
+        }
+    }
+
+    *indices = output;
+}
+
+//Splits the (already cache optimized) triangles in to clusters wherever the cache starts over,
+//then sorts the clusters so the ones facing away from the middle of the mesh draw first
+//this is the same idea as Sander et al's Tipsify overdraw pass, and barely costs any cache efficiency
+pub fn optimize_overdraw(indices: &mut Vec<u32>, vertices: &[Vertex], cache_size: usize) {
+    let tri_count = indices.len() / 3;
+    if tri_count == 0 {
+        return;
+    }
+
+    let pos = |i: u32| {
+        let p = vertices[i as usize].position;
+        Vector3::new(p.0, p.1, p.2)
+    };
+
+    //a triangle that misses on all three vertices is a good place to start a new cluster
+    let mut cluster_starts = vec![0];
+    let mut cached_at = vec![None; vertices.len()];
+    let mut misses = 0usize;
+    for t in 0..tri_count {
+        let mut tri_misses = 0;
+        for idx in &indices[t * 3..t * 3 + 3] {
+            let hit = matches!(cached_at[*idx as usize], Some(at) if misses - at < cache_size);
+            if !hit {
+                cached_at[*idx as usize] = Some(misses);
+                misses += 1;
+                tri_misses += 1;
+            }
+        }
+        if tri_misses == 3 && t != 0 {
+            cluster_starts.push(t);
+        }
+    }
+    cluster_starts.push(tri_count);
+
+    let mesh_center = indices.iter().fold(Vector3::new(0.0, 0.0, 0.0), |acc, i| acc + pos(*i)) / indices.len() as f32;
+
+    let mut clusters = cluster_starts
+        .windows(2)
+        .map(|w| {
+            let (start, end) = (w[0], w[1]);
+            let mut center = Vector3::new(0.0, 0.0, 0.0);
+            let mut normal = Vector3::new(0.0, 0.0, 0.0);
+            let mut area = 0.0;
+            for t in start..end {
+                let (a, b, c) = (pos(indices[t * 3]), pos(indices[t * 3 + 1]), pos(indices[t * 3 + 2]));
+                let n = (b - a).cross(c - a);
+                let tri_area = n.magnitude();
+                center += (a + b + c) / 3.0 * tri_area;
+                normal += n;
+                area += tri_area;
+            }
+            let center = if area > 0.0 { center / area } else { center };
+            let normal = if normal.magnitude2() > 0.0 { normal.normalize() } else { normal };
+            //clusters out on the edge of the mesh facing outwards are the ones most likely to cover something
+            let sort_key = (center - mesh_center).dot(normal);
+            (sort_key, start, end)
+        })
+        .collect::<Vec<_>>();
+
+    clusters.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    let mut output = Vec::with_capacity(indices.len());
+    for (_, start, end) in clusters {
+        output.extend_from_slice(&indices[start * 3..end * 3]);
+    }
+    *indices = output;
+}
+
+//Puts the vertices in the order the index buffer first touches them so the GPU reads memory front to back,
+//vertices nothing points at get dropped
+pub fn optimize_vertex_fetch(vertices: &mut Vec<Vertex>, indices: &mut [u32]) {
+    let mut remap = vec![None; vertices.len()];
+    let mut reordered = Vec::with_capacity(vertices.len());
+
+    for idx in indices.iter_mut() {
+        let new_idx = *remap[*idx as usize].get_or_insert_with(|| {
+            reordered.push(vertices[*idx as usize].clone());
+            (reordered.len() - 1) as u32
+        });
+        *idx = new_idx;
+    }
+
+    *vertices = reordered;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid_vertices(count: usize) -> Vec<Vertex> {
+        (0..count).map(|i| Vertex { position: (i as f32, (i * i) as f32, 0.0), ..Default::default() }).collect()
+    }
+
+    #[test]
+    fn partial_triangle_is_dropped() {
+        let mut vertices = grid_vertices(4);
+        let mut indices = vec![0, 1, 2, 2, 1, 3, 0, 3];
+        optimize_mesh(&mut vertices, &mut indices, &OptimizeOptions::default());
+        assert_eq!(indices.len(), 6);
+
+        let mut indices = vec![0, 1, 2, 2, 1, 3, 0];
+        optimize_vertex_cache(&mut indices, 4, 16);
+        assert_eq!(indices.len(), 6);
+    }
+
+    #[test]
+    fn out_of_range_indices_leave_the_mesh_alone() {
+        let mut vertices = grid_vertices(3);
+        let mut indices = vec![0, 1, 2, 2, 1, 7];
+        optimize_mesh(&mut vertices, &mut indices, &OptimizeOptions::default());
+        assert_eq!(indices, vec![0, 1, 2, 2, 1, 7]);
+        assert_eq!(vertices.len(), 3);
+
+        optimize_vertex_cache(&mut indices, 3, 16);
+        assert_eq!(indices, vec![0, 1, 2, 2, 1, 7]);
+    }
+
+    //an n by n grid of quads, with the triangles shuffled so the order is as bad for the cache as a loader could make it
+    fn shuffled_grid(n: u32) -> (Vec<Vertex>, Vec<u32>) {
+        let vertices = (0..n * n)
+            .map(|i| Vertex { position: ((i % n) as f32, (i / n) as f32, 0.0), normal: (0.0, 0.0, 1.0), ..Default::default() })
+            .collect();
+        let mut triangles = Vec::new();
+        for y in 0..n - 1 {
+            for x in 0..n - 1 {
+                let i = y * n + x;
+                triangles.push([i, i + 1, i + n]);
+                triangles.push([i + n, i + 1, i + n + 1]);
+            }
+        }
+        let mut seed = 12345u32;
+        for i in (1..triangles.len()).rev() {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            triangles.swap(i, (seed >> 8) as usize % (i + 1));
+        }
+        (vertices, triangles.concat())
+    }
+
+    //every triangle as its three positions, rotated to start at the smallest so winding is kept, then sorted
+    fn triangle_set(vertices: &[Vertex], indices: &[u32]) -> Vec<[(u32, u32, u32); 3]> {
+        let key = |i: u32| {
+            let p = vertices[i as usize].position;
+            (p.0.to_bits(), p.1.to_bits(), p.2.to_bits())
+        };
+        let mut set = indices
+            .chunks_exact(3)
+            .map(|t| {
+                let mut tri = [key(t[0]), key(t[1]), key(t[2])];
+                let first = (0..3).min_by_key(|&c| tri[c]).unwrap();
+                tri.rotate_left(first);
+                tri
+            })
+            .collect::<Vec<_>>();
+        set.sort();
+        set
+    }
+
+    #[test]
+    fn passes_keep_every_triangle() {
+        let (vertices, indices) = shuffled_grid(12);
+        let before = triangle_set(&vertices, &indices);
+
+        let mut reordered = indices.clone();
+        optimize_vertex_cache(&mut reordered, vertices.len(), 16);
+        assert_eq!(triangle_set(&vertices, &reordered), before);
+
+        optimize_overdraw(&mut reordered, &vertices, 16);
+        assert_eq!(triangle_set(&vertices, &reordered), before);
+
+        //fetch reorders the vertices too, comparing positions follows the indices through the remap
+        let mut moved = vertices.clone();
+        optimize_vertex_fetch(&mut moved, &mut reordered);
+        assert_eq!(moved.len(), vertices.len());
+        assert_eq!(triangle_set(&moved, &reordered), before);
+    }
+
+    #[test]
+    fn vertex_cache_lowers_acmr() {
+        let (mut vertices, mut indices) = shuffled_grid(24);
+        let (acmr_before, _) = cache_stats(&indices, vertices.len(), 16);
+
+        let mut reordered = indices.clone();
+        optimize_vertex_cache(&mut reordered, vertices.len(), 16);
+        let (acmr_after, _) = cache_stats(&reordered, vertices.len(), 16);
+        assert!(acmr_after < acmr_before, "ACMR went from {} to {}", acmr_before, acmr_after);
+
+        //and the whole pipeline reports the same gain it made
+        let stats = optimize_mesh(&mut vertices, &mut indices, &OptimizeOptions::default());
+        assert!(stats.acmr_after <= stats.acmr_before);
+        assert_eq!(stats.acmr_after, cache_stats(&indices, vertices.len(), STATS_CACHE_SIZE).0);
+    }
+}
@@ -1,23 +1,123 @@
 #version 450
+#extension GL_GOOGLE_include_directive : require
 
 const float PI = 3.141592;
 const float Epsilon = 0.00001;
 
 const vec3 Fdielectric = vec3(0.04);
 
+// the tonemapper specialization constant and its curves, sky_frag.glsl uses them too
+#include "tonemap.glsl"
+
 layout(location = 0) in vec3 pos_in;
 layout(location = 1) in vec2 uv_in;
 layout(location = 2) in vec3 norm_in;
+layout(location = 3) in vec4 tangent_in;
 layout(location = 4) in vec3 eye_pos_in;
-layout(location = 5) in vec3 light_dir_in;
+// only used for tessellation, see pbr_vert.glsl
+layout(location = 5) in vec3 displace_in;
 
 layout(set = 0, binding = 1) uniform sampler2D albedo_t;
-layout(set = 0, binding = 2) uniform sampler2D roughness_t;
-layout(set = 0, binding = 3) uniform sampler2D metalness_t;
-layout(set = 0, binding = 4) uniform sampler2D normalmap_t;
+layout(set = 0, binding = 2) uniform sampler2D normalmap_t;
+layout(set = 0, binding = 3) uniform sampler2D emission_t;
+// the single value parameters (ao, roughness, metalness, opacity) all come out of these,
+// only the first data_count are different textures, the rest just repeat the first one
+layout(set = 0, binding = 4) uniform sampler2D data_t[4];
+// white is high, only bound to something real when params.height_scale isn't 0
+layout(set = 0, binding = 5) uniform sampler2D height_t;
+
+// image based lighting, see ibl.rs for how these get made
+// the irradiance map is the diffuse light for a normal, the prefiltered map has the reflection for a roughness in each mip
+// and the LUT is the BRDF's scale (r) and bias (g) for F0 by n.v (x) and roughness (y)
+layout(set = 0, binding = 6) uniform samplerCube irradiance_t;
+layout(set = 0, binding = 7) uniform samplerCube prefiltered_t;
+layout(set = 0, binding = 8) uniform sampler2D brdf_lut_t;
+
+// the same for every object in a frame, rotation turns the environment around +Y
+layout(set = 0, binding = 9) uniform Environment {
+    float intensity;
+    float max_lod;
+    float rotation_cos;
+    float rotation_sin;
+} env;
+
+// every light in the scene, see Light in renderer.rs, kind 0 = directional, 1 = point, 2 = spot
+// direction is the way the light travels, range 0 means it never fades out completely
+// intensity is lux for directional lights and candela for the others, the cones are cosines of the spot's half angles
+// the first one is the main light, the one parallax self shadowing marches towards
+// shadow_layer is where the light's first cascade is in shadow_t, or its first layer in local_shadow_t for point and spot lights,
+// -1 if it doesn't cast shadows
+struct LightData {
+    vec3 position;
+    float range;
+    vec3 direction;
+    float intensity;
+    vec3 color;
+    uint kind;
+    float cone_inner;
+    float cone_outer;
+    int shadow_layer;
+};
+
+layout(set = 0, binding = 10) readonly buffer Lights {
+    LightData lights[];
+};
+
+// cascaded shadow maps, see shadows.rs, every cascade of every shadowed light is a layer
+layout(set = 0, binding = 11) uniform sampler2DArrayShadow shadow_t;
+
+// texel_world is the size of one texel in world units and depth_per_world turns world units in to the map's depth
+struct ShadowCascade {
+    mat4 view_proj;
+    float texel_world;
+    float depth_per_world;
+};
+
+// one spot light or one face of a point light's cube, texel_scale is a texel's size in world units one unit from the light
+struct LocalShadow {
+    mat4 view_proj;
+    float texel_scale;
+};
+
+// 16 is MAX_SHADOW_LIGHTS * MAX_CASCADES and 24 is MAX_LOCAL_SHADOW_LAYERS, splits is where each cascade ends along view_forward
+// blend is how much of a cascade fades in to the next one, the rest are in ShadowSettings in shadows.rs
+layout(set = 0, binding = 12) uniform Shadows {
+    ShadowCascade cascades[16];
+    LocalShadow locals[24];
+    vec4 splits;
+    vec4 view_forward;
+    uint cascade_count;
+    float blend;
+    float softness;
+    float depth_bias;
+    float normal_bias;
+} shadows;
+
+// point and spot light shadows, a layer per spot and six per point light, see local_views in shadows.rs
+layout(set = 0, binding = 13) uniform sampler2DArrayShadow local_shadow_t;
+
+// where each single value parameter lives, x = ao, y = roughness, z = metalness, w = opacity
+// slots is which data_t to read and channels is which channel of it, see TextureChannel in renderer.rs
+// normal_y is -1 for DirectX normal maps, normal_encoding is 0 for RGB, 1 for unsigned RG and 2 for signed RG
+// height_scale is 0 when there's no height map, see Parallax in renderer.rs for the rest
+// shadow_offset is how far tessellation can have pushed the surface in, the shadow maps only have the undisplaced mesh
+layout(push_constant) uniform MaterialParams {
+    uvec4 slots;
+    uvec4 channels;
+    uint data_count;
+    float normal_y;
+    float normal_strength;
+    uint normal_encoding;
+    float height_scale;
+    uint parallax_min_steps;
+    uint parallax_max_steps;
+    uint parallax_shadow;
+    float shadow_offset;
+} params;
 
 layout(location = 0) out vec4 f_color;
 
+
 mat3 cotangentFrame(vec3 N, vec3 p, vec2 uv) {
   // get edge vectors of the pixel triangle
   vec3 dp1 = dFdx(p);
@@ -37,9 +137,94 @@ mat3 cotangentFrame(vec3 N, vec3 p, vec2 uv) {
 }
 
 
-vec3 perturb(vec3 map, vec3 N, vec3 V, vec2 texcoord) {
-  mat3 TBN = cotangentFrame(N, -V, texcoord);
-  return normalize(TBN * map);
+// the tangent frame the normal map and height map live in, built out of the MikkTSpace tangent the same way
+// the baker did, falling back to the screen space frame if the mesh didn't get tangents
+mat3 tangentFrame(vec3 N, vec3 V, vec2 texcoord) {
+  if (dot(tangent_in.xyz, tangent_in.xyz) < Epsilon) {
+    return cotangentFrame(N, V, texcoord);
+  }
+  // no normalizing or orthogonalizing the interpolated tangent, MikkTSpace bakers don't either
+  vec3 T = tangent_in.xyz;
+  vec3 B = tangent_in.w * cross(N, T);
+  return mat3(T, B, N);
+}
+
+// world space direction in to the tangent frame, the frame isn't always orthonormal so no transpose
+vec3 toTangent(mat3 TBN, vec3 v) {
+  return normalize(vec3(dot(v, TBN[0]), dot(v, TBN[1]), dot(v, TBN[2])));
+}
+
+// how far below the top of the height map a point is, 0 is the top and 1 the bottom
+// the loops below aren't uniform control flow so the mip level comes from the unshifted uv's derivatives
+float depthAt(vec2 uv, vec2 dx, vec2 dy) {
+  return 1.0 - textureGrad(height_t, uv, dx, dy).r;
+}
+
+// parallax occlusion mapping, steps the view ray down through layers of the height map until it ends up
+// under the surface, then interpolates between the last two steps. returns the uv and depth it hit at
+vec3 parallaxMap(vec2 uv, vec3 V, vec2 dx, vec2 dy) {
+  // straight on needs fewer layers than grazing angles
+  float steps = mix(float(params.parallax_max_steps), float(params.parallax_min_steps), abs(V.z));
+  float layer = 1.0 / steps;
+  // the uv shift per layer, z is clamped so grazing angles don't shoot off to infinity
+  vec2 delta = V.xy / max(V.z, 0.05) * params.height_scale * layer;
+
+  float ray = 0.0;
+  float depth = depthAt(uv, dx, dy);
+  float prev_depth = depth;
+  for (uint i = 0; i < params.parallax_max_steps && ray < depth; i++) {
+    uv -= delta;
+    prev_depth = depth;
+    depth = depthAt(uv, dx, dy);
+    ray += layer;
+  }
+
+  // the surface crossed the ray somewhere between this step and the last
+  float after = depth - ray;
+  float before = prev_depth - (ray - layer);
+  float t = after / min(after - before, -Epsilon);
+  t = clamp(t, 0.0, 1.0);
+  return vec3(uv + delta * t, ray - layer * t);
+}
+
+// marches from where the view ray hit towards the light, anything in the height map sticking up above that
+// path shades the point, closer and taller bumps shade it more. 1 is fully lit
+float parallaxShadow(vec2 uv, float depth, vec3 L, vec2 dx, vec2 dy) {
+  if (L.z <= 0.0 || depth <= 0.0) {
+    return 1.0;
+  }
+  float steps = mix(float(params.parallax_max_steps), float(params.parallax_min_steps), L.z);
+  float layer = depth / steps;
+  vec2 delta = L.xy / max(L.z, 0.05) * params.height_scale * layer;
+
+  float occlusion = 0.0;
+  float ray = depth - layer;
+  for (uint i = 1; i < params.parallax_max_steps && ray > 0.0; i++) {
+    uv += delta;
+    float under = ray - depthAt(uv, dx, dy);
+    occlusion = max(occlusion, under * (1.0 - float(i) / steps));
+    ray -= layer;
+  }
+  // a bump a quarter of the map's full depth above the path is enough to block the light completely
+  return 1.0 - clamp(occlusion * 4.0, 0.0, 1.0);
+}
+
+// tangent space normal out of the map, two channel maps only store X and Y since Z is always the positive
+// one that makes it unit length. strength scales the tilt and the vector gets normalized later
+vec3 readNormalMap(vec2 uv, vec2 dx, vec2 dy) {
+    vec3 n;
+    if (params.normal_encoding == 0) {
+        n = textureGrad(normalmap_t, uv, dx, dy).rgb * 2.0 - 1.0;
+    } else {
+        vec2 xy = textureGrad(normalmap_t, uv, dx, dy).rg;
+        if (params.normal_encoding == 1) {
+            xy = xy * 2.0 - 1.0;
+        }
+        n = vec3(xy, sqrt(max(1.0 - dot(xy, xy), 0.0)));
+    }
+    n.y *= params.normal_y;
+    n.xy *= params.normal_strength;
+    return n;
 }
 
 float DistributionGGX(vec3 N, vec3 H, float roughness)
@@ -82,16 +267,186 @@ vec3 fresnelSchlick(float cosTheta, vec3 F0)
     return F0 + (1.0 - F0) * pow(max(1.0 - cosTheta, 0.0), 5.0);
 }
 
-void main() {
-    vec3 albedo = texture(albedo_t, uv_in).rgb;
-    float metalness = texture(metalness_t, uv_in).r;
-    float roughness = texture(roughness_t, uv_in).r;
-    vec3 nmap = texture(normalmap_t, uv_in).rgb * 2.0 - 1.0;
+// the same as fresnelSchlick but rough surfaces reflect less at grazing angles, for light coming from everywhere at once
+vec3 fresnelSchlickRoughness(float cosTheta, vec3 F0, float roughness)
+{
+    return F0 + (max(vec3(1.0 - roughness), F0) - F0) * pow(max(1.0 - cosTheta, 0.0), 5.0);
+}
 
-    vec3 N = normalize(norm_in);
-    vec3 V = normalize(eye_pos_in - pos_in);
+// how much light gets to pos from one light and the direction it comes from
+vec3 lightIncoming(LightData light, vec3 pos, out vec3 L) {
+    if (light.kind == 0) {
+        L = -light.direction;
+        return light.color * light.intensity;
+    }
+    vec3 to_light = light.position - pos;
+    float dist2 = max(dot(to_light, to_light), 0.0001);
+    L = to_light * inversesqrt(dist2);
+    // inverse square, windowed so it reaches exactly 0 at the range (the falloff UE4 and glTF use)
+    float atten = 1.0 / dist2;
+    if (light.range > 0.0) {
+        float r = dist2 / (light.range * light.range);
+        float window = clamp(1.0 - r * r, 0.0, 1.0);
+        atten *= window * window;
+    }
+    if (light.kind == 2) {
+        float cos_angle = dot(-L, light.direction);
+        float cone = clamp((cos_angle - light.cone_outer) / max(light.cone_inner - light.cone_outer, 0.0001), 0.0, 1.0);
+        atten *= cone * cone;
+    }
+    return light.color * light.intensity * atten;
+}
 
-    //vec3 N = perturb(nmap, normalize(norm_in), - V, uv_in);
+// PCF taps, a Poisson disk that gets spun around per pixel so banding turns in to fine noise
+const vec2 poisson[16] = vec2[](
+    vec2(-0.94201624, -0.39906216), vec2(0.94558609, -0.76890725), vec2(-0.09418410, -0.92938870), vec2(0.34495938, 0.29387760),
+    vec2(-0.91588581, 0.45771432), vec2(-0.81544232, -0.87912464), vec2(-0.38277543, 0.27676845), vec2(0.97484398, 0.75648379),
+    vec2(0.44323325, -0.97511554), vec2(0.53742981, -0.47373420), vec2(-0.26496911, -0.41893023), vec2(0.79197514, 0.19090188),
+    vec2(-0.24188840, 0.99706507), vec2(-0.81409955, 0.91437590), vec2(0.19984126, 0.78641367), vec2(0.14383161, -0.14100790)
+);
+
+// Jimenez's interleaved gradient noise, 0 to 1 and different for every pixel
+float interleavedGradientNoise(vec2 p) {
+    return fract(52.9829189 * fract(dot(p, vec2(0.06711056, 0.00583715))));
+}
+
+// a new rotation of the PCF taps for every pixel
+mat2 pcfSpin() {
+    float angle = 2.0 * PI * interleavedGradientNoise(gl_FragCoord.xy);
+    return mat2(cos(angle), sin(angle), -sin(angle), cos(angle));
+}
+
+// the PCF filter both kinds of map share, how much of the disk around uv is closer to the light than reference
+// zero gradients since this runs inside branches, the maps only have the one mip anyway
+float filterShadow(sampler2DArrayShadow map, vec2 uv, int layer, float reference, mat2 spin) {
+    vec2 texel = 1.0 / vec2(textureSize(map, 0).xy);
+    float lit = 0.0;
+    for (int i = 0; i < 16; i++) {
+        vec2 offset = spin * poisson[i] * shadows.softness * texel;
+        lit += textureGrad(map, vec4(uv + offset, float(layer), reference), vec2(0.0), vec2(0.0));
+    }
+    return lit / 16.0;
+}
+
+// how lit pos is in one layer of the shadow maps, 1 is fully lit
+float sampleCascade(int layer, vec3 pos, vec3 Ng, vec3 L, mat2 spin) {
+    ShadowCascade cascade = shadows.cascades[layer];
+    float cos_theta = clamp(dot(Ng, L), 0.0, 1.0);
+    // off the surface along its normal, more the further it's turned from the light
+    vec3 offset_pos = pos + Ng * shadows.normal_bias * cascade.texel_world * (1.0 - cos_theta);
+    vec4 p = cascade.view_proj * vec4(offset_pos, 1.0);
+    vec3 coord = p.xyz / p.w;
+    vec2 uv = coord.xy * 0.5 + 0.5;
+    // slope scaled, a texel covers more depth the more the surface is turned away
+    float slope = clamp(sqrt(1.0 - cos_theta * cos_theta) / max(cos_theta, 0.05), 0.0, 10.0);
+    float reference = coord.z - shadows.depth_bias * cascade.texel_world * (1.0 + slope) * cascade.depth_per_world;
+
+    return filterShadow(shadow_t, uv, layer, reference, spin);
+}
+
+// picks the cascade pos is in and fades to the next one over the end of it, past the last one there's no shadow
+float shadowFactor(int first_layer, vec3 pos, vec3 Ng, vec3 L) {
+    float depth = dot(pos - eye_pos_in, shadows.view_forward.xyz);
+    int count = int(shadows.cascade_count);
+    int c = 0;
+    while (c < count && depth > shadows.splits[c]) {
+        c++;
+    }
+    if (c >= count) {
+        return 1.0;
+    }
+
+    mat2 spin = pcfSpin();
+    float lit = sampleCascade(first_layer + c, pos, Ng, L, spin);
+
+    float start = c > 0 ? shadows.splits[c - 1] : 0.0;
+    float band = (shadows.splits[c] - start) * shadows.blend;
+    float t = band > 0.0 ? clamp((depth - (shadows.splits[c] - band)) / band, 0.0, 1.0) : 0.0;
+    if (t > 0.0) {
+        float next = c + 1 < count ? sampleCascade(first_layer + c + 1, pos, Ng, L, spin) : 1.0;
+        lit = mix(lit, next, t);
+    }
+    return lit;
+}
+
+// how lit pos is by a point or spot light, point lights pick the cube face pos is behind
+// the biases are in texels like the cascades but a texel gets bigger the further it is from the light,
+// the depth bias moves pos straight at the light which doesn't change where it lands in the map, just its depth
+float localShadowFactor(LightData light, vec3 pos, vec3 Ng, vec3 L) {
+    int layer = light.shadow_layer;
+    vec3 d = pos - light.position;
+    if (light.kind == 1) {
+        vec3 a = abs(d);
+        if (a.x >= a.y && a.x >= a.z) {
+            layer += d.x > 0.0 ? 0 : 1;
+        } else if (a.y >= a.z) {
+            layer += d.y > 0.0 ? 2 : 3;
+        } else {
+            layer += d.z > 0.0 ? 4 : 5;
+        }
+    }
+    LocalShadow local = shadows.locals[layer];
+    float texel = length(d) * local.texel_scale;
+    float cos_theta = clamp(dot(Ng, L), 0.0, 1.0);
+    float slope = clamp(sqrt(1.0 - cos_theta * cos_theta) / max(cos_theta, 0.05), 0.0, 10.0);
+    vec3 offset_pos = pos + Ng * shadows.normal_bias * texel * (1.0 - cos_theta);
+    offset_pos += L * shadows.depth_bias * texel * (1.0 + slope);
+    vec4 p = local.view_proj * vec4(offset_pos, 1.0);
+    vec3 coord = p.xyz / p.w;
+    return filterShadow(local_shadow_t, coord.xy * 0.5 + 0.5, layer, coord.z, pcfSpin());
+}
+
+// world space direction to where it is in the environment maps
+vec3 envDir(vec3 d) {
+    return vec3(env.rotation_cos * d.x - env.rotation_sin * d.z, d.y, env.rotation_sin * d.x + env.rotation_cos * d.z);
+}
+
+void main() {
+    vec3 V = normalize(eye_pos_in - pos_in);
+    vec3 main_L;
+    lightIncoming(lights[0], pos_in, main_L);
+    vec3 N = normalize(norm_in);
+    // the normal without the normal map, what the shadow biases push along
+    vec3 Ng = N;
+    // back out to where the shadow pass drew the surface, otherwise tessellated surfaces shadow themselves
+    vec3 shadow_pos = pos_in + Ng * params.shadow_offset;
+    mat3 TBN = tangentFrame(N, V, uv_in);
+
+    // every texture gets read at the uv the parallax lands on, the derivatives have to come from before any branching
+    vec2 uv_dx = dFdx(uv_in);
+    vec2 uv_dy = dFdy(uv_in);
+    vec2 uv = uv_in;
+    float parallax_shadow = 1.0;
+    if (params.height_scale > 0.0) {
+        vec3 hit = parallaxMap(uv_in, toTangent(TBN, V), uv_dx, uv_dy);
+        uv = hit.xy;
+        if (params.parallax_shadow != 0) {
+            parallax_shadow = parallaxShadow(uv, hit.z, toTangent(TBN, main_L), uv_dx, uv_dy);
+        }
+    }
+
+    // each data texture gets sampled once no matter how many parameters are packed in to it
+    // constant indices only, indexing a sampler array with a variable needs a device feature
+    vec4 data[4];
+    data[0] = textureGrad(data_t[0], uv, uv_dx, uv_dy);
+    data[1] = params.data_count > 1 ? textureGrad(data_t[1], uv, uv_dx, uv_dy) : data[0];
+    data[2] = params.data_count > 2 ? textureGrad(data_t[2], uv, uv_dx, uv_dy) : data[0];
+    data[3] = params.data_count > 3 ? textureGrad(data_t[3], uv, uv_dx, uv_dy) : data[0];
+
+    // cutout transparency, there's no blending so anything mostly see-through just isn't drawn
+    float opacity = data[params.slots.w][params.channels.w];
+    if (opacity < 0.5) {
+        discard;
+    }
+
+    vec3 albedo = textureGrad(albedo_t, uv, uv_dx, uv_dy).rgb;
+    float ao = data[params.slots.x][params.channels.x];
+    vec3 emission = textureGrad(emission_t, uv, uv_dx, uv_dy).rgb;
+    float metalness = data[params.slots.z][params.channels.z];
+    float roughness = data[params.slots.y][params.channels.y];
+    vec3 nmap = readNormalMap(uv, uv_dx, uv_dy);
+
+    N = normalize(TBN * nmap);
 
     vec3 F0 = vec3(0.04);
 
@@ -99,37 +454,50 @@ void main() {
 
     vec3 Lo = vec3(0.0, 0.0, 0.0);
 
-    vec3 L = normalize(light_dir_in);
+    for (int i = 0; i < lights.length(); i++) {
+        vec3 L;
+        vec3 radiance = lightIncoming(lights[i], pos_in, L);
+        if (i == 0) {
+            radiance *= parallax_shadow;
+        }
+        if (lights[i].shadow_layer >= 0) {
+            radiance *= lights[i].kind == 0 ? shadowFactor(lights[i].shadow_layer, shadow_pos, Ng, L) : localShadowFactor(lights[i], shadow_pos, Ng, L);
+        }
 
-    vec3 H = normalize(V + L);
+        vec3 H = normalize(V + L);
 
-    float dist = length(light_dir_in);
+        float NDF = DistributionGGX(N, H, roughness);
+        float G = GeometrySmith(N, V, L, roughness);
+        vec3 F = fresnelSchlick(clamp(dot(H,V), 0.0, 1.0), F0);
 
-    float atten = 1.0 / (dist * dist);
+        vec3 nom = NDF * G * F;
+        float denom = 4 * max(dot(N, V), 0.0) * max(dot(N, L), 0.0);
 
-    vec3 radiance = vec3(1.0, 1.0, 1.0) * atten;
+        vec3 specular = nom / max(denom, 0.001);
 
-    float NDF = DistributionGGX(N, H, roughness);
-    float G = GeometrySmith(N, V, L, roughness);
-    vec3 F = fresnelSchlick(clamp(dot(H,V), 0.0, 1.0), F0);
+        vec3 kS = F;
+        vec3 kD = vec3(1.0) - kS;
+        kD *= 1.0 - metalness;
+        float NdotL = max(dot(N, L), 0.0);
 
-    vec3 nom = NDF * G * F;
-    float denom = 4 * max(dot(N, V), 0.0) * max(dot(N, L), 0.0);
+        Lo += (kD * albedo / PI + specular) * radiance * NdotL;
+    }
 
-    vec3 specular = nom / max(denom, 0.001);
-
-    vec3 kS = F;
-    vec3 kD = vec3(1.0) - kS;
-    kD *= 1.0 - metalness;
-    float NdotL = max(dot(N, L), 0.0);
-
-    Lo += (kD * albedo / PI + specular) * radiance * NdotL;
-    vec3 ambient = vec3(0.03) * albedo;
+    // image based lighting with the split sum, diffuse comes out of the irradiance map and specular is the
+    // prefiltered reflection times the BRDF's response, which the LUT has for every angle and roughness
+    float NdotV = max(dot(N, V), 0.0);
+    vec3 F_env = fresnelSchlickRoughness(NdotV, F0, roughness);
+    vec3 kD_env = (1.0 - F_env) * (1.0 - metalness);
+    vec3 irradiance = texture(irradiance_t, envDir(N)).rgb;
+    vec3 prefiltered = textureLod(prefiltered_t, envDir(reflect(-V, N)), roughness * env.max_lod).rgb;
+    vec2 brdf = texture(brdf_lut_t, vec2(NdotV, roughness)).rg;
+    // occlusion only darkens the ambient term, direct light has its own visibility
+    vec3 ambient = (kD_env * irradiance * albedo + prefiltered * (F_env * brdf.x + brdf.y)) * env.intensity * ao;
 
-    vec3 color = ambient + Lo;
+    vec3 color = ambient + Lo + emission;
 
     // HDR tonemapping
-    color = color / (color + vec3(1.0));
+    color = tonemap(color);
     // gamma correct
     color = pow(color, vec3(1.0/2.2)); 
 
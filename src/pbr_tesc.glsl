@@ -0,0 +1,71 @@
+#version 450
+
+// only in the pipeline when the renderer is set to Displacement::Tessellation
+// splits each triangle up so its edges come out about tess_edge pixels long on screen
+
+layout(vertices = 3) out;
+
+layout(location = 0) in vec3 pos_in[];
+layout(location = 1) in vec2 uv_in[];
+layout(location = 2) in vec3 norm_in[];
+layout(location = 3) in vec4 tangent_in[];
+layout(location = 4) in vec3 eye_pos_in[];
+layout(location = 5) in vec3 displace_in[];
+
+layout(location = 0) out vec3 pos_out[];
+layout(location = 1) out vec2 uv_out[];
+layout(location = 2) out vec3 norm_out[];
+layout(location = 3) out vec4 tangent_out[];
+layout(location = 4) out vec3 eye_pos_out[];
+layout(location = 5) out vec3 displace_out[];
+
+// has to match the one in pbr_vert.glsl
+layout(set = 0, binding = 0) uniform Data {
+    mat4 mvp;
+    vec3 camloc;
+    mat4 rotation;
+    mat4 view_proj;
+    vec2 viewport;
+    float height_scale;
+    float tess_edge;
+} uniforms;
+
+// clip space to pixels, w is clamped so edges that cross behind the camera don't blow up
+vec2 toScreen(vec4 clip) {
+    return clip.xy / max(clip.w, 0.0001) * 0.5 * uniforms.viewport;
+}
+
+// both triangles sharing an edge work this out from the same two points, so they always agree and there are no cracks
+float edgeLevel(vec4 a, vec4 b) {
+    float pixels = distance(toScreen(a), toScreen(b));
+    return clamp(pixels / uniforms.tess_edge, 1.0, 64.0);
+}
+
+void main() {
+    pos_out[gl_InvocationID] = pos_in[gl_InvocationID];
+    uv_out[gl_InvocationID] = uv_in[gl_InvocationID];
+    norm_out[gl_InvocationID] = norm_in[gl_InvocationID];
+    tangent_out[gl_InvocationID] = tangent_in[gl_InvocationID];
+    eye_pos_out[gl_InvocationID] = eye_pos_in[gl_InvocationID];
+    displace_out[gl_InvocationID] = displace_in[gl_InvocationID];
+    gl_out[gl_InvocationID].gl_Position = gl_in[gl_InvocationID].gl_Position;
+
+    if (gl_InvocationID == 0) {
+        // nothing to displace, no point splitting it
+        if (uniforms.height_scale <= 0.0) {
+            gl_TessLevelOuter[0] = 1.0;
+            gl_TessLevelOuter[1] = 1.0;
+            gl_TessLevelOuter[2] = 1.0;
+            gl_TessLevelInner[0] = 1.0;
+            return;
+        }
+        // outer level i is the edge across from vertex i
+        vec4 p0 = gl_in[0].gl_Position;
+        vec4 p1 = gl_in[1].gl_Position;
+        vec4 p2 = gl_in[2].gl_Position;
+        gl_TessLevelOuter[0] = edgeLevel(p1, p2);
+        gl_TessLevelOuter[1] = edgeLevel(p2, p0);
+        gl_TessLevelOuter[2] = edgeLevel(p0, p1);
+        gl_TessLevelInner[0] = max(max(gl_TessLevelOuter[0], gl_TessLevelOuter[1]), gl_TessLevelOuter[2]);
+    }
+}
@@ -0,0 +1,58 @@
+#version 450
+
+// places the vertices the control shader made and pushes them along the normal by the height map,
+// the same way parallax reads it: white is the surface and black sinks height_scale below it
+
+layout(triangles, fractional_odd_spacing, ccw) in;
+
+layout(location = 0) in vec3 pos_in[];
+layout(location = 1) in vec2 uv_in[];
+layout(location = 2) in vec3 norm_in[];
+layout(location = 3) in vec4 tangent_in[];
+layout(location = 4) in vec3 eye_pos_in[];
+layout(location = 5) in vec3 displace_in[];
+
+layout(location = 0) out vec3 pos_out;
+layout(location = 1) out vec2 uv_out;
+layout(location = 2) out vec3 norm_out;
+layout(location = 3) out vec4 tangent_out;
+layout(location = 4) out vec3 eye_pos_out;
+layout(location = 5) out vec3 displace_out;
+
+// has to match the one in pbr_vert.glsl
+layout(set = 0, binding = 0) uniform Data {
+    mat4 mvp;
+    vec3 camloc;
+    mat4 rotation;
+    mat4 view_proj;
+    vec2 viewport;
+    float height_scale;
+    float tess_edge;
+} uniforms;
+
+layout(set = 0, binding = 5) uniform sampler2D height_t;
+
+#define BLEND(a) (gl_TessCoord.x * a[0] + gl_TessCoord.y * a[1] + gl_TessCoord.z * a[2])
+
+void main() {
+    vec2 uv = BLEND(uv_in);
+    vec3 N = normalize(BLEND(norm_in));
+    // pushed along the shared normal rather than N, the copies of a vertex on a hard edge or UV seam have different Ns
+    // and would pull apart, leaving a crack along the edge
+    vec3 D = normalize(BLEND(displace_in));
+
+    // no derivatives out here, so the mip comes from how many height map texels end up between two new vertices
+    float uv_edge = max(max(distance(uv_in[0], uv_in[1]), distance(uv_in[1], uv_in[2])), distance(uv_in[2], uv_in[0]));
+    float texels = uv_edge * float(textureSize(height_t, 0).x) / gl_TessLevelInner[0];
+    float depth = 1.0 - textureLod(height_t, uv, log2(max(texels, 1.0))).r;
+    vec3 pos = BLEND(pos_in) - D * depth * uniforms.height_scale;
+
+    pos_out = pos;
+    uv_out = uv;
+    norm_out = N;
+    // the bitangent sign is the same across a triangle
+    tangent_out = vec4(BLEND(tangent_in).xyz, tangent_in[0].w);
+    eye_pos_out = eye_pos_in[0];
+    displace_out = D;
+    gl_Position = uniforms.view_proj * vec4(pos, 1.0);
+}
@@ -3,12 +3,16 @@
 layout(location = 0) in vec3 position;
 layout(location = 1) in vec3 normal;
 layout(location = 2) in vec2 uv;
+layout(location = 3) in vec4 tangent;
+layout(location = 4) in vec3 displace;
 
 layout(location = 0) out vec3 pos_out;
 layout(location = 1) out vec2 uv_out;
 layout(location = 2) out vec3 norm_out;
+layout(location = 3) out vec4 tangent_out;
 layout(location = 4) out vec3 eye_pos_out;
-layout(location = 5) out vec3 light_dir_out;
+// only the tessellation shaders use it, the fragment shader has to take it anyway so the stages line up
+layout(location = 5) out vec3 displace_out;
 
 // layout(set = 0, binding = 0) uniform Data {
 //     mat4 world;
@@ -22,8 +26,12 @@ layout(location = 5) out vec3 light_dir_out;
 layout(set = 0, binding = 0) uniform Data {
     mat4 mvp;
     vec3 camloc;
-    vec3 lightdir;
     mat4 rotation;
+    // the rest are only read by the tessellation shaders, see pbr_tesc.glsl and pbr_tese.glsl
+    mat4 view_proj;
+    vec2 viewport;
+    float height_scale;
+    float tess_edge;
 } uniforms;
 
 void main() {
@@ -40,8 +48,11 @@ void main() {
     gl_Position = uniforms.mvp * pos;
 
     pos_out = vec3((uniforms.rotation * pos).xyz);
-    norm_out = vec3((uniforms.rotation * vec4(normal, 1.0)).xyz);
+    //w of 0 so a translated model doesn't drag its normals along with it
+    norm_out = vec3((uniforms.rotation * vec4(normal, 0.0)).xyz);
+    //w is the bitangent sign, it doesn't get rotated
+    tangent_out = vec4((uniforms.rotation * vec4(tangent.xyz, 0.0)).xyz, tangent.w);
+    displace_out = vec3((uniforms.rotation * vec4(displace, 0.0)).xyz);
     eye_pos_out = uniforms.camloc;
-    light_dir_out = uniforms.lightdir;
     uv_out = uv;
 }
\ No newline at end of file
@@ -0,0 +1,275 @@
+use std::{collections::HashMap, f32::consts::PI};
+
+use crate::mesh::Mesh;
+use crate::vertex::Vertex;
+use crate::tangents::generate_tangents;
+
+//Procedural shapes so materials can be previewed without any model files
+//everything is centered on the origin, has outward facing normals, UVs in [0, 1], and MikkTSpace tangents
+
+fn vert(position: (f32, f32, f32), normal: (f32, f32, f32), uv: (f32, f32)) -> Vertex {
+    Vertex {
+        position,
+        normal,
+        uv,
+        ..Default::default()
+    }
+}
+
+//all the shapes build their vertices and indices then come through here to get tangents and a name
+fn finish(name: &str, mut vertices: Vec<Vertex>, mut indices: Vec<u32>) -> Mesh {
+    generate_tangents(&mut vertices, &mut indices);
+    Mesh {
+        name: name.to_string(),
+        vertices,
+        indices,
+        material_id: None,
+    }
+}
+
+//indices for a grid of (cols + 1) x (rows + 1) vertices laid out row by row, two triangles per cell
+fn grid_indices(cols: u32, rows: u32, base: u32, indices: &mut Vec<u32>) {
+    let stride = cols + 1;
+    for r in 0..rows {
+        for c in 0..cols {
+            let i0 = base + r * stride + c;
+            let i1 = i0 + 1;
+            let i2 = i0 + stride;
+            let i3 = i2 + 1;
+            indices.extend_from_slice(&[i0, i2, i1, i1, i2, i3]);
+        }
+    }
+}
+
+//Globe style sphere, segments go around the equator and rings go pole to pole
+pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> Mesh {
+    let segments = segments.max(3);
+    let rings = rings.max(2);
+
+    let mut vertices = Vec::new();
+    //the seam column is doubled up so the UVs can run all the way from 0 to 1
+    for r in 0..=rings {
+        let v = r as f32 / rings as f32;
+        let phi = v * PI;
+        for s in 0..=segments {
+            let u = s as f32 / segments as f32;
+            let theta = u * 2.0 * PI;
+            //going around clockwise from above keeps the triangles facing outwards with u running left to right
+            let n = (phi.sin() * theta.cos(), phi.cos(), -phi.sin() * theta.sin());
+            vertices.push(vert((n.0 * radius, n.1 * radius, n.2 * radius), n, (u, v)));
+        }
+    }
+
+    let mut indices = Vec::new();
+    grid_indices(segments, rings, 0, &mut indices);
+
+    finish("uv_sphere", vertices, indices)
+}
+
+//Sphere made by subdividing an icosahedron, the triangles come out much more even than a UV sphere
+pub fn ico_sphere(radius: f32, subdivisions: u32) -> Mesh {
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut points: Vec<[f32; 3]> = vec![
+        [-1.0, t, 0.0], [1.0, t, 0.0], [-1.0, -t, 0.0], [1.0, -t, 0.0],
+        [0.0, -1.0, t], [0.0, 1.0, t], [0.0, -1.0, -t], [0.0, 1.0, -t],
+        [t, 0.0, -1.0], [t, 0.0, 1.0], [-t, 0.0, -1.0], [-t, 0.0, 1.0],
+    ];
+    let mut tris: Vec<[u32; 3]> = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    let normalize = |p: [f32; 3]| {
+        let len = (p[0] * p[0] + p[1] * p[1] + p[2] * p[2]).sqrt();
+        [p[0] / len, p[1] / len, p[2] / len]
+    };
+    for p in points.iter_mut() {
+        *p = normalize(*p);
+    }
+
+    //split every triangle in to four, sharing the new midpoints between neighbours
+    for _ in 0..subdivisions {
+        let mut midpoints = HashMap::<(u32, u32), u32>::new();
+        let mut midpoint = |a: u32, b: u32, points: &mut Vec<[f32; 3]>| {
+            let key = (a.min(b), a.max(b));
+            *midpoints.entry(key).or_insert_with(|| {
+                let (pa, pb) = (points[a as usize], points[b as usize]);
+                points.push(normalize([(pa[0] + pb[0]) / 2.0, (pa[1] + pb[1]) / 2.0, (pa[2] + pb[2]) / 2.0]));
+                (points.len() - 1) as u32
+            })
+        };
+
+        let mut next = Vec::with_capacity(tris.len() * 4);
+        for [a, b, c] in tris {
+            let ab = midpoint(a, b, &mut points);
+            let bc = midpoint(b, c, &mut points);
+            let ca = midpoint(c, a, &mut points);
+            next.extend_from_slice(&[[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]);
+        }
+        tris = next;
+    }
+
+    let spherical_uv = |p: [f32; 3]| (0.5 + p[2].atan2(p[0]) / (2.0 * PI), 0.5 - p[1].asin() / PI);
+    let mut vertices = points
+        .iter()
+        .map(|p| vert((p[0] * radius, p[1] * radius, p[2] * radius), (p[0], p[1], p[2]), spherical_uv(*p)))
+        .collect::<Vec<_>>();
+
+    //triangles that straddle the seam would smear the whole texture across themselves,
+    //so the vertices on the low side of the seam get a copy shifted past u = 1
+    let mut wrapped = HashMap::<u32, u32>::new();
+    let mut indices = Vec::with_capacity(tris.len() * 3);
+    for tri in tris {
+        let us = tri.map(|i| vertices[i as usize].uv.0);
+        let crosses_seam = us.iter().cloned().fold(f32::MIN, f32::max) - us.iter().cloned().fold(f32::MAX, f32::min) > 0.5;
+        for i in tri {
+            if crosses_seam && vertices[i as usize].uv.0 < 0.5 {
+                let copy = *wrapped.entry(i).or_insert_with(|| {
+                    let mut v = vertices[i as usize].clone();
+                    v.uv.0 += 1.0;
+                    vertices.push(v);
+                    (vertices.len() - 1) as u32
+                });
+                indices.push(copy);
+            } else {
+                indices.push(i);
+            }
+        }
+    }
+
+    finish("ico_sphere", vertices, indices)
+}
+
+//Axis aligned cube, each face gets its own four vertices so the edges stay hard and every face shows the whole texture
+pub fn cube(size: f32) -> Mesh {
+    let h = size / 2.0;
+
+    //normal, then the directions u and v run along the face
+    let faces: [([f32; 3], [f32; 3], [f32; 3]); 6] = [
+        ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, -1.0, 0.0]),
+        ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+        ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+        ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+        ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+        ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ];
+
+    let mut vertices = Vec::with_capacity(24);
+    let mut indices = Vec::with_capacity(36);
+    for (n, u, v) in faces.iter() {
+        let base = vertices.len() as u32;
+        for (su, sv) in [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)].iter() {
+            let p = (
+                (n[0] + u[0] * su + v[0] * sv) * h,
+                (n[1] + u[1] * su + v[1] * sv) * h,
+                (n[2] + u[2] * su + v[2] * sv) * h,
+            );
+            vertices.push(vert(p, (n[0], n[1], n[2]), ((su + 1.0) / 2.0, (sv + 1.0) / 2.0)));
+        }
+        grid_indices(1, 1, base, &mut indices);
+    }
+
+    finish("cube", vertices, indices)
+}
+
+//Flat plane on XZ facing +Y, subdivided so it can be displaced later
+pub fn plane(width: f32, depth: f32, subdivisions_x: u32, subdivisions_z: u32) -> Mesh {
+    let cols = subdivisions_x.max(1);
+    let rows = subdivisions_z.max(1);
+
+    let mut vertices = Vec::new();
+    for r in 0..=rows {
+        let v = r as f32 / rows as f32;
+        for c in 0..=cols {
+            let u = c as f32 / cols as f32;
+            vertices.push(vert(((u - 0.5) * width, 0.0, (v - 0.5) * depth), (0.0, 1.0, 0.0), (u, v)));
+        }
+    }
+
+    let mut indices = Vec::new();
+    grid_indices(cols, rows, 0, &mut indices);
+
+    finish("plane", vertices, indices)
+}
+
+//Donut lying in the XZ plane, major goes around the hole and minor goes around the tube
+pub fn torus(major_radius: f32, minor_radius: f32, major_segments: u32, minor_segments: u32) -> Mesh {
+    let major_segments = major_segments.max(3);
+    let minor_segments = minor_segments.max(3);
+
+    let mut vertices = Vec::new();
+    for i in 0..=major_segments {
+        let u = i as f32 / major_segments as f32;
+        let theta = u * 2.0 * PI;
+        for j in 0..=minor_segments {
+            let v = j as f32 / minor_segments as f32;
+            let phi = v * 2.0 * PI;
+            let n = (phi.cos() * theta.cos(), phi.sin(), -phi.cos() * theta.sin());
+            let ring = major_radius + minor_radius * phi.cos();
+            let p = (ring * theta.cos(), minor_radius * phi.sin(), -ring * theta.sin());
+            vertices.push(vert(p, n, (u, v)));
+        }
+    }
+
+    let mut indices = Vec::new();
+    grid_indices(minor_segments, major_segments, 0, &mut indices);
+
+    finish("torus", vertices, indices)
+}
+
+//Upright cylinder along Y, the caps get their own vertices so the rim is a hard edge
+pub fn cylinder(radius: f32, height: f32, segments: u32, caps: bool) -> Mesh {
+    let segments = segments.max(3);
+    let h = height / 2.0;
+
+    let mut vertices = Vec::new();
+    for (r, y) in [(0.0, h), (1.0, -h)].iter() {
+        for s in 0..=segments {
+            let u = s as f32 / segments as f32;
+            let theta = u * 2.0 * PI;
+            let n = (theta.cos(), 0.0, -theta.sin());
+            vertices.push(vert((n.0 * radius, *y, n.2 * radius), n, (u, *r)));
+        }
+    }
+
+    let mut indices = Vec::new();
+    grid_indices(segments, 1, 0, &mut indices);
+
+    if caps {
+        for (y, ny) in [(h, 1.0), (-h, -1.0)].iter() {
+            let center = vertices.len() as u32;
+            vertices.push(vert((0.0, *y, 0.0), (0.0, *ny, 0.0), (0.5, 0.5)));
+            for s in 0..=segments {
+                let theta = s as f32 / segments as f32 * 2.0 * PI;
+                let (c, sn) = (theta.cos(), theta.sin());
+                vertices.push(vert((c * radius, *y, sn * radius), (0.0, *ny, 0.0), (0.5 + c * 0.5, 0.5 + sn * 0.5)));
+            }
+            for s in 0..segments {
+                let a = center + 1 + s;
+                //flip the winding on the bottom so both caps face outwards
+                if *ny > 0.0 {
+                    indices.extend_from_slice(&[center, a + 1, a]);
+                } else {
+                    indices.extend_from_slice(&[center, a, a + 1]);
+                }
+            }
+        }
+    }
+
+    finish("cylinder", vertices, indices)
+}
+
+//Looks up a primitive by name with a tessellation good enough for material previews
+pub fn by_name(name: &str) -> Option<Mesh> {
+    match name {
+        "sphere" | "uv_sphere" => Some(uv_sphere(1.0, 64, 32)),
+        "icosphere" | "ico_sphere" => Some(ico_sphere(1.0, 4)),
+        "cube" => Some(cube(1.5)),
+        "plane" => Some(plane(2.0, 2.0, 32, 32)),
+        "torus" => Some(torus(0.7, 0.3, 64, 32)),
+        "cylinder" => Some(cylinder(0.75, 1.5, 64, true)),
+        _ => None,
+    }
+}
@@ -0,0 +1,324 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use serde_json::{json, Value};
+use vulkano::format::Format;
+
+use crate::bc_encode::{encode_bc4, encode_bc5, encode_bc7};
+use crate::color_space::linear_to_srgb;
+use crate::error::RendererError;
+use crate::image_formats::decode_image;
+use crate::material::MaterialSet;
+use crate::mipmaps::{generate_mips, MipFilter};
+use crate::render_helpers::TextureRole;
+use crate::renderer::NormalConvention;
+use crate::texture_containers::{is_container, write_ktx2, ContainerImage};
+use crate::texture_format::{expand_rg_to_rgba, expand_to_rgba, format_layout, read_sample, SampleType};
+
+//Offline texture processing, turns a folder of loose maps in to KTX2 files that load without any decoding
+//every map gets its full mip chain made here and is BC compressed, AO/roughness/metalness get packed in to one ORM texture
+//the output folder has a manifest.json so find_material can load the whole thing from the folder name
+
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+//bumped whenever the layout of the manifest changes
+const MANIFEST_VERSION: u64 = 1;
+
+//What got written for one map
+pub struct ProcessedMap {
+    pub slot: &'static str,
+    pub file: PathBuf,
+    pub format: Format,
+    pub width: u32,
+    pub height: u32,
+    pub levels: usize,
+}
+
+//decodes a map to 8 bits a channel, RGBA or just the first channel
+//color maps come out sRGB encoded even if the file was 16 bit or float (those get decoded to linear)
+fn decode_8bit(path: &Path, role: TextureRole, channels: usize) -> Result<(Vec<u8>, u32, u32), RendererError> {
+    let path_str = path.to_string_lossy();
+    if is_container(&path_str) {
+        return Err(RendererError::decode(path, "already a KTX2/DDS, process the original image instead"));
+    }
+    let image = decode_image(&path_str, role)?;
+    let (count, sample) = format_layout(image.format).ok_or(RendererError::TextureFormat(image.format))?;
+    //two channel normal maps and data maps are RG, not grey and alpha like a two channel color image
+    let rgba = if role == TextureRole::Color {
+        expand_to_rgba(&image.pixels, count, sample)
+    } else {
+        expand_rg_to_rgba(&image.pixels, count, sample)
+    };
+    //a two channel normal only has X and Y, Z gets rebuilt so the normal mip filter sees the whole vector
+    let rebuild_z = role == TextureRole::Normal && count == 2;
+
+    let encode = role == TextureRole::Color && sample != SampleType::Srgb8;
+    let mut out = Vec::with_capacity((image.width * image.height) as usize * channels);
+    for (i, s) in rgba.chunks_exact(sample.bytes()).enumerate() {
+        let c = i % 4;
+        if c >= channels {
+            continue;
+        }
+        let mut v = read_sample(s, sample);
+        if encode && c < 3 {
+            v = linear_to_srgb(v);
+        }
+        if rebuild_z && c == 2 {
+            let pixel = &rgba[(i - 2) * sample.bytes()..i * sample.bytes()];
+            let x = read_sample(&pixel[..sample.bytes()], sample) * 2.0 - 1.0;
+            let y = read_sample(&pixel[sample.bytes()..], sample) * 2.0 - 1.0;
+            v = (1.0 - x * x - y * y).max(0.0).sqrt() * 0.5 + 0.5;
+        }
+        out.push((v.clamp(0.0, 1.0) * 255.0).round() as u8);
+    }
+    Ok((out, image.width, image.height))
+}
+
+//nearest neighbour is fine here, it only happens when a material's maps were exported at different sizes
+fn resize_channel(pixels: &[u8], width: u32, height: u32, new_width: u32, new_height: u32) -> Vec<u8> {
+    if (width, height) == (new_width, new_height) {
+        return pixels.to_vec();
+    }
+    let mut out = Vec::with_capacity((new_width * new_height) as usize);
+    for y in 0..new_height {
+        let sy = (y as u64 * height as u64 / new_height as u64) as usize;
+        for x in 0..new_width {
+            let sx = (x as u64 * width as u64 / new_width as u64) as usize;
+            out.push(pixels[sy * width as usize + sx]);
+        }
+    }
+    out
+}
+
+//mips then compression, pixels are RGBA8 for BC7 and BC5 and one channel for BC4
+//BC5 only keeps red and green, the mips still get made from all four so the normal filter sees whole vectors
+fn compress(pixels: Vec<u8>, width: u32, height: u32, format: Format, filter: MipFilter) -> ContainerImage {
+    let uncompressed = match format {
+        Format::BC7SrgbBlock => Format::R8G8B8A8Srgb,
+        Format::BC7UnormBlock | Format::BC5UnormBlock => Format::R8G8B8A8Unorm,
+        _ => Format::R8Unorm,
+    };
+    let encode = |pixels: &[u8], w: u32, h: u32| match format {
+        Format::BC7SrgbBlock | Format::BC7UnormBlock => encode_bc7(pixels, w, h),
+        Format::BC5UnormBlock => {
+            let rg = pixels.chunks_exact(4).flat_map(|px| [px[0], px[1]]).collect::<Vec<_>>();
+            encode_bc5(&rg, w, h)
+        }
+        _ => encode_bc4(pixels, w, h),
+    };
+
+    let mips = generate_mips(&pixels, width, height, uncompressed, filter);
+    let mut levels = vec![encode(&pixels, width, height)];
+    levels.extend(mips.iter().map(|m| encode(&m.pixels, m.width, m.height)));
+    ContainerImage { format, width, height, levels }
+}
+
+fn write_map(out_dir: &Path, name: &str, slot: &'static str, suffix: &str, image: ContainerImage) -> Result<ProcessedMap, RendererError> {
+    let file = PathBuf::from(format!("{}_{}.ktx2", name, suffix));
+    write_ktx2(out_dir.join(&file), &image)?;
+    println!("Wrote {:?}: {:?}, {}x{}, {} mip levels", out_dir.join(&file), image.format, image.width, image.height, image.levels.len());
+    Ok(ProcessedMap { slot, file, format: image.format, width: image.width, height: image.height, levels: image.levels.len() })
+}
+
+//AO, roughness and metalness in R, G and B, anything missing comes from an already packed map if there is one
+//or gets the same constant load_material would use, the largest map sets the size
+fn pack_orm(set: &MaterialSet) -> Result<Option<(Vec<u8>, u32, u32)>, RendererError> {
+    let packed = match &set.orm {
+        Some(path) => Some(decode_8bit(path, TextureRole::Data, 4)?),
+        None => None,
+    };
+    let mut channels = Vec::new();
+    for (c, (path, default)) in [(&set.ao, 255u8), (&set.roughness, 128), (&set.metalness, 0)].iter().enumerate() {
+        let channel = match (path, &packed) {
+            (Some(path), _) => Some(decode_8bit(path, TextureRole::Data, 1)?),
+            (None, Some((pixels, w, h))) => Some((pixels.iter().skip(c).step_by(4).copied().collect(), *w, *h)),
+            (None, None) => None,
+        };
+        channels.push((channel, *default));
+    }
+    let sizes = channels.iter().filter_map(|(c, _)| c.as_ref().map(|(_, w, h)| (*w, *h)));
+    let (width, height) = match sizes.max_by_key(|(w, h)| w * h) {
+        Some(size) => size,
+        None => return Ok(None),
+    };
+
+    let mut orm = vec![255u8; (width * height * 4) as usize];
+    for (c, (channel, default)) in channels.iter().enumerate() {
+        let values = match channel {
+            Some((pixels, w, h)) => resize_channel(pixels, *w, *h, width, height),
+            None => vec![*default; (width * height) as usize],
+        };
+        for (px, v) in orm.chunks_exact_mut(4).zip(values) {
+            px[c] = v;
+        }
+    }
+
+    //gloss is upside down roughness
+    if set.roughness_is_gloss && set.roughness.is_some() {
+        for px in orm.chunks_exact_mut(4) {
+            px[1] = 255 - px[1];
+        }
+    }
+    Ok(Some((orm, width, height)))
+}
+
+//Processes every map in the set in to out_dir and writes the manifest, hands back the manifest's path
+pub fn process_material<P: AsRef<Path>>(set: &MaterialSet, out_dir: P) -> Result<PathBuf, RendererError> {
+    let out_dir = out_dir.as_ref();
+    fs::create_dir_all(out_dir).map_err(|e| RendererError::io(out_dir, e))?;
+    let name = set.name.as_str();
+    let mut maps = Vec::new();
+
+    if let Some(path) = &set.albedo {
+        let (pixels, w, h) = decode_8bit(path, TextureRole::Color, 4)?;
+        maps.push(write_map(out_dir, name, "albedo", "Color", compress(pixels, w, h, Format::BC7SrgbBlock, MipFilter::Box))?);
+    }
+    if let Some((pixels, w, h)) = pack_orm(set)? {
+        maps.push(write_map(out_dir, name, "orm", "ORM", compress(pixels, w, h, Format::BC7UnormBlock, MipFilter::Box))?);
+    }
+    if let Some(path) = &set.normal {
+        let (mut pixels, w, h) = decode_8bit(path, TextureRole::Normal, 4)?;
+        //stored the way the shader reads them so the runtime never has to care,
+        //only X and Y are kept (BC5), the shader rebuilds Z
+        if set.normal_convention == NormalConvention::DirectX {
+            for px in pixels.chunks_exact_mut(4) {
+                px[1] = 255 - px[1];
+            }
+        }
+        maps.push(write_map(out_dir, name, "normal", "NormalGL", compress(pixels, w, h, Format::BC5UnormBlock, MipFilter::Normal))?);
+    }
+    if let Some(path) = &set.emission {
+        let (pixels, w, h) = decode_8bit(path, TextureRole::Color, 4)?;
+        maps.push(write_map(out_dir, name, "emission", "Emission", compress(pixels, w, h, Format::BC7SrgbBlock, MipFilter::Box))?);
+    }
+    if let Some(path) = &set.opacity {
+        let (pixels, w, h) = decode_8bit(path, TextureRole::Data, 1)?;
+        maps.push(write_map(out_dir, name, "opacity", "Opacity", compress(pixels, w, h, Format::BC4UnormBlock, MipFilter::Box))?);
+    }
+    //BC4 only keeps about 8 bits, plenty for the shader but worth knowing if a 16 bit height map goes in
+    if let Some(path) = &set.displacement {
+        let (pixels, w, h) = decode_8bit(path, TextureRole::Data, 1)?;
+        maps.push(write_map(out_dir, name, "displacement", "Displacement", compress(pixels, w, h, Format::BC4UnormBlock, MipFilter::Box))?);
+    }
+
+    let mut entries = serde_json::Map::new();
+    for map in maps.iter() {
+        entries.insert(map.slot.to_string(), json!({
+            "file": map.file,
+            "format": format!("{:?}", map.format),
+            "width": map.width,
+            "height": map.height,
+            "levels": map.levels,
+        }));
+    }
+    let manifest = json!({
+        "version": MANIFEST_VERSION,
+        "name": set.name,
+        "maps": entries,
+        "orm_channels": { "ao": "r", "roughness": "g", "metalness": "b" },
+        "normal_strength": set.normal_strength,
+    });
+
+    let path = out_dir.join(MANIFEST_NAME);
+    let text = serde_json::to_string_pretty(&manifest).expect("manifest is always valid JSON");
+    fs::write(&path, text).map_err(|e| RendererError::io(&path, e))?;
+    println!("Processed {:?} in to {} maps, see {:?}", set.name, maps.len(), path);
+    Ok(path)
+}
+
+pub fn has_manifest<P: AsRef<Path>>(dir: P) -> bool {
+    dir.as_ref().join(MANIFEST_NAME).is_file()
+}
+
+//Reads a processed folder back in to a MaterialSet, load_material takes it from there
+pub fn read_manifest<P: AsRef<Path>>(dir: P) -> Result<MaterialSet, RendererError> {
+    let dir = dir.as_ref();
+    let path = dir.join(MANIFEST_NAME);
+    let text = fs::read_to_string(&path).map_err(|e| RendererError::io(&path, e))?;
+    let manifest: Value = serde_json::from_str(&text).map_err(|e| RendererError::decode(&path, e))?;
+
+    let version = manifest["version"].as_u64().unwrap_or(0);
+    if version != MANIFEST_VERSION {
+        return Err(RendererError::decode(&path, format!("manifest is version {}, this build reads version {}, process the material again", version, MANIFEST_VERSION)));
+    }
+    let file = |slot: &str| manifest["maps"][slot]["file"].as_str().map(|f| dir.join(f));
+
+    Ok(MaterialSet {
+        name: manifest["name"].as_str().unwrap_or("processed").to_string(),
+        albedo: file("albedo"),
+        orm: file("orm"),
+        normal: file("normal"),
+        emission: file("emission"),
+        opacity: file("opacity"),
+        displacement: file("displacement"),
+        //normals were flipped to OpenGL when they got processed, older manifests don't have a strength
+        normal_strength: manifest["normal_strength"].as_f64().unwrap_or(1.0) as f32,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use crate::texture_containers::load_container;
+
+    #[test]
+    fn manifest_round_trips() {
+        let dir = std::env::temp_dir().join(format!("project_g_manifest_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        //an 8x8 RGBA PNG to process
+        let source = dir.join("Test_Color.png");
+        let mut encoder = png::Encoder::new(File::create(&source).unwrap(), 8, 8);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        let pixels = (0..8 * 8 * 4).map(|i| (i * 3) as u8).collect::<Vec<_>>();
+        encoder.write_header().unwrap().write_image_data(&pixels).unwrap();
+
+        let set = MaterialSet { name: "Test".to_string(), albedo: Some(source), normal_strength: 0.5, ..Default::default() };
+        let out = dir.join("out");
+        process_material(&set, &out).unwrap();
+
+        assert!(has_manifest(&out));
+        let read = read_manifest(&out).unwrap();
+        assert_eq!(read.name, "Test");
+        assert_eq!(read.normal_strength, 0.5);
+        assert!(read.normal.is_none() && read.orm.is_none());
+        let albedo = read.albedo.expect("the albedo is in the manifest");
+        let image = load_container(&albedo.to_string_lossy(), TextureRole::Color).unwrap();
+        assert_eq!(image.format, Format::BC7SrgbBlock);
+        assert_eq!((image.width, image.height, image.levels.len()), (8, 8, 4));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn two_channel_normals_keep_x_and_y() {
+        let dir = std::env::temp_dir().join(format!("project_g_rg_normal_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        //a grey+alpha PNG is how two channel normal maps usually get saved, X in the first channel and Y in the second
+        let source = dir.join("Test_NormalGL.png");
+        let mut encoder = png::Encoder::new(File::create(&source).unwrap(), 2, 1);
+        encoder.set_color(png::ColorType::GrayscaleAlpha);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header().unwrap().write_image_data(&[128, 128, 255, 128]).unwrap();
+
+        let (pixels, w, h) = decode_8bit(&source, TextureRole::Normal, 4).unwrap();
+        assert_eq!((w, h), (2, 1));
+        //straight up, then tilted all the way along X so Z is 0
+        assert_eq!(pixels, vec![128, 128, 255, 255, 255, 128, 128, 255]);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn old_manifests_are_rejected() {
+        let dir = std::env::temp_dir().join(format!("project_g_old_manifest_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(MANIFEST_NAME), r#"{"version": 0, "maps": {}}"#).unwrap();
+        assert!(read_manifest(&dir).is_err());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -2,8 +2,15 @@
 use std::{fs::File, io::{Cursor, Read}, sync::Arc};
 
 use vulkano::{
+    buffer::{
+        BufferUsage,
+        CpuAccessibleBuffer
+    },
     command_buffer::{
+        AutoCommandBufferBuilder,
         CommandBufferExecFuture, 
+        CommandBufferUsage,
+        PrimaryCommandBuffer,
         PrimaryAutoCommandBuffer, 
         pool::{
             standard::StandardCommandPoolAlloc
@@ -12,10 +19,12 @@ use vulkano::{
     device::Queue, 
     format::Format, 
     image::{
+        ImageCreateFlags,
         ImageDimensions, 
+        ImageLayout,
         ImmutableImage, 
         MipmapsCount, 
-        view::ImageView
+        view::{ImageView, ImageViewType}
     }, 
     instance::{
         Instance, 
@@ -27,15 +36,22 @@ use vulkano::{
     sync::NowFuture
 };
 use vulkano::device::{DeviceExtensions, Device};
-use vulkano::swapchain::Swapchain;
+use vulkano::swapchain::{PresentMode, Swapchain};
 use vulkano::image::ImageUsage;
 
 use vulkano_win::VkSurfaceBuild;
 use winit::{event_loop::EventLoop, window::Window};
-use winit::window::WindowBuilder;
-
-#[path = "./vertex.rs"]
-pub mod vertex;
+use winit::{dpi::PhysicalSize, window::WindowBuilder};
+
+use crate::color_space::{linear_to_srgb, png_encoding, Encoding};
+use crate::error::RendererError;
+use crate::mesh::{check_indices, MeshError};
+use crate::mipmaps::{generate_mips, MipFilter};
+use crate::renderer::{Parallax, TextureChannel};
+use crate::image_formats::{decode_image, finish_decode};
+use crate::texture_containers::{is_container, load_container, ContainerImage};
+use crate::texture_format::{can_sample, format_for, make_sampleable, SampleType};
+use crate::vertex::Vertex;
 
 //LOADING SHADERS HERE
 //kind of strange because shaders are handled at compile time
@@ -48,6 +64,21 @@ pub mod vs {
     }
 }
 
+//the tessellation shaders are only loaded when displacement is switched to tessellation, see Renderer::set_displacement
+pub mod tcs {
+    vulkano_shaders::shader! {
+        ty: "tess_ctrl",
+        path: "src/pbr_tesc.glsl"
+    }
+}
+
+pub mod tes {
+    vulkano_shaders::shader! {
+        ty: "tess_eval",
+        path: "src/pbr_tese.glsl"
+    }
+}
+
 pub mod fs {
     vulkano_shaders::shader! {
         ty: "fragment",
@@ -55,37 +86,176 @@ pub mod fs {
     }
 }
 
+//the background, drawn before everything else, see Background in renderer.rs
+pub mod sky_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/sky_vert.glsl"
+    }
+}
+
+pub mod sky_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/sky_frag.glsl"
+    }
+}
+
+//depth only, for the shadow maps, see shadows.rs
+pub mod shadow_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shadow_vert.glsl"
+    }
+}
+
+pub mod shadow_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shadow_frag.glsl"
+    }
+}
+
+//Index buffer contents, 16 bit indices take half the memory and bandwidth so we use them whenever the mesh is small enough
+pub enum IndexData {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl IndexData {
+    //picks the smallest index type that can address every vertex
+    pub fn compact(indices: Vec<u32>, vertex_count: usize) -> IndexData {
+        if vertex_count <= u16::MAX as usize + 1 {
+            IndexData::U16(indices.into_iter().map(|i| i as u16).collect())
+        } else {
+            IndexData::U32(indices)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            IndexData::U16(i) => i.len(),
+            IndexData::U32(i) => i.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn check(&self, mesh: &str, vertex_count: usize) -> Result<(), MeshError> {
+        match self {
+            IndexData::U16(i) => check_indices(mesh, i, vertex_count),
+            IndexData::U32(i) => check_indices(mesh, i, vertex_count),
+        }
+    }
+}
+
 //Object to be rendered by the event loop
 pub struct ObjectData {
-    pub vert_data: Vec<vertex::Vertex>,
-    pub index_data: Vec<u32>,
+    pub vert_data: Vec<Vertex>,
+    pub index_data: IndexData,
     pub albedo: Texture,
-    pub roughness: Texture,
-    pub metallness: Texture,
-    pub normalmap: Texture
+    //always the OpenGL convention, glTF says so
+    pub normalmap: Texture,
+    pub normal_strength: f32,
+    pub emission: Texture,
+    //textures the single value parameters below get read from, they point in to this by index
+    pub data: Vec<Texture>,
+    pub roughness: TextureChannel<usize>,
+    pub metalness: TextureChannel<usize>,
+    pub ao: TextureChannel<usize>,
+    pub opacity: TextureChannel<usize>,
+    //parallax occlusion mapping, white is high, read from the red channel
+    pub height: Option<Texture>,
+    pub parallax: Parallax,
 }
 
 
-//Vulkan context
+//Vulkan context, the window's event loop stays with whoever created it so they can run it themselves
 pub struct Vulkan {
     pub images: Vec<std::sync::Arc<vulkano::image::SwapchainImage<winit::window::Window>>>,
     pub swapchain: Arc<vulkano::swapchain::Swapchain<winit::window::Window>>,
     pub device: Arc<vulkano::device::Device>,
     pub queue: Arc<vulkano::device::Queue>,
-    pub events_loop: EventLoop<()>,
     pub surface: Arc<vulkano::swapchain::Surface<winit::window::Window>>,
-    pub render_pass: Arc<RenderPass>
+    pub render_pass: Arc<RenderPass>,
+    //MSAA sample count the render pass was built with, 1 means no multisampling
+    pub samples: u32,
+}
+
+//Knobs for setup_vulkano, the defaults are what you get without asking for anything
+pub struct VulkanOptions {
+    //window size in pixels, None lets the platform pick
+    pub window_size: Option<[u32; 2]>,
+    //Fifo is vsync and the only mode every driver has, anything else falls back to it if it isn't supported
+    pub present_mode: PresentMode,
+    //which GPU to use out of list_devices(), None takes the first one
+    pub device_index: Option<usize>,
+    //MSAA sample count, drops to the highest count the GPU supports if it's too high
+    pub samples: u32,
+}
+
+impl Default for VulkanOptions {
+    fn default() -> Self {
+        VulkanOptions {
+            window_size: None,
+            present_mode: PresentMode::Fifo,
+            device_index: None,
+            samples: 1,
+        }
+    }
 }
 
 //Info about a texture, these future types are brutally long
+//the future is None for a second handle on an upload someone else is already waiting on, see Texture::share
 pub struct Texture {
     pub texture: Arc<ImageView<Arc<ImmutableImage>>>, 
-    pub tex_future: CommandBufferExecFuture<NowFuture, PrimaryAutoCommandBuffer<StandardCommandPoolAlloc>>
+    pub tex_future: Option<CommandBufferExecFuture<NowFuture, PrimaryAutoCommandBuffer<StandardCommandPoolAlloc>>>
+}
+
+impl Texture {
+    //another handle on the same image, only the original has to be waited on
+    pub fn share(&self) -> Texture {
+        Texture { texture: self.texture.clone(), tex_future: None }
+    }
 }
 
 //this describes what a render pass looks like
 //for us its just Do The Colors -> Update The Depth Buffer
-pub fn setup_render_pass(device: Arc<Device>, swapchain: &Swapchain<Window>) -> Arc<RenderPass>{
+//with MSAA the colors and depth get drawn multisampled and then resolved down in to the swapchain image
+pub fn setup_render_pass(device: Arc<Device>, swapchain: &Swapchain<Window>, samples: u32) -> Result<Arc<RenderPass>, RendererError>{
+    if samples > 1 {
+        let render_pass = vulkano::single_pass_renderpass!(device.clone(),
+            attachments: {
+                intermediary: {
+                    load: Clear,
+                    store: DontCare,
+                    format: swapchain.format(),
+                    samples: samples,
+                },
+                depth: {
+                    load: Clear,
+                    store: DontCare,
+                    format: Format::D16Unorm,
+                    samples: samples,
+                },
+                color: {
+                    load: DontCare,
+                    store: Store,
+                    format: swapchain.format(),
+                    samples: 1,
+                }
+            },
+            pass: {
+                color: [intermediary],
+                depth_stencil: {depth},
+                resolve: [color],
+            }
+        )?;
+        return Ok(Arc::new(render_pass));
+    }
+
     let render_pass_noarc = vulkano::single_pass_renderpass!(device.clone(),
         attachments: {
             color: {
@@ -105,21 +275,49 @@ pub fn setup_render_pass(device: Arc<Device>, swapchain: &Swapchain<Window>) ->
             color: [color],
             depth_stencil: {depth}
         }
-    )
-    .unwrap();
+    )?;
     let render_pass = Arc::new(render_pass_noarc);
 
-    return render_pass;
+    Ok(render_pass)
+}
+
+//Names of every GPU Vulkan can see, in the order device_index picks from
+pub fn list_devices() -> Result<Vec<String>, RendererError> {
+    let instance = Instance::new(None, &vulkano_win::required_extensions(), None)?;
+    let devices = PhysicalDevice::enumerate(&instance)
+        .map(|p| format!("{} ({:?})", p.name(), p.ty()))
+        .collect();
+    Ok(devices)
+}
+
+//highest sample count at or below the requested one that the GPU can use for both color and depth
+fn supported_samples(physical: PhysicalDevice, requested: u32) -> u32 {
+    let limits = physical.limits();
+    let supported = limits.framebuffer_color_sample_counts() & limits.framebuffer_depth_sample_counts();
+    let mut samples = requested.max(1).next_power_of_two();
+    while samples > 1 && supported & samples == 0 {
+        samples /= 2;
+    }
+    if samples != requested {
+        println!("WARNING: {}x MSAA isn't supported by {}, using {}x", requested, physical.name(), samples);
+    }
+    samples
 }
 
-//Sets up the Vulkano Context
-pub fn setup_vulkano() -> Vulkan {
+//Sets up the Vulkano Context with a window on the given event loop
+pub fn setup_vulkano(event_loop: &EventLoop<()>, options: &VulkanOptions) -> Result<Vulkan, RendererError> {
     let required_extension = vulkano_win::required_extensions();
     
-    let instance = Instance::new(None, &required_extension, None).unwrap();
-
-    //grab the first device, might be a problem for those with multiple GPUs or and iGPU and dGPU both enabled.
-    let physical = PhysicalDevice::enumerate(&instance).next().unwrap();
+    let instance = Instance::new(None, &required_extension, None)?;
+
+    //grab the requested device, or the first one, which might be a problem for those with multiple GPUs or and iGPU and dGPU both enabled.
+    let physical = match options.device_index {
+        Some(i) => PhysicalDevice::enumerate(&instance).nth(i).ok_or_else(|| {
+            RendererError::DeviceSelection(format!("no device {}, there are only {}", i, PhysicalDevice::enumerate(&instance).count()))
+        })?,
+        None => PhysicalDevice::enumerate(&instance).next()
+            .ok_or_else(|| RendererError::DeviceSelection("no Vulkan capable devices found".to_string()))?,
+    };
 
     println!(
         "Using device: {} (type: {:?})",
@@ -127,13 +325,12 @@ pub fn setup_vulkano() -> Vulkan {
         physical.ty()
     );
     
-    //window event loop
-    let event_loop = EventLoop::new();
-
     //surface to draw on
-    let surface = WindowBuilder::new()
-        .build_vk_surface(&event_loop, instance.clone())
-        .unwrap();
+    let mut window = WindowBuilder::new();
+    if let Some([width, height]) = options.window_size {
+        window = window.with_inner_size(PhysicalSize::new(width, height));
+    }
+    let surface = window.build_vk_surface(event_loop, instance.clone())?;
 
     //each graphics card has a number of queues you can submit to
     //this finds a queue that supports drawing to images
@@ -143,8 +340,8 @@ pub fn setup_vulkano() -> Vulkan {
         if res {
             println!("Using this queue");
         }
-        return res;
-    }).unwrap();
+        res
+    }).ok_or_else(|| RendererError::DeviceSelection(format!("{} has no queue that can draw to the window", physical.name())))?;
 
     let device_ext = DeviceExtensions {
         khr_swapchain: true,
@@ -157,19 +354,25 @@ pub fn setup_vulkano() -> Vulkan {
         physical.supported_features(),
         &device_ext,
         [(queue_family, 0.5)].iter().cloned(),
-    ).unwrap();
+    )?;
 
-    //get a queue
-    let queue = queues.next().unwrap();
+    //get a queue, Device::new hands back one for every family we asked for so this is always there
+    let queue = queues.next().expect("device created without the requested queue");
 
     //set up the initial swapchain configuration
     //the actual stages are fairly self explanatory, but knowing which stages to use is a very advanced topic
     let (swapchain, images) = {
-        let caps = surface.capabilities(physical).unwrap();
-        let alpha = caps.supported_composite_alpha.iter().next().unwrap();
+        let caps = surface.capabilities(physical)?;
+        //the spec guarantees at least one composite alpha mode
+        let alpha = caps.supported_composite_alpha.iter().next().expect("surface supports no composite alpha modes");
         let format = caps.supported_formats[0].0;
         let dimensions: [u32; 2] = surface.window().inner_size().into();
-
+        let present_mode = if caps.present_modes.supports(options.present_mode) {
+            options.present_mode
+        } else {
+            println!("WARNING: present mode {:?} isn't supported, using Fifo", options.present_mode);
+            PresentMode::Fifo
+        };
 
         Swapchain::start(device.clone(), surface.clone())
             .num_images(caps.min_image_count)
@@ -179,26 +382,26 @@ pub fn setup_vulkano() -> Vulkan {
             //.transform(SurfaceTransform::Identity)
             .sharing_mode(&queue)
             .composite_alpha(alpha)
-            //.present_mode(PresentMode::Fifo)
+            .present_mode(present_mode)
             //.fullscreen_exclusive(FullscreenExclusive::Default)
             //.color_space(ColorSpace::SrgbNonLinear)
-            .build()
-            .unwrap()
+            .build()?
     };
 
     let dev_clone = device.clone();
     let swap_clone = swapchain.clone();
+    let samples = supported_samples(physical, options.samples);
 
     //return the vulkan context
-    Vulkan {
-        device: device,
-        images: images,
-        swapchain: swapchain,
-        events_loop: event_loop,
-        surface: surface,
-        queue: queue,
-        render_pass: setup_render_pass(dev_clone, &swap_clone)
-    }
+    Ok(Vulkan {
+        device,
+        images,
+        swapchain,
+        surface,
+        queue,
+        render_pass: setup_render_pass(dev_clone, &swap_clone, samples)?,
+        samples,
+    })
 
     //return (device, images, swapchain, event_loop, surface, queue)
     
@@ -206,61 +409,209 @@ pub fn setup_vulkano() -> Vulkan {
 
 
 
-pub fn prep_texture(path: &String, queue: Arc<Queue>) -> Texture{
+//What a texture holds, which decides whether its values get gamma decoded when sampled
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureRole {
+    //albedo, emission: colors someone looked at on a monitor, stored gamma encoded
+    Color,
+    //roughness, metalness, AO, opacity, height: the numbers are used as they are
+    Data,
+    //data too, but the mips get renormalized
+    Normal,
+}
+
+impl TextureRole {
+    pub fn mip_filter(self) -> MipFilter {
+        match self {
+            TextureRole::Normal => MipFilter::Normal,
+            _ => MipFilter::Box,
+        }
+    }
+}
+
+//An image pulled off disk and decoded, laid out the way format says
+pub struct DecodedImage {
+    pub pixels: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub format: Format,
+    pub channels: usize,
+    pub bytes_per_channel: usize,
+}
+
+pub fn decode_png(path: &str, role: TextureRole) -> Result<DecodedImage, RendererError> {
     let mut buffer = Vec::new();
-    let mut f = File::open(path).unwrap();
-    f.read_to_end(&mut buffer).unwrap();
+    let mut f = File::open(path).map_err(|e| RendererError::io(path, e))?;
+    f.read_to_end(&mut buffer).map_err(|e| RendererError::io(path, e))?;
+
+    //color maps are sRGB unless the file says otherwise, data maps are taken as is whatever the file says
+    //since image editors tag everything they save as sRGB
+    let encoding = match role {
+        TextureRole::Color => png_encoding(&buffer).unwrap_or(Encoding::Srgb),
+        TextureRole::Data | TextureRole::Normal => Encoding::Linear,
+    };
     
-    let (texture, tex_future) = {
-        //load the PNG
-        let png_bytes = buffer;
-        let cursor = Cursor::new(png_bytes);
-        let decoder = png::Decoder::new(cursor);
-        let (info, mut reader) = decoder.read_info().unwrap();
-        let dimensions = ImageDimensions::Dim2d {
-            width: info.width,
-            height: info.height,
-            array_layers: 1,
-        };
+    //load the PNG
+    //EXPAND turns palettes in to RGB(A), unpacks 1/2/4 bit grey to 8 bit and turns tRNS transparency in to an alpha channel
+    //so all that's left to deal with is 8 or 16 bit grey/grey+alpha/RGB/RGBA
+    let png_bytes = buffer;
+    let cursor = Cursor::new(png_bytes);
+    let mut decoder = png::Decoder::new(cursor);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let (info, mut reader) = decoder.read_info().map_err(|e| RendererError::decode(path, e))?;
+
+    //each of these stupid PNGs comes in like a billion possible formats
+    //if this thing guesses wrong it'll screw up how the GPU interprets the bytes of the images
+    //Unorm means we are normalizing each value to [0, 1.0] rather than [0, 255]
+    //Srgb means the GPU undoes the sRGB curve when it samples, there are only 8 bit versions of those
+    //whether the GPU can actually sample the format gets sorted out when it's uploaded, see texture_format.rs
+    let (channels, bytes_per_channel) = match reader.output_color_type() {
+        (png::ColorType::Grayscale, png::BitDepth::Eight) => (1, 1),
+        (png::ColorType::GrayscaleAlpha, png::BitDepth::Eight) => (2, 1),
+        (png::ColorType::RGB, png::BitDepth::Eight) => (3, 1),
+        (png::ColorType::RGBA, png::BitDepth::Eight) => (4, 1),
+        (png::ColorType::Grayscale, png::BitDepth::Sixteen) => (1, 2),
+        (png::ColorType::GrayscaleAlpha, png::BitDepth::Sixteen) => (2, 2),
+        (png::ColorType::RGB, png::BitDepth::Sixteen) => (3, 2),
+        (png::ColorType::RGBA, png::BitDepth::Sixteen) => (4, 2),
+        (color_type, bit_depth) => {
+            let msg = format!("{:?} {:?} didn't expand to something we can upload", bit_depth, color_type);
+            return Err(RendererError::decode(path, png::DecodingError::Other(msg.into())));
+        }
+    };
+    let sample = match bytes_per_channel {
+        2 => SampleType::Unorm16,
+        _ if encoding != Encoding::Linear => SampleType::Srgb8,
+        _ => SampleType::Unorm8,
+    };
+    //format_for covers every combination matched above
+    let format = format_for(channels, sample).expect("no format for decoded PNG layout");
+    let bytes_per_px = (channels * bytes_per_channel) as u32;
+
+    println!("Selected {:?}, {:?} for {:?} ({:?})", format, bytes_per_px, path, encoding);
 
-        //each of these stupid PNGs comes in like a billion possible formats
-        //we try and handle the most common ones here
-        //if this thing guesses wrong it'll screw up how the GPU interprets the bytes of the images
-        //Unorm means we are normalizing each value to [0, 1.0] rather than [0, 255]
-        //Supported Input Targets: 16 bit grayscale, 8 bit rgb
-        let (format, bytes_per_px) = match (info.color_type, info.bit_depth) {
-            (png::ColorType::Grayscale, png::BitDepth::Sixteen) => (Format::R16Unorm, 2),
-            (png::ColorType::GrayscaleAlpha, png::BitDepth::Sixteen) => (Format::R16G16Unorm, 4),
-            (png::ColorType::Grayscale, png::BitDepth::Eight) => (Format::R8Unorm, 1),
-            (png::ColorType::GrayscaleAlpha, png::BitDepth::Eight) => (Format::R8G8Unorm, 2),
-            (png::ColorType::RGB, png::BitDepth::Sixteen) => (Format::R16G16B16Unorm, 6),
-            (png::ColorType::RGB, png::BitDepth::Eight) => (Format::R8G8B8Unorm, 3),
-            (png::ColorType::RGBA, png::BitDepth::Sixteen) => (Format::R16G16B16A16Unorm,8),
-            (png::ColorType::RGBA, png::BitDepth::Eight) => (Format::R8G8B8A8Srgb,4),
-            (a, b) => {
-                println!("{:?} UNSUPPORTED BIT DEPTH/COLOR TYPE COMBO: {:?}, {:?}, TEXTURE MAY RENDER STRANGELY", path, b, a); 
-                (Format::R8G8B8A8Unorm, 4) 
+    let mut image_data = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut image_data).map_err(|e| RendererError::decode(path, e))?;
+
+    //PNG stores 16 bit samples big endian, the GPU wants them in its own byte order
+    if bytes_per_channel == 2 {
+        for sample in image_data.chunks_exact_mut(2) {
+            let v = u16::from_be_bytes([sample[0], sample[1]]);
+            sample.copy_from_slice(&v.to_ne_bytes());
+        }
+    }
+
+    //anything the GPU can't decode for us gets converted here, alpha is always linear so it's left alone
+    let color_channels = if channels == 2 || channels == 4 { channels - 1 } else { channels };
+    if bytes_per_channel == 2 && encoding != Encoding::Linear {
+        //no 16 bit sRGB formats, but 16 bits is plenty to store linear values in
+        for px in image_data.chunks_exact_mut(bytes_per_px as usize) {
+            for sample in px[..color_channels * 2].chunks_exact_mut(2) {
+                let v = u16::from_ne_bytes([sample[0], sample[1]]) as f32 / 65535.0;
+                let v = (encoding.to_linear(v) * 65535.0).round() as u16;
+                sample.copy_from_slice(&v.to_ne_bytes());
             }
-        };
+        }
+    } else if let Encoding::Gamma(_) = encoding {
+        //8 bit with an odd gamma gets moved on to the sRGB curve so it can use the sRGB format
+        let table = (0..256)
+            .map(|v| (linear_to_srgb(encoding.to_linear(v as f32 / 255.0)) * 255.0).round() as u8)
+            .collect::<Vec<u8>>();
+        for px in image_data.chunks_exact_mut(bytes_per_px as usize) {
+            for sample in px[..color_channels].iter_mut() {
+                *sample = table[*sample as usize];
+            }
+        }
+    }
+
+    Ok(finish_decode(image_data, info.width, info.height, channels, sample, role))
+}
+
+pub fn prep_texture(path: &str, role: TextureRole, queue: Arc<Queue>) -> Result<Texture, RendererError> {
+    //KTX2/DDS are already in a GPU format with their mips made, everything else gets decoded and mipped here
+    if is_container(path) {
+        return upload_container(load_container(path, role)?, queue);
+    }
+    let image = decode_image(path, role)?;
+    upload_texture(image.pixels, image.width, image.height, image.format, role.mip_filter(), queue)
+}
 
-        println!("Selected {:?}, {:?} for {:?}", format, bytes_per_px, path);
-
-        let mut image_data = Vec::new();
-        image_data.resize((info.width * info.height * bytes_per_px) as usize, 0);
-        reader.next_frame(&mut image_data).unwrap();
-        
-        //ImmutableImage means that it lives on the GPU and we are not writing to it
-        //some dynamic textures will get written to and they need a different thing for this (some games do mirrors like this)
-        let (image, future) = ImmutableImage::from_iter(
-            image_data.iter().cloned(),
-            dimensions,
-            MipmapsCount::One,
-            format,
-            queue.clone(),
-        )
-        .unwrap();
-        (ImageView::new(image).unwrap(), future)
+//Sends already decoded pixels to the GPU, the bytes need to already be laid out the way the format says they are
+//the whole mip chain gets built on the CPU first, see mipmaps.rs for why we don't just let the GPU blit it
+pub fn upload_texture(pixels: Vec<u8>, width: u32, height: u32, format: Format, filter: MipFilter, queue: Arc<Queue>) -> Result<Texture, RendererError> {
+    let (pixels, format) = make_sampleable(pixels, format, queue.device().physical_device())?;
+    let mips = generate_mips(&pixels, width, height, format, filter);
+    let mut levels = vec![pixels];
+    levels.extend(mips.into_iter().map(|m| m.pixels));
+    upload_levels(levels, width, height, 1, format, queue)
+}
+
+//Sends a KTX2/DDS texture as is, there's nothing we can convert block compressed data in to so the GPU has to take it
+pub fn upload_container(image: ContainerImage, queue: Arc<Queue>) -> Result<Texture, RendererError> {
+    if !can_sample(queue.device().physical_device(), image.format) {
+        return Err(RendererError::TextureFormat(image.format));
+    }
+    upload_levels(image.levels, image.width, image.height, 1, image.format, queue)
+}
+
+//Sends a cubemap, every level has all six faces one after the other in Vulkan's order (+X, -X, +Y, -Y, +Z, -Z)
+pub fn upload_cubemap(levels: Vec<Vec<u8>>, size: u32, format: Format, queue: Arc<Queue>) -> Result<Texture, RendererError> {
+    upload_levels(levels, size, size, 6, format, queue)
+}
+
+//puts a full size image and however many mips come after it in to one ImmutableImage
+//6 layers makes it a cubemap
+fn upload_levels(levels: Vec<Vec<u8>>, width: u32, height: u32, layers: u32, format: Format, queue: Arc<Queue>) -> Result<Texture, RendererError> {
+    let device = queue.device().clone();
+    let dimensions = ImageDimensions::Dim2d {
+        width,
+        height,
+        array_layers: layers,
     };
+    let cube = layers == 6;
+
+    //ImmutableImage means that it lives on the GPU and we are not writing to it
+    //some dynamic textures will get written to and they need a different thing for this (some games do mirrors like this)
+    let usage = ImageUsage {
+        transfer_destination: true,
+        sampled: true,
+        ..ImageUsage::none()
+    };
+    let (image, init) = ImmutableImage::uninitialized(
+        device.clone(),
+        dimensions,
+        format,
+        MipmapsCount::Specific(levels.len() as u32),
+        usage,
+        ImageCreateFlags { cube_compatible: cube, ..ImageCreateFlags::none() },
+        ImageLayout::ShaderReadOnlyOptimal,
+        device.active_queue_families(),
+    )?;
+    let init = Arc::new(init);
+
+    //one staging buffer and copy per level, they all go in the same command buffer
+    let upload_err = |e: &dyn std::fmt::Display| RendererError::Upload(e.to_string());
+    let mut builder = AutoCommandBufferBuilder::primary(device.clone(), queue.family(), CommandBufferUsage::OneTimeSubmit)
+        .map_err(|e| upload_err(&e))?;
+    for (level, data) in levels.into_iter().enumerate() {
+        let (w, h) = ((width >> level).max(1), (height >> level).max(1));
+        let buffer = CpuAccessibleBuffer::from_iter(device.clone(), BufferUsage::transfer_source(), false, data.into_iter())?;
+        builder
+            .copy_buffer_to_image_dimensions(buffer, init.clone(), [0, 0, 0], [w, h, 1], 0, layers, level as u32)
+            .map_err(|e| upload_err(&e))?;
+    }
+    let command_buffer = builder.build().map_err(|e| upload_err(&e))?;
+    let tex_future = command_buffer.execute(queue).map_err(|e| upload_err(&e))?;
+
+    let view = if cube {
+        ImageView::start(image).with_type(ImageViewType::Cubemap).build()?
+    } else {
+        ImageView::new(image)?
+    };
+    Ok(Texture { texture: view, tex_future: Some(tex_future) })
+}
 
-    return Texture { texture: texture, tex_future: tex_future};
-}
\ No newline at end of file
+//A 1x1 texture of a single value, used when a material has a constant instead of a texture
+pub fn solid_texture(pixel: &[u8], format: Format, queue: Arc<Queue>) -> Result<Texture, RendererError> {
+    upload_texture(pixel.to_vec(), 1, 1, format, MipFilter::Box, queue)
+}
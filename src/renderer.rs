@@ -0,0 +1,1458 @@
+use std::{collections::BTreeMap, path::Path, sync::Arc};
+use cgmath::{Deg, EuclideanSpace, InnerSpace, Matrix4, Point3, Rad, SquareMatrix, Vector3};
+use vulkano::{buffer::{BufferUsage, CpuAccessibleBuffer, CpuBufferPool}, command_buffer::{AutoCommandBufferBuilder, DynamicState, SubpassContents}, descriptor::{PipelineLayoutAbstract, descriptor_set::PersistentDescriptorSet}, device::{Device, Queue}, format::{ClearValue, Format}, image::{AttachmentImage, ImmutableImage, SwapchainImage, view::{ImageView, ImageViewAbstract}}, pipeline::{GraphicsPipeline, GraphicsPipelineAbstract, GraphicsPipelineCreationError, vertex::{BufferlessDefinition, BufferlessVertices, SingleBufferDefinition}, viewport::Viewport}, render_pass::{Framebuffer, FramebufferAbstract, RenderPass, Subpass}, sampler::{BorderColor, Compare, Filter, MipmapMode, Sampler, SamplerAddressMode}, swapchain::{self, AcquireError, Surface, Swapchain, SwapchainCreationError}, sync::{self, FlushError, GpuFuture}};
+use winit::window::Window;
+
+use crate::error::RendererError;
+use crate::ibl::{brdf_lut, EnvironmentMaps, BRDF_LUT_SIZE};
+use crate::mipmaps::MipFilter;
+use crate::mesh::{check_indices, Mesh};
+use crate::mesh_gen::displacement_normals;
+use crate::shadows::{bounding_sphere, shadow_filter, fit_cascades, local_views, merge_spheres, shadow_render_pass, transform_sphere, ShadowMaps, ShadowSettings, MAX_CASCADES, MAX_LOCAL_SHADOW_LAYERS, MAX_SHADOW_LIGHTS};
+use crate::texture_format::{format_layout, write_sample, SampleType};
+use crate::render_helpers::{fs, prep_texture, shadow_fs, shadow_vs, sky_fs, sky_vs, tcs, tes, upload_cubemap, upload_texture, vs, IndexData, ObjectData, Texture, TextureRole, Vulkan};
+use crate::vertex::Vertex;
+
+//handle to a texture that lives on the GPU
+pub type TextureView = Arc<ImageView<Arc<ImmutableImage>>>;
+
+//Handles handed back when things get added to the Renderer, they only mean something to the renderer that made them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MeshId(u64);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TextureId(u64);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MaterialId(u64);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LightId(u64);
+
+//One channel of a texture
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    R,
+    G,
+    B,
+    A,
+}
+
+//Where a single value material parameter is read from, a texture and one of its channels
+//this is what lets packed textures (ORM, glTF's metallicRoughness) get used as is
+//the texture is a TextureId for materials, ObjectData uses an index in to its own list of textures
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextureChannel<T = TextureId> {
+    pub texture: T,
+    pub channel: Channel,
+}
+
+impl<T> TextureChannel<T> {
+    pub fn new(texture: T, channel: Channel) -> Self {
+        TextureChannel { texture, channel }
+    }
+}
+
+//a plain single channel texture is read from red
+impl From<TextureId> for TextureChannel {
+    fn from(texture: TextureId) -> Self {
+        TextureChannel::new(texture, Channel::R)
+    }
+}
+
+//Which way the green channel of a normal map points
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NormalConvention {
+    //+Y up, what Vulkan/OpenGL/Blender/glTF use
+    #[default]
+    OpenGl,
+    //+Y down, what DirectX/Unreal use
+    DirectX,
+}
+
+//Parallax occlusion mapping, only does anything for materials with a height map
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Parallax {
+    //how deep the black parts of the height map sit below the white ones, in UV units, 0 turns it off
+    //with Displacement::Tessellation it's how far the vertices move instead, in world units
+    pub height_scale: f32,
+    //how many layers the view ray gets stepped through, looking straight down uses min_steps and grazing angles max_steps
+    pub min_steps: u32,
+    pub max_steps: u32,
+    //also march towards the main light so the bumps shade each other
+    pub self_shadow: bool,
+}
+
+impl Default for Parallax {
+    fn default() -> Self {
+        Parallax {
+            height_scale: 0.05,
+            min_steps: 8,
+            max_steps: 32,
+            self_shadow: true,
+        }
+    }
+}
+
+//How height maps get turned in to bumps, it changes the pipeline so it's picked for the whole renderer
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Displacement {
+    //parallax occlusion mapping in the fragment shader, cheap but the silhouette stays flat
+    #[default]
+    Parallax,
+    //meshes get split up on the GPU until their edges are about edge_pixels long on screen
+    //and the new vertices get moved along the normal, needs the GPU's tessellation_shader feature
+    //hard edges and UV seams move as one (see Vertex::displace) but each side still reads its own height,
+    //so they only stay closed where the height map agrees across them
+    Tessellation { edge_pixels: f32 },
+}
+
+//The textures that make up a PBR material, each one has to have been added with add_texture first
+//see material.rs for building one out of a folder of maps with defaults for the ones that are missing
+#[derive(Clone, Copy, Debug)]
+pub struct Material {
+    pub albedo: TextureId,
+    pub roughness: TextureChannel,
+    pub metalness: TextureChannel,
+    //two channel normal maps (BC5, RG) get Z rebuilt in the shader
+    pub normal: TextureId,
+    pub normal_convention: NormalConvention,
+    //scales how far the normal map tilts things, 0 is flat and above 1 exaggerates the bumps
+    pub normal_strength: f32,
+    pub ao: TextureChannel,
+    pub emission: TextureId,
+    //anything under half gets cut out
+    pub opacity: TextureChannel,
+    //white is high, read from the red channel, none means no parallax
+    pub height: Option<TextureId>,
+    pub parallax: Parallax,
+}
+
+//What shape of light it is and where, positions and ranges are in world units which count as meters
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    //infinitely far away like the sun, direction is the way the light travels
+    Directional { direction: Vector3<f32> },
+    //shines the same every way and falls off with the square of the distance,
+    //range is where it's faded out completely, 0 lets it go on forever
+    Point { position: Point3<f32>, range: f32 },
+    //a point light cut down to a cone around direction, full brightness inside inner_angle fading to nothing at outer_angle
+    //the angles are from the middle of the cone to its edge
+    Spot { position: Point3<f32>, direction: Vector3<f32>, range: f32, inner_angle: Rad<f32>, outer_angle: Rad<f32> },
+}
+
+//A light, intensity is lux (lm/m^2) for directional lights and candela (lm/sr) for point and spot lights
+//the same units glTF's KHR_lights_punctual uses, color is linear and gets multiplied by the intensity
+//casts_shadows gives directional lights cascaded shadow maps and point and spot lights local ones, see shadows.rs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub casts_shadows: bool,
+}
+
+impl Default for Light {
+    fn default() -> Self {
+        Light {
+            kind: LightKind::Directional { direction: Vector3::new(0.0, -1.0, -2.0) },
+            color: [1.0, 1.0, 1.0],
+            intensity: 3.0,
+            casts_shadows: true,
+        }
+    }
+}
+
+//The usual product shot lighting, worked out from where the camera is so it looks the same from anywhere
+//a warm key spot up and to the right of the camera, a dimmer cool fill point light on the left
+//and a rim light from behind and above that outlines the model against the background
+//the key and fill get brighter with the distance so the model always gets about the same light
+pub fn three_point_rig(camera: &Camera) -> Vec<Light> {
+    let to_camera = camera.eye - camera.target;
+    let distance = to_camera.magnitude().max(0.01);
+    let back = to_camera / distance;
+    //the camera's up is flipped for Vulkan, the world's is +Y
+    let up = Vector3::unit_y();
+    let right = up.cross(back);
+    let right = if right.magnitude2() > 1e-6 { right.normalize() } else { Vector3::unit_x() };
+
+    let key_position = camera.target + (back + right + up * 0.6).normalize() * distance;
+    let fill_position = camera.target + (back - right + up * 0.2).normalize() * distance;
+    vec![
+        Light {
+            kind: LightKind::Spot {
+                position: key_position,
+                direction: camera.target - key_position,
+                range: 0.0,
+                inner_angle: Deg(30.0).into(),
+                outer_angle: Deg(45.0).into(),
+            },
+            color: [1.0, 0.95, 0.88],
+            intensity: 3.0 * distance * distance,
+            casts_shadows: true,
+        },
+        Light {
+            kind: LightKind::Point { position: fill_position, range: 0.0 },
+            color: [0.85, 0.9, 1.0],
+            intensity: 1.0 * distance * distance,
+            casts_shadows: false,
+        },
+        Light {
+            kind: LightKind::Directional { direction: (back - up * 0.8).normalize() },
+            color: [1.0, 1.0, 1.0],
+            intensity: 2.0,
+            casts_shadows: true,
+        },
+    ]
+}
+
+//what the shader gets for one light, directions get normalized here so the shader doesn't have to
+//shadow_layer is where its cascades start in the shadow maps (its first layer in the local maps for point and spot lights), -1 for none
+fn light_data(light: &Light, shadow_layer: i32) -> fs::ty::LightData {
+    let normalize = |d: Vector3<f32>| if d.magnitude2() > 0.0 { d.normalize() } else { -Vector3::unit_y() };
+    let (kind, position, direction, range, cones) = match light.kind {
+        LightKind::Directional { direction } => (0, Point3::origin(), normalize(direction), 0.0, (1.0, 1.0)),
+        LightKind::Point { position, range } => (1, position, -Vector3::unit_y(), range, (1.0, 1.0)),
+        LightKind::Spot { position, direction, range, inner_angle, outer_angle } => {
+            let outer = outer_angle.0.clamp(0.0, std::f32::consts::FRAC_PI_2);
+            (2, position, normalize(direction), range, (inner_angle.0.clamp(0.0, outer).cos(), outer.cos()))
+        }
+    };
+    fs::ty::LightData {
+        position: position.into(),
+        range: range.max(0.0),
+        direction: direction.into(),
+        intensity: light.intensity.max(0.0),
+        color: light.color,
+        kind,
+        cone_inner: cones.0,
+        cone_outer: cones.1,
+        shadow_layer,
+        _dummy0: [0; 4],
+    }
+}
+
+//How the HDR lighting gets squashed down to something the screen can show
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Tonemapper {
+    #[default]
+    Reinhard = 0,
+    Aces = 1,
+    Uncharted2 = 2,
+    //no curve at all, anything over 1.0 just clips
+    None = 3,
+}
+
+//What gets drawn behind everything
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Background {
+    //the environment the scene is lit by, turned by the environment's rotation so it lines up with the reflections
+    //blur goes from 0 (sharp) to 1 (the roughest reflection), intensity is on top of the environment's own
+    Environment { blur: f32, intensity: f32 },
+    //the colors are what ends up on screen (0-1 sRGB), the tonemapper leaves them alone
+    Color([f32; 3]),
+    //top is straight up and bottom straight down
+    Gradient { top: [f32; 3], bottom: [f32; 3] },
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Environment { blur: 0.0, intensity: 1.0 }
+    }
+}
+
+//Where we are looking from and how wide the lens is
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub eye: Point3<f32>,
+    pub target: Point3<f32>,
+    pub up: Vector3<f32>,
+    pub fov_y: Rad<f32>,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        //y points down in Vulkan's clip space, flipping up here keeps models the right way up
+        Camera {
+            eye: Point3::new(1.0, 1.0, 1.0),
+            target: Point3::new(0.0, 0.0, 0.0),
+            up: Vector3::new(0.0, -1.0, 0.0),
+            fov_y: Rad(std::f32::consts::FRAC_PI_2),
+            near: 0.01,
+            far: 100.0,
+        }
+    }
+}
+
+impl Camera {
+    pub fn view_proj(&self, aspect_ratio: f32) -> Matrix4<f32> {
+        let proj = cgmath::perspective(self.fov_y, aspect_ratio, self.near, self.far);
+        proj * Matrix4::look_at_rh(self.eye, self.target, self.up)
+    }
+
+    //the same but with the camera left at the origin, so only the direction it looks in matters, what the background needs
+    fn rotation_proj(&self, aspect_ratio: f32) -> Matrix4<f32> {
+        let proj = cgmath::perspective(self.fov_y, aspect_ratio, self.near, self.far);
+        proj * Matrix4::look_at_rh(Point3::origin(), Point3::from_vec(self.target - self.eye), self.up)
+    }
+}
+
+//the index buffer type is part of the draw call's type so we have to carry both around
+enum IndexBuffer {
+    U16(Arc<CpuAccessibleBuffer<[u16]>>),
+    U32(Arc<CpuAccessibleBuffer<[u32]>>),
+}
+
+//materials and meshes hang on to the texture views themselves,
+//so removing a texture or material only stops new things from using it and never pulls it out from under something being drawn
+#[derive(Clone)]
+struct GpuMaterial {
+    albedo: TextureView,
+    normalmap: TextureView,
+    emission: TextureView,
+    //the different textures the single value parameters come from, params says which parameter is where
+    data: Vec<TextureView>,
+    height: Option<TextureView>,
+    params: fs::ty::MaterialParams,
+}
+
+//how many data textures the shader has room for, one per single value parameter so it can never run out
+const DATA_SLOTS: usize = 4;
+
+//how the shader has to decode a normal map stored in this format
+//0 is plain RGB, 1 and 2 are two channel maps (unsigned and signed) that only store X and Y so Z gets rebuilt
+fn normal_encoding(format: Format) -> u32 {
+    match format {
+        Format::BC5SnormBlock | Format::R8G8Snorm | Format::R16G16Snorm => 2,
+        Format::BC5UnormBlock => 1,
+        f if matches!(format_layout(f), Some((2, _))) => 1,
+        _ => 0,
+    }
+}
+
+//The push constants for a material
+//gives each different texture used by ao, roughness, metalness and opacity (in that order) a slot,
+//parameters packed in to the same texture share a slot so it only gets sampled once
+fn material_params<T: PartialEq + Copy>(
+    sources: [TextureChannel<T>; DATA_SLOTS],
+    normal: &TextureView,
+    convention: NormalConvention,
+    strength: f32,
+    //none when there is no height map
+    parallax: Option<Parallax>,
+) -> (Vec<T>, fs::ty::MaterialParams) {
+    let mut textures = Vec::new();
+    let mut slots = [0u32; DATA_SLOTS];
+    let mut channels = [0u32; DATA_SLOTS];
+    for (i, source) in sources.iter().enumerate() {
+        let slot = match textures.iter().position(|t| *t == source.texture) {
+            Some(slot) => slot,
+            None => {
+                textures.push(source.texture);
+                textures.len() - 1
+            }
+        };
+        slots[i] = slot as u32;
+        channels[i] = source.channel as u32;
+    }
+    let params = fs::ty::MaterialParams {
+        slots,
+        channels,
+        data_count: textures.len() as u32,
+        //flipping green turns a DirectX map in to an OpenGL one
+        normal_y: if convention == NormalConvention::DirectX { -1.0 } else { 1.0 },
+        normal_strength: strength,
+        normal_encoding: normal_encoding(normal.format()),
+        //a height scale of 0 is what tells the shader to skip parallax
+        height_scale: parallax.map_or(0.0, |p| p.height_scale.max(0.0)),
+        parallax_min_steps: parallax.map_or(1, |p| p.min_steps.max(1)),
+        parallax_max_steps: parallax.map_or(1, |p| p.max_steps.max(p.min_steps).max(1)),
+        parallax_shadow: parallax.map_or(0, |p| p.self_shadow as u32),
+        shadow_offset: 0.0,
+    };
+    (textures, params)
+}
+
+//Everything the GPU needs to draw one mesh
+struct DrawItem {
+    vertex_buffer: Arc<CpuAccessibleBuffer<[Vertex]>>,
+    index_buffer: IndexBuffer,
+    material: GpuMaterial,
+    transform: Matrix4<f32>,
+    //around the vertices before the transform, for fitting the shadow maps
+    bounds: (Point3<f32>, f32),
+}
+
+//drawing without a vertex buffer needs the pipeline's real type, a dyn one only takes buffers
+type SkyPipeline = Arc<GraphicsPipeline<BufferlessDefinition, Box<dyn PipelineLayoutAbstract + Send + Sync>>>;
+
+//the mesh pipeline, then the background's
+type PipelineAndFramebuffers = (
+    Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    SkyPipeline,
+    Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
+);
+
+//An environment on the GPU, see ibl.rs
+struct GpuEnvironment {
+    radiance: TextureView,
+    irradiance: TextureView,
+    specular: TextureView,
+    specular_levels: u32,
+}
+
+//what the renderer lights things with until it's given an environment, the same flat ambient it always had
+const DEFAULT_AMBIENT: [f32; 3] = [0.03, 0.03, 0.03];
+
+//joins an upload in to the future the next frame waits on
+fn join_upload(previous: &mut Option<Box<dyn GpuFuture>>, device: &Arc<Device>, texture: Texture) -> TextureView {
+    if let Some(upload) = texture.tex_future {
+        let fut = previous.take().unwrap_or_else(|| sync::now(device.clone()).boxed());
+        *previous = Some(fut.join(upload).boxed());
+    }
+    texture.texture
+}
+
+//the cubes go up as half floats, the radiance one is only for the background
+fn upload_environment(maps: &EnvironmentMaps, previous: &mut Option<Box<dyn GpuFuture>>, queue: Arc<Queue>) -> Result<GpuEnvironment, RendererError> {
+    let device = queue.device().clone();
+    let radiance = upload_cubemap(maps.radiance.to_rgba16f(), maps.radiance.size, Format::R16G16B16A16Sfloat, queue.clone())?;
+    let irradiance = upload_cubemap(maps.irradiance.to_rgba16f(), maps.irradiance.size, Format::R16G16B16A16Sfloat, queue.clone())?;
+    let specular = upload_cubemap(maps.specular.to_rgba16f(), maps.specular.size, Format::R16G16B16A16Sfloat, queue)?;
+    Ok(GpuEnvironment {
+        radiance: join_upload(previous, &device, radiance),
+        irradiance: join_upload(previous, &device, irradiance),
+        specular: join_upload(previous, &device, specular),
+        specular_levels: maps.specular.levels.len() as u32,
+    })
+}
+
+//The shader modules the pipeline gets built out of
+//the tessellation ones only get loaded once they're asked for, loading them on a GPU without tessellation panics
+struct Shaders {
+    vs: vs::Shader,
+    fs: fs::Shader,
+    tess: Option<(tcs::Shader, tes::Shader)>,
+    sky_vs: sky_vs::Shader,
+    sky_fs: sky_fs::Shader,
+    shadow_vs: shadow_vs::Shader,
+    shadow_fs: shadow_fs::Shader,
+}
+
+//GETS INVOKED EACH TIME THE WINDOW IS RESIZED,
+//NEEDED TO REBUILD THE FRAMEBUFFERS AND PIPELINE WITH THE NEW WINDOW SIZE
+fn window_size_dependent_setup(
+    device: Arc<Device>,
+    images: &[Arc<SwapchainImage<Window>>],
+    shaders: &Shaders,
+    render_pass: Arc<RenderPass>,
+    samples: u32,
+    tonemapper: Tonemapper,
+    displacement: Displacement,
+) -> Result<PipelineAndFramebuffers, RendererError> {
+    let dimensions = images[0].dimensions();
+
+    let depth_buffer = ImageView::new(
+        AttachmentImage::transient_multisampled(device.clone(), dimensions, samples, Format::D16Unorm)?,
+    )?;
+
+    //with MSAA we draw in to a multisampled image that gets resolved in to the swapchain image at the end of the pass
+    //the attachments have to go in the same order setup_render_pass declared them
+    let framebuffers = if samples > 1 {
+        let intermediary = ImageView::new(
+            AttachmentImage::transient_multisampled(device.clone(), dimensions, samples, images[0].swapchain().format())?,
+        )?;
+        images
+            .iter()
+            .map(|image| {
+                let view = ImageView::new(image.clone())?;
+                Ok(Arc::new(
+                    Framebuffer::start(render_pass.clone())
+                        .add(intermediary.clone())?
+                        .add(depth_buffer.clone())?
+                        .add(view)?
+                        .build()?,
+                ) as Arc<dyn FramebufferAbstract + Send + Sync>)
+            })
+            .collect::<Result<Vec<_>, RendererError>>()?
+    } else {
+        images
+            .iter()
+            .map(|image| {
+                let view = ImageView::new(image.clone())?;
+                Ok(Arc::new(
+                    Framebuffer::start(render_pass.clone())
+                        .add(view)?
+                        .add(depth_buffer.clone())?
+                        .build()?,
+                ) as Arc<dyn FramebufferAbstract + Send + Sync>)
+            })
+            .collect::<Result<Vec<_>, RendererError>>()?
+    };
+
+    //the tonemapper is a specialization constant so the shader doesn't branch on it per pixel
+    let fs_consts = fs::SpecializationConstants {
+        tonemapper: tonemapper as i32,
+    };
+
+    // In the triangle example we use a dynamic viewport, as its a simple example.
+    // However in the teapot example, we recreate the pipelines with a hardcoded viewport instead.
+    // This allows the driver to optimize things, at the cost of slower window resizes.
+    // https://computergraphics.stackexchange.com/questions/5742/vulkan-best-way-of-updating-pipeline-viewport
+    let viewport = Viewport {
+        origin: [0.0, 0.0],
+        dimensions: [dimensions[0] as f32, dimensions[1] as f32],
+        depth_range: 0.0..1.0,
+    };
+    let subpass = Subpass::from(render_pass.clone(), 0).expect("render pass has no first subpass");
+
+    //the tessellated variant feeds the triangles in as 3 vertex patches, the builder's type changes with the stages so it's a whole second chain
+    let pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync> = match (&shaders.tess, displacement) {
+        (Some((tcs, tes)), Displacement::Tessellation { .. }) => Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input(SingleBufferDefinition::<Vertex>::new())
+                .vertex_shader(shaders.vs.main_entry_point(), ())
+                .tessellation_shaders(tcs.main_entry_point(), (), tes.main_entry_point(), ())
+                .patch_list(3)
+                .viewports_dynamic_scissors_irrelevant(1)
+                .viewports(std::iter::once(viewport.clone()))
+                .fragment_shader(shaders.fs.main_entry_point(), fs_consts)
+                .depth_stencil_simple_depth()
+                .render_pass(subpass.clone())
+                .build(device.clone())?,
+        ),
+        _ => Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input(SingleBufferDefinition::<Vertex>::new())
+                .vertex_shader(shaders.vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .viewports(std::iter::once(viewport.clone()))
+                .fragment_shader(shaders.fs.main_entry_point(), fs_consts)
+                .depth_stencil_simple_depth()
+                .render_pass(subpass.clone())
+                .build(device.clone())?,
+        ),
+    };
+
+    //the background covers the whole screen and gets drawn first, it doesn't touch the depth buffer so the meshes go over it
+    let sky_consts = sky_fs::SpecializationConstants {
+        tonemapper: tonemapper as i32,
+    };
+    let sky_pipeline = Arc::new(
+        GraphicsPipeline::start()
+            .vertex_input(BufferlessDefinition)
+            .vertex_shader(shaders.sky_vs.main_entry_point(), ())
+            .triangle_list()
+            .viewports_dynamic_scissors_irrelevant(1)
+            .viewports(std::iter::once(viewport))
+            .fragment_shader(shaders.sky_fs.main_entry_point(), sky_consts)
+            .render_pass(subpass)
+            .build(device)?,
+    );
+
+    Ok((pipeline, sky_pipeline, framebuffers))
+}
+
+//the shadow maps don't change size with the window, this only gets rebuilt when their resolution does
+//the height map's displacement is left out, shadows come from the undisplaced mesh
+//tessellation only ever pushes the surface in, so the main pass moves its lookups back out by the height scale to match
+fn shadow_pipeline(device: Arc<Device>, shaders: &Shaders, render_pass: Arc<RenderPass>, resolution: u32) -> Result<Arc<dyn GraphicsPipelineAbstract + Send + Sync>, RendererError> {
+    let viewport = Viewport {
+        origin: [0.0, 0.0],
+        dimensions: [resolution as f32, resolution as f32],
+        depth_range: 0.0..1.0,
+    };
+    Ok(Arc::new(
+        GraphicsPipeline::start()
+            .vertex_input(SingleBufferDefinition::<Vertex>::new())
+            .vertex_shader(shaders.shadow_vs.main_entry_point(), ())
+            .triangle_list()
+            .viewports_dynamic_scissors_irrelevant(1)
+            .viewports(std::iter::once(viewport))
+            .fragment_shader(shaders.shadow_fs.main_entry_point(), ())
+            .depth_stencil_simple_depth()
+            .render_pass(Subpass::from(render_pass, 0).expect("shadow render pass has no first subpass"))
+            .build(device)?,
+    ))
+}
+
+//The renderer owns everything on the GPU, you add meshes, textures, materials, lights and a camera to it
+//then call render_frame whenever your event loop wants a new frame
+pub struct Renderer {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    surface: Arc<Surface<Window>>,
+    swapchain: Arc<Swapchain<Window>>,
+    render_pass: Arc<RenderPass>,
+    shaders: Shaders,
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    sky_pipeline: SkyPipeline,
+    framebuffers: Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
+    sampler: Arc<Sampler>,
+    uniform_buffer: CpuBufferPool<vs::ty::Data>,
+
+    //image based lighting, the LUT doesn't depend on the environment so it's made once
+    //the environment maps and LUT get a sampler that clamps, repeating would bleed the LUT's edges in to each other
+    environment: GpuEnvironment,
+    environment_intensity: f32,
+    environment_rotation: Rad<f32>,
+    brdf_lut: TextureView,
+    clamp_sampler: Arc<Sampler>,
+    environment_buffer: CpuBufferPool<fs::ty::Environment>,
+    background: Background,
+    //every light goes in to one storage buffer each frame, the shader loops over all of them
+    light_buffer: CpuBufferPool<fs::ty::LightData>,
+
+    //cascaded shadow maps for directional lights, the maps get remade when more layers or another resolution is needed
+    shadow_settings: ShadowSettings,
+    shadow_render_pass: Arc<RenderPass>,
+    shadow_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    shadow_maps: ShadowMaps,
+    shadow_sampler: Arc<Sampler>,
+    shadow_buffer: CpuBufferPool<fs::ty::Shadows>,
+    //point and spot light shadows, these are kept from frame to frame and a layer only gets redrawn when the light that owns it
+    //changes or a mesh does, geometry_version goes up with every mesh change and local_shadow_geometry is the one the maps have
+    local_shadow_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    local_shadow_maps: ShadowMaps,
+    local_shadow_owners: Vec<Option<(LightId, Light)>>,
+    local_shadow_geometry: u64,
+    geometry_version: u64,
+
+    //previous_frame_end is a future that resolves when the GPU is finished displaying the most recently submitted frame
+    //texture uploads get joined in to it so the next frame waits until they are on the GPU
+    previous_frame_end: Option<Box<dyn GpuFuture>>,
+
+    //This gets set to true either when the swapchain gets filled with garbage and needs to be cleaned up or when the window is resized
+    recreate_swapchain: bool,
+    samples: u32,
+    tonemapper: Tonemapper,
+    displacement: Displacement,
+
+    next_id: u64,
+    meshes: BTreeMap<MeshId, DrawItem>,
+    textures: BTreeMap<TextureId, TextureView>,
+    materials: BTreeMap<MaterialId, GpuMaterial>,
+    lights: BTreeMap<LightId, Light>,
+    camera: Camera,
+}
+
+impl Renderer {
+    pub fn new(vk: Vulkan) -> Result<Renderer, RendererError> {
+        //load the shaders
+        let shaders = Shaders {
+            vs: vs::Shader::load(vk.device.clone())?,
+            fs: fs::Shader::load(vk.device.clone())?,
+            tess: None,
+            sky_vs: sky_vs::Shader::load(vk.device.clone())?,
+            sky_fs: sky_fs::Shader::load(vk.device.clone())?,
+            shadow_vs: shadow_vs::Shader::load(vk.device.clone())?,
+            shadow_fs: shadow_fs::Shader::load(vk.device.clone())?,
+        };
+
+        //set up the initial GPU pipeline and framebuffers
+        //the pipeline describes what steps the GPU should take, for us this is...
+        //Load Vertexes -> Apply Vertex Shader -> Setup Viewport (the thing the fragment shader writes to) -> Run Fragment Shader -> Do a Depth Pass -> Render to frame
+        //We are running a simple framebuffer setup where we just have a depth buffer and view buffer
+        let tonemapper = Tonemapper::default();
+        let displacement = Displacement::default();
+        let (pipeline, sky_pipeline, framebuffers) = window_size_dependent_setup(vk.device.clone(), &vk.images, &shaders, vk.render_pass.clone(), vk.samples, tonemapper, displacement)?;
+
+        //This is the object that describes how we should sample textures
+        //This handles mipmapping, what to do with texcoords out of [0.0, 1.0], and how to resolve coordinates that fall between two pixels
+        //We use the same sampler for all our textures
+        let sampler = Sampler::new(
+            vk.device.clone(),
+            Filter::Linear,
+            Filter::Linear,
+            MipmapMode::Linear,
+            SamplerAddressMode::Repeat,
+            SamplerAddressMode::Repeat,
+            SamplerAddressMode::Repeat,
+            0.0,
+            1.0,
+            0.0,
+            //no upper limit on the mip level, every texture has a full chain
+            1000.0,
+        )?;
+
+        let clamp_sampler = Sampler::new(
+            vk.device.clone(),
+            Filter::Linear,
+            Filter::Linear,
+            MipmapMode::Linear,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            1000.0,
+        )?;
+
+        //this is the same as the uniform buffer in OpenGL
+        let uniform_buffer = CpuBufferPool::<vs::ty::Data>::new(vk.device.clone(), BufferUsage::all());
+        let environment_buffer = CpuBufferPool::<fs::ty::Environment>::new(vk.device.clone(), BufferUsage::all());
+        let light_buffer = CpuBufferPool::<fs::ty::LightData>::new(vk.device.clone(), BufferUsage::all());
+
+        //the shadow maps get compared against rather than read, the hardware does a 2x2 PCF with each lookup when it can filter them
+        //outside the map is white, which is as far away as it gets so it's lit
+        let shadow_settings = ShadowSettings::default();
+        let shadow_render_pass = shadow_render_pass(vk.device.clone())?;
+        let local_shadow_pipeline = shadow_pipeline(vk.device.clone(), &shaders, shadow_render_pass.clone(), shadow_settings.local_resolution)?;
+        let shadow_pipeline = shadow_pipeline(vk.device.clone(), &shaders, shadow_render_pass.clone(), shadow_settings.resolution)?;
+        let shadow_maps = ShadowMaps::new(&vk.queue, shadow_render_pass.clone(), shadow_settings.resolution, 1)?;
+        let local_shadow_maps = ShadowMaps::new(&vk.queue, shadow_render_pass.clone(), shadow_settings.local_resolution, 1)?;
+        let border = SamplerAddressMode::ClampToBorder(BorderColor::FloatOpaqueWhite);
+        let shadow_filter = shadow_filter(vk.device.physical_device());
+        let shadow_sampler = Sampler::compare(
+            vk.device.clone(),
+            shadow_filter,
+            shadow_filter,
+            MipmapMode::Nearest,
+            border,
+            border,
+            border,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+            Compare::LessOrEqual,
+        )?;
+        let shadow_buffer = CpuBufferPool::<fs::ty::Shadows>::new(vk.device.clone(), BufferUsage::all());
+
+        //flat ambient light until set_environment gets called
+        let mut previous_frame_end = Some(sync::now(vk.device.clone()).boxed());
+        let environment = upload_environment(&EnvironmentMaps::uniform(DEFAULT_AMBIENT), &mut previous_frame_end, vk.queue.clone())?;
+        let mut lut = Vec::new();
+        for texel in brdf_lut().iter() {
+            write_sample(texel[0], SampleType::Float16, &mut lut);
+            write_sample(texel[1], SampleType::Float16, &mut lut);
+        }
+        let lut = upload_texture(lut, BRDF_LUT_SIZE, BRDF_LUT_SIZE, Format::R16G16Sfloat, MipFilter::Box, vk.queue.clone())?;
+        let brdf_lut = join_upload(&mut previous_frame_end, &vk.device, lut);
+
+        Ok(Renderer {
+            previous_frame_end,
+            device: vk.device,
+            queue: vk.queue,
+            surface: vk.surface,
+            swapchain: vk.swapchain,
+            render_pass: vk.render_pass,
+            shaders,
+            pipeline,
+            sky_pipeline,
+            framebuffers,
+            sampler,
+            uniform_buffer,
+            environment,
+            environment_intensity: 1.0,
+            environment_rotation: Rad(0.0),
+            brdf_lut,
+            clamp_sampler,
+            environment_buffer,
+            background: Background::default(),
+            light_buffer,
+            shadow_settings,
+            shadow_render_pass,
+            shadow_pipeline,
+            shadow_maps,
+            shadow_sampler,
+            shadow_buffer,
+            local_shadow_pipeline,
+            local_shadow_maps,
+            local_shadow_owners: vec![None],
+            local_shadow_geometry: 0,
+            geometry_version: 0,
+            recreate_swapchain: false,
+            samples: vk.samples,
+            tonemapper,
+            displacement,
+            next_id: 0,
+            meshes: BTreeMap::new(),
+            textures: BTreeMap::new(),
+            materials: BTreeMap::new(),
+            lights: BTreeMap::new(),
+            camera: Camera::default(),
+        })
+    }
+
+    pub fn window(&self) -> &Window {
+        self.surface.window()
+    }
+
+    //the queue textures should be uploaded on
+    pub fn queue(&self) -> Arc<Queue> {
+        self.queue.clone()
+    }
+
+    //call this when the window gets resized so the swapchain gets rebuilt before the next frame
+    pub fn resize(&mut self) {
+        self.recreate_swapchain = true;
+    }
+
+    pub fn tonemapper(&self) -> Tonemapper {
+        self.tonemapper
+    }
+
+    //the tonemapper is baked in to the pipeline, so this rebuilds it before the next frame
+    pub fn set_tonemapper(&mut self, tonemapper: Tonemapper) {
+        if tonemapper != self.tonemapper {
+            self.tonemapper = tonemapper;
+            self.recreate_swapchain = true;
+        }
+    }
+
+    pub fn displacement(&self) -> Displacement {
+        self.displacement
+    }
+
+    //switching between parallax and tessellation swaps the pipeline, it gets rebuilt before the next frame
+    //errors without changing anything if the GPU can't tessellate
+    pub fn set_displacement(&mut self, displacement: Displacement) -> Result<(), RendererError> {
+        if let Displacement::Tessellation { .. } = displacement {
+            if self.shaders.tess.is_none() {
+                if !self.device.enabled_features().tessellation_shader {
+                    return Err(GraphicsPipelineCreationError::TessellationShaderFeatureNotEnabled.into());
+                }
+                self.shaders.tess = Some((tcs::Shader::load(self.device.clone())?, tes::Shader::load(self.device.clone())?));
+            }
+        }
+        if displacement != self.displacement {
+            self.displacement = displacement;
+            self.recreate_swapchain = true;
+        }
+        Ok(())
+    }
+
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    //the first frame drawn after this waits for the upload to finish
+    fn wait_for_upload(&mut self, texture: Texture) -> TextureView {
+        join_upload(&mut self.previous_frame_end, &self.device, texture)
+    }
+
+    //ENVIRONMENT
+    //what everything gets lit by besides the lights, see ibl.rs for making the maps
+    pub fn set_environment(&mut self, maps: &EnvironmentMaps) -> Result<(), RendererError> {
+        self.environment = upload_environment(maps, &mut self.previous_frame_end, self.queue.clone())?;
+        Ok(())
+    }
+
+    //loads an equirectangular HDR/EXR (or an ordinary image) and lights everything with it
+    pub fn load_environment<P: AsRef<Path>>(&mut self, path: P) -> Result<(), RendererError> {
+        let maps = EnvironmentMaps::load(&path.as_ref().to_string_lossy())?;
+        self.set_environment(&maps)
+    }
+
+    //scales all the environment's light, 1 is as it is in the file
+    pub fn set_environment_intensity(&mut self, intensity: f32) {
+        self.environment_intensity = intensity;
+    }
+
+    //turns the environment around the vertical axis
+    pub fn set_environment_rotation(&mut self, rotation: Rad<f32>) {
+        self.environment_rotation = rotation;
+    }
+
+    pub fn background(&self) -> Background {
+        self.background
+    }
+
+    pub fn set_background(&mut self, background: Background) {
+        self.background = background;
+    }
+
+    pub fn shadows(&self) -> ShadowSettings {
+        self.shadow_settings
+    }
+
+    //a new resolution rebuilds the shadow pipeline and maps straight away
+    //any change at all means the local maps get redrawn next frame
+    pub fn set_shadows(&mut self, settings: ShadowSettings) -> Result<(), RendererError> {
+        let settings = ShadowSettings {
+            resolution: settings.resolution.max(1),
+            local_resolution: settings.local_resolution.max(1),
+            cascades: settings.cascades.clamp(1, MAX_CASCADES as u32),
+            ..settings
+        };
+        if settings.resolution != self.shadow_settings.resolution {
+            self.shadow_pipeline = shadow_pipeline(self.device.clone(), &self.shaders, self.shadow_render_pass.clone(), settings.resolution)?;
+            self.shadow_maps = ShadowMaps::new(&self.queue, self.shadow_render_pass.clone(), settings.resolution, self.shadow_maps.layers)?;
+        }
+        if settings.local_resolution != self.shadow_settings.local_resolution {
+            self.local_shadow_pipeline = shadow_pipeline(self.device.clone(), &self.shaders, self.shadow_render_pass.clone(), settings.local_resolution)?;
+            self.local_shadow_maps = ShadowMaps::new(&self.queue, self.shadow_render_pass.clone(), settings.local_resolution, self.local_shadow_maps.layers)?;
+        }
+        if settings != self.shadow_settings {
+            self.local_shadow_owners = vec![None; self.local_shadow_maps.layers as usize];
+        }
+        self.shadow_settings = settings;
+        Ok(())
+    }
+
+    //TEXTURES
+    pub fn add_texture(&mut self, texture: Texture) -> TextureId {
+        let id = TextureId(self.next_id());
+        let view = self.wait_for_upload(texture);
+        self.textures.insert(id, view);
+        id
+    }
+
+    //loads a PNG from disk and adds it, the role picks between sRGB and linear and how the mips are filtered
+    pub fn load_texture<P: AsRef<Path>>(&mut self, path: P, role: TextureRole) -> Result<TextureId, RendererError> {
+        let texture = prep_texture(&path.as_ref().to_string_lossy(), role, self.queue.clone())?;
+        Ok(self.add_texture(texture))
+    }
+
+    pub fn remove_texture(&mut self, id: TextureId) -> bool {
+        self.textures.remove(&id).is_some()
+    }
+
+    //MATERIALS
+    pub fn add_material(&mut self, material: Material) -> Result<MaterialId, RendererError> {
+        let texture = |id: TextureId| {
+            self.textures.get(&id).cloned()
+                .ok_or_else(|| RendererError::UnknownHandle(format!("no texture with id {:?}", id)))
+        };
+        let normalmap = texture(material.normal)?;
+        let sources = [material.ao, material.roughness, material.metalness, material.opacity];
+        let height = material.height.map(texture).transpose()?;
+        let parallax = Some(material.parallax).filter(|_| height.is_some());
+        let (data, params) = material_params(sources, &normalmap, material.normal_convention, material.normal_strength, parallax);
+        let gpu_material = GpuMaterial {
+            albedo: texture(material.albedo)?,
+            normalmap,
+            emission: texture(material.emission)?,
+            data: data.into_iter().map(texture).collect::<Result<_, _>>()?,
+            height,
+            params,
+        };
+
+        let id = MaterialId(self.next_id());
+        self.materials.insert(id, gpu_material);
+        Ok(id)
+    }
+
+    pub fn remove_material(&mut self, id: MaterialId) -> bool {
+        self.materials.remove(&id).is_some()
+    }
+
+    fn material(&self, id: MaterialId) -> Result<GpuMaterial, RendererError> {
+        self.materials.get(&id).cloned()
+            .ok_or_else(|| RendererError::UnknownHandle(format!("no material with id {:?}", id)))
+    }
+
+    //MESHES
+    pub fn add_mesh(&mut self, mesh: &Mesh, material: MaterialId) -> Result<MeshId, RendererError> {
+        let material = self.material(material)?;
+        //checked before compacting, squeezing a bad index in to 16 bits could wrap it back in to range
+        check_indices(&mesh.name, &mesh.indices, mesh.vertices.len())?;
+        let index_data = IndexData::compact(mesh.indices.clone(), mesh.vertices.len());
+        self.upload_mesh(&mesh.vertices, index_data, material)
+    }
+
+    //adds a loaded object along with its textures, the textures only belong to this mesh and don't get their own ids
+    pub fn add_object(&mut self, object: ObjectData) -> Result<MeshId, RendererError> {
+        object.index_data.check("object", object.vert_data.len())?;
+        let normalmap = self.wait_for_upload(object.normalmap);
+        let sources = [object.ao, object.roughness, object.metalness, object.opacity];
+        let height = object.height.map(|t| self.wait_for_upload(t));
+        let parallax = Some(object.parallax).filter(|_| height.is_some());
+        let (used, params) = material_params(sources, &normalmap, NormalConvention::OpenGl, object.normal_strength, parallax);
+        let data = object.data.into_iter().map(|t| self.wait_for_upload(t)).collect::<Vec<_>>();
+        let data = used
+            .into_iter()
+            .map(|i| data.get(i).cloned().ok_or_else(|| RendererError::UnknownHandle(format!("object only has {} data textures, asked for {}", data.len(), i))))
+            .collect::<Result<_, _>>()?;
+        let material = GpuMaterial {
+            albedo: self.wait_for_upload(object.albedo),
+            normalmap,
+            emission: self.wait_for_upload(object.emission),
+            data,
+            height,
+            params,
+        };
+        self.upload_mesh(&object.vert_data, object.index_data, material)
+    }
+
+    fn upload_mesh(&mut self, vertices: &[Vertex], index_data: IndexData, material: GpuMaterial) -> Result<MeshId, RendererError> {
+        //This is like the stupid VBO setup in OpenGL except not garbage
+        //There are a few buffer types (ImmutableBuffer, CPUAccessibleBuffer, CpuBufferPool), each of which strike a different tradeoff between GPU access speed and CPU access speed
+        //CPUAccessibleBuffer is *good enough* for regular GPU rendering, though not as fast as an ImmutableBuffer + some others
+        //CpuBufferPool is for data that gets changed *every frame* by the CPU
+        let mut vertices = vertices.to_vec();
+        displacement_normals(&mut vertices);
+        let vertex_buffer =
+        CpuAccessibleBuffer::from_iter(self.device.clone(), BufferUsage::all(), false, vertices.iter().cloned())?;
+
+        let index_buffer = match index_data {
+            IndexData::U16(idxs) => IndexBuffer::U16(CpuAccessibleBuffer::from_iter(self.device.clone(), BufferUsage::all(), false, idxs.into_iter())?),
+            IndexData::U32(idxs) => IndexBuffer::U32(CpuAccessibleBuffer::from_iter(self.device.clone(), BufferUsage::all(), false, idxs.into_iter())?),
+        };
+
+        let id = MeshId(self.next_id());
+        self.geometry_version += 1;
+        self.meshes.insert(id, DrawItem {
+            vertex_buffer,
+            index_buffer,
+            material,
+            transform: Matrix4::identity(),
+            bounds: bounding_sphere(vertices.iter().map(|v| Point3::new(v.position.0, v.position.1, v.position.2))),
+        });
+        Ok(id)
+    }
+
+    //the model matrix for a mesh, returns false if there is no such mesh
+    pub fn set_mesh_transform(&mut self, id: MeshId, transform: Matrix4<f32>) -> bool {
+        match self.meshes.get_mut(&id) {
+            Some(item) => {
+                if item.transform != transform {
+                    item.transform = transform;
+                    self.geometry_version += 1;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_mesh_material(&mut self, id: MeshId, material: MaterialId) -> Result<(), RendererError> {
+        let material = self.material(material)?;
+        let item = self.meshes.get_mut(&id)
+            .ok_or_else(|| RendererError::UnknownHandle(format!("no mesh with id {:?}", id)))?;
+        item.material = material;
+        Ok(())
+    }
+
+    pub fn remove_mesh(&mut self, id: MeshId) -> bool {
+        let removed = self.meshes.remove(&id).is_some();
+        if removed {
+            self.geometry_version += 1;
+        }
+        removed
+    }
+
+    //LIGHTS
+    //there's no limit on how many, the first one added is the main light parallax self shadowing uses
+    pub fn add_light(&mut self, light: Light) -> LightId {
+        let id = LightId(self.next_id());
+        self.lights.insert(id, light);
+        id
+    }
+
+    pub fn set_light(&mut self, id: LightId, light: Light) -> bool {
+        match self.lights.get_mut(&id) {
+            Some(l) => {
+                *l = light;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove_light(&mut self, id: LightId) -> bool {
+        self.lights.remove(&id).is_some()
+    }
+
+    //CAMERA
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.camera = camera;
+    }
+
+    //Draws everything that has been added and presents it to the window
+    //returns Ok without drawing anything when the window can't be drawn to right now (minimized, mid resize)
+    pub fn render_frame(&mut self) -> Result<(), RendererError> {
+        //wait here for the previous frame to finish cleaning up
+        if let Some(fut) = self.previous_frame_end.as_mut() {
+            fut.cleanup_finished();
+        }
+
+        //check if we need to remake the swapchain, if we do, remake it
+        if self.recreate_swapchain {
+            let dimensions: [u32; 2] = self.surface.window().inner_size().into();
+            let (new_swapchain, new_images) =
+                match self.swapchain.recreate().dimensions(dimensions).build() {
+                    Ok(r) => r,
+                    Err(SwapchainCreationError::UnsupportedDimensions) => return Ok(()),
+                    Err(e) => return Err(e.into()),
+                };
+
+            //update the swapchain, pipeline, and framebuffers
+            self.swapchain = new_swapchain;
+            let (new_pipeline, new_sky_pipeline, new_framebuffers) = window_size_dependent_setup(
+                self.device.clone(),
+                &new_images,
+                &self.shaders,
+                self.render_pass.clone(),
+                self.samples,
+                self.tonemapper,
+                self.displacement,
+            )?;
+            self.pipeline = new_pipeline;
+            self.sky_pipeline = new_sky_pipeline;
+            self.framebuffers = new_framebuffers;
+            self.recreate_swapchain = false;
+        }
+
+        //not really sure what this is up to, I think it's just trying to get the next frame to draw on from the swapchain
+        let (image_num, suboptimal, acquire_future) =
+            match swapchain::acquire_next_image(self.swapchain.clone(), None) {
+                Ok(r) => r,
+                Err(AcquireError::OutOfDate) => {
+                    self.recreate_swapchain = true;
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+        //I think this corresponds to "garbage being in the swapchain"
+        if suboptimal {
+            self.recreate_swapchain = true;
+        }
+
+        //this is doing all the same projection math that we did in OpenGL project 2
+        let dimensions = self.swapchain.dimensions();
+        let aspect_ratio = dimensions[0] as f32 / dimensions[1] as f32;
+        let view_proj = self.camera.view_proj(aspect_ratio);
+        //with tessellation the height map moves vertices instead, so the fragment shader's parallax gets switched off
+        let tess_edge = match self.displacement {
+            Displacement::Tessellation { edge_pixels } => Some(edge_pixels.max(1.0)),
+            Displacement::Parallax => None,
+        };
+
+        //SHADOWS
+        //the first MAX_SHADOW_LIGHTS directional lights that cast shadows get a set of cascades each, fitted around the camera
+        //and pulled back far enough to take in everything that has been added
+        //point and spot lights get layers in the local maps in the order they were added until MAX_LOCAL_SHADOW_LAYERS runs out
+        let scene = merge_spheres(self.meshes.values().map(|item| transform_sphere(item.bounds, &item.transform)));
+        let cascade_count = (self.shadow_settings.cascades as usize).clamp(1, MAX_CASCADES);
+        let mut cascades = Vec::new();
+        let mut splits = Vec::new();
+        let mut locals = Vec::new();
+        let mut local_owners = Vec::new();
+        let mut lights = Vec::new();
+        for (id, light) in self.lights.iter() {
+            let mut shadow_layer = -1;
+            let shadowed = light.casts_shadows && self.shadow_settings.enabled;
+            match (light.kind, scene) {
+                (LightKind::Directional { direction }, Some(scene)) => {
+                    let room = cascades.len() < MAX_SHADOW_LIGHTS * cascade_count;
+                    if shadowed && room && direction.magnitude2() > 0.0 {
+                        shadow_layer = cascades.len() as i32;
+                        let (fitted, light_splits) = fit_cascades(&self.camera, aspect_ratio, direction, &self.shadow_settings, scene);
+                        cascades.extend(fitted);
+                        splits = light_splits;
+                    }
+                }
+                (kind, Some(scene)) if shadowed => {
+                    if let Some(views) = local_views(&kind, &self.shadow_settings, scene) {
+                        if locals.len() + views.len() <= MAX_LOCAL_SHADOW_LAYERS {
+                            shadow_layer = locals.len() as i32;
+                            local_owners.extend(views.iter().map(|_| Some((*id, *light))));
+                            locals.extend(views);
+                        }
+                    }
+                }
+                _ => {}
+            }
+            lights.push(light_data(light, shadow_layer));
+        }
+        //the maps only ever grow, going back to fewer lights just leaves some layers unused
+        if cascades.len() as u32 > self.shadow_maps.layers {
+            self.shadow_maps = ShadowMaps::new(&self.queue, self.shadow_render_pass.clone(), self.shadow_settings.resolution, cascades.len() as u32)?;
+        }
+        if locals.len() as u32 > self.local_shadow_maps.layers {
+            self.local_shadow_maps = ShadowMaps::new(&self.queue, self.shadow_render_pass.clone(), self.shadow_settings.local_resolution, locals.len() as u32)?;
+            self.local_shadow_owners = vec![None; locals.len()];
+        }
+        //a local layer only gets drawn if it's now somebody else's, its light changed or any mesh did
+        let geometry_changed = self.local_shadow_geometry != self.geometry_version;
+        let stale_layers = (0..locals.len())
+            .filter(|&layer| geometry_changed || self.local_shadow_owners[layer] != local_owners[layer])
+            .collect::<Vec<_>>();
+        //these only get recorded as drawn once the frame has been handed to the GPU, see the end of this function
+        let geometry_version = self.geometry_version;
+
+        let mut cascade_data = [fs::ty::ShadowCascade {
+            view_proj: Matrix4::identity().into(),
+            texel_world: 0.0,
+            depth_per_world: 0.0,
+            _dummy0: [0; 8],
+        }; MAX_SHADOW_LIGHTS * MAX_CASCADES];
+        for (data, cascade) in cascade_data.iter_mut().zip(cascades.iter()) {
+            data.view_proj = cascade.view_proj.into();
+            data.texel_world = cascade.texel_world;
+            data.depth_per_world = cascade.depth_per_world;
+        }
+        let mut local_data = [fs::ty::LocalShadow {
+            view_proj: Matrix4::identity().into(),
+            texel_scale: 0.0,
+            _dummy0: [0; 12],
+        }; MAX_LOCAL_SHADOW_LAYERS];
+        for (data, view) in local_data.iter_mut().zip(locals.iter()) {
+            data.view_proj = view.view_proj.into();
+            data.texel_scale = view.texel_scale;
+        }
+        let mut split_data = [0.0; MAX_CASCADES];
+        for (data, split) in split_data.iter_mut().zip(splits.iter()) {
+            *data = *split;
+        }
+        let forward = (self.camera.target - self.camera.eye).normalize();
+        let shadow_data = fs::ty::Shadows {
+            cascades: cascade_data,
+            locals: local_data,
+            splits: split_data,
+            view_forward: [forward.x, forward.y, forward.z, 0.0],
+            cascade_count: splits.len() as u32,
+            blend: self.shadow_settings.blend.clamp(0.0, 1.0),
+            softness: self.shadow_settings.softness.max(0.0),
+            depth_bias: self.shadow_settings.depth_bias,
+            normal_bias: self.shadow_settings.normal_bias,
+        };
+        let shadow_buffer = Arc::new(self.shadow_buffer.next(shadow_data)?);
+
+        //a buffer can't be empty, with no lights the shader gets one black one
+        if lights.is_empty() {
+            lights.push(light_data(&Light { intensity: 0.0, ..Light::default() }, -1));
+        }
+        let light_buffer = Arc::new(self.light_buffer.chunk(lights)?);
+
+        //the environment is the same for every object so it only goes in a buffer once a frame
+        let environment_data = fs::ty::Environment {
+            intensity: self.environment_intensity,
+            max_lod: self.environment.specular_levels.saturating_sub(1) as f32,
+            rotation_cos: self.environment_rotation.0.cos(),
+            rotation_sin: self.environment_rotation.0.sin(),
+        };
+        let environment_buffer = Arc::new(self.environment_buffer.next(environment_data)?);
+
+        //in your shader you have a "set" and "binding" variable. This line picks which set it will be in
+        let layout_hot = self.pipeline.descriptor_set_layout(0).unwrap();
+
+        //earlier we set up a pipeline to describe the order of operations we are doing
+        //this describes how to feed stuff in and out of that pipeline
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.device.clone(),
+            self.queue.family(),
+            vulkano::command_buffer::CommandBufferUsage::OneTimeSubmit
+        )
+        .map_err(RendererError::CommandBuffer)?;
+
+        //one clear value per attachment, the resolve target doesn't get cleared since the resolve overwrites all of it
+        //the background covers every pixel so the color clear never shows
+        let clear_values = if self.samples > 1 {
+            vec![[0.0, 0.0, 0.0, 1.0].into(), 1f32.into(), ClearValue::None]
+        } else {
+            vec![[0.0, 0.0, 0.0, 1.0].into(), 1f32.into()]
+        };
+
+        //one depth only pass per cascade and per stale local layer before anything else, every mesh goes in to every one
+        //the shadow pass doesn't read textures so cutouts cast solid shadows
+        let mut shadow_passes = cascades
+            .iter()
+            .enumerate()
+            .map(|(layer, cascade)| (self.shadow_maps.framebuffers[layer].clone(), self.shadow_pipeline.clone(), cascade.view_proj))
+            .collect::<Vec<_>>();
+        shadow_passes.extend(
+            stale_layers
+                .iter()
+                .map(|&layer| (self.local_shadow_maps.framebuffers[layer].clone(), self.local_shadow_pipeline.clone(), locals[layer].view_proj)),
+        );
+        for (framebuffer, pipeline, view_proj) in shadow_passes {
+            builder.begin_render_pass(framebuffer, SubpassContents::Inline, vec![1f32.into()])?;
+            for item in self.meshes.values() {
+                let params = shadow_vs::ty::ShadowParams {
+                    mvp: (view_proj * item.transform).into(),
+                };
+                match &item.index_buffer {
+                    IndexBuffer::U16(index_buffer) => builder.draw_indexed(
+                        pipeline.clone(),
+                        &DynamicState::none(),
+                        vec![item.vertex_buffer.clone()],
+                        index_buffer.clone(),
+                        (),
+                        params,
+                        vec![],
+                    ),
+                    IndexBuffer::U32(index_buffer) => builder.draw_indexed(
+                        pipeline.clone(),
+                        &DynamicState::none(),
+                        vec![item.vertex_buffer.clone()],
+                        index_buffer.clone(),
+                        (),
+                        params,
+                        vec![],
+                    ),
+                }?;
+            }
+            builder.end_render_pass()?;
+        }
+
+        //HERE IS THE ACTUAL OPERATIONS WE ARE RUNNING
+        builder
+            .begin_render_pass(
+                self.framebuffers[image_num].clone(),
+                SubpassContents::Inline,
+                clear_values,
+            )?;
+
+        //BACKGROUND
+        //the solid color and gradient only use the push constants but the maps still have to be bound
+        let max_lod = self.environment.specular_levels.saturating_sub(1) as f32;
+        let (mode, top, bottom, intensity, lod) = match self.background {
+            Background::Environment { blur, intensity } => (0, [0.0; 3], [0.0; 3], intensity * self.environment_intensity, blur.clamp(0.0, 1.0) * max_lod),
+            Background::Color(color) => (1, color, color, 1.0, 0.0),
+            Background::Gradient { top, bottom } => (2, top, bottom, 1.0, 0.0),
+        };
+        let inverse_view_proj = self.camera.rotation_proj(aspect_ratio).invert().unwrap_or_else(Matrix4::identity);
+        let sky_params = sky_fs::ty::Sky {
+            inverse_view_proj: inverse_view_proj.into(),
+            top: [top[0], top[1], top[2], 1.0],
+            bottom: [bottom[0], bottom[1], bottom[2], 1.0],
+            mode,
+            intensity,
+            lod,
+            rotation_cos: self.environment_rotation.0.cos(),
+            rotation_sin: self.environment_rotation.0.sin(),
+        };
+        let sky_set = Arc::new(
+            PersistentDescriptorSet::start(self.sky_pipeline.descriptor_set_layout(0).unwrap().clone())
+                .add_sampled_image(self.environment.radiance.clone(), self.clamp_sampler.clone())?
+                .add_sampled_image(self.environment.specular.clone(), self.clamp_sampler.clone())?
+                .build()?,
+        );
+        builder
+            .draw(
+                self.sky_pipeline.clone(),
+                &DynamicState::none(),
+                BufferlessVertices { vertices: 3, instances: 1 },
+                sky_set,
+                sky_params,
+                vec![],
+            )?;
+
+        for item in self.meshes.values() {
+            //this is the part of the uniform buffers that gets updated every frame
+            //apparently you are supposed to make a different uniform buffer for your frequently and infrequently changed variables
+            //but I couldn't figure that out so everything goes in the hot buffer.
+            //not sure why I need the dummy variables, something strange is going in with the SPIRV compiler
+            //type checker says I need em and it works when I add em, so I'm not going to worry about it
+            let uniform_data = vs::ty::Data {
+                mvp: (view_proj * item.transform).into(),
+                camloc: self.camera.eye.into(),
+                rotation: item.transform.into(),
+                view_proj: view_proj.into(),
+                viewport: [dimensions[0] as f32, dimensions[1] as f32],
+                height_scale: item.material.params.height_scale,
+                tess_edge: tess_edge.unwrap_or(1.0),
+                _dummy0: [0,0,0,0],
+            };
+
+            //jams the data in to the uniform buffer
+            let uniform_buffer_subbuffer = Arc::new(self.uniform_buffer.next(uniform_data)?);
+
+            //this is super inefficient here, should have the textures in a long lived Set (wrapper for uniform vals), and the matricies in a short lived one
+            //instead everything goes in the short lived one.
+            //THE ORDER THESE ARE ADDED IN CORRESPONDS TO THE binding FEILD IN THE SHADERS
+            //every slot in the data array has to have something in it, the unused ones repeat the first texture and never get sampled
+            //the builder's type changes with each image added so the DATA_SLOTS of them can't go in a loop
+            let data = |slot: usize| item.material.data.get(slot).unwrap_or(&item.material.data[0]).clone();
+            //same for the height map, the shader never reads it when params.height_scale is 0
+            let height = item.material.height.clone().unwrap_or_else(|| item.material.albedo.clone());
+            let mut params = item.material.params;
+            if tess_edge.is_some() {
+                params.shadow_offset = params.height_scale;
+                params.height_scale = 0.0;
+            }
+            let set_hot = Arc::new(
+                PersistentDescriptorSet::start(layout_hot.clone())
+                    .add_buffer(uniform_buffer_subbuffer)?
+                    .add_sampled_image(item.material.albedo.clone(), self.sampler.clone())?
+                    .add_sampled_image(item.material.normalmap.clone(), self.sampler.clone())?
+                    .add_sampled_image(item.material.emission.clone(), self.sampler.clone())?
+                    .enter_array()?
+                    .add_sampled_image(data(0), self.sampler.clone())?
+                    .add_sampled_image(data(1), self.sampler.clone())?
+                    .add_sampled_image(data(2), self.sampler.clone())?
+                    .add_sampled_image(data(3), self.sampler.clone())?
+                    .leave_array()?
+                    .add_sampled_image(height, self.sampler.clone())?
+                    .add_sampled_image(self.environment.irradiance.clone(), self.clamp_sampler.clone())?
+                    .add_sampled_image(self.environment.specular.clone(), self.clamp_sampler.clone())?
+                    .add_sampled_image(self.brdf_lut.clone(), self.clamp_sampler.clone())?
+                    .add_buffer(environment_buffer.clone())?
+                    .add_buffer(light_buffer.clone())?
+                    .add_sampled_image(self.shadow_maps.array.clone(), self.shadow_sampler.clone())?
+                    .add_buffer(shadow_buffer.clone())?
+                    .add_sampled_image(self.local_shadow_maps.array.clone(), self.shadow_sampler.clone())?
+                    .build()?,
+            );
+
+            match &item.index_buffer {
+                IndexBuffer::U16(index_buffer) => builder.draw_indexed(
+                    self.pipeline.clone(),
+                    &DynamicState::none(),
+                    vec![item.vertex_buffer.clone()],
+                    index_buffer.clone(),
+                    set_hot,
+                    params,
+                    vec![],
+                ),
+                IndexBuffer::U32(index_buffer) => builder.draw_indexed(
+                    self.pipeline.clone(),
+                    &DynamicState::none(),
+                    vec![item.vertex_buffer.clone()],
+                    index_buffer.clone(),
+                    set_hot,
+                    params,
+                    vec![],
+                ),
+            }?;
+        }
+
+        builder.end_render_pass()?;
+
+        let command_buffer = builder.build()?;
+
+        //SUBMIT TO THE GPU AND GET A FUTURE BACK
+        let future = self.previous_frame_end
+            .take()
+            .unwrap_or_else(|| sync::now(self.device.clone()).boxed())
+            .join(acquire_future)
+            .then_execute(self.queue.clone(), command_buffer)?
+            .then_swapchain_present(self.queue.clone(), self.swapchain.clone(), image_num)
+            .then_signal_fence_and_flush();
+
+        //if the future looks good, save it and loop
+        //if the future lookds bad, rebuild the swapchain lol
+        //if the future looks really bad hand the error back, the next frame starts fresh
+        match future {
+            Ok(future) => {
+                self.previous_frame_end = Some(future.boxed());
+                //the local maps really have been drawn now, anything that returned early leaves them stale for the next frame to redo
+                self.local_shadow_owners[..local_owners.len()].clone_from_slice(&local_owners);
+                self.local_shadow_geometry = geometry_version;
+                Ok(())
+            },
+            Err(FlushError::OutOfDate) => {
+                self.recreate_swapchain = true;
+                self.previous_frame_end = Some(sync::now(self.device.clone()).boxed());
+                Ok(())
+            },
+            Err(e) => {
+                self.previous_frame_end = Some(sync::now(self.device.clone()).boxed());
+                Err(e.into())
+            }
+        }
+    }
+}
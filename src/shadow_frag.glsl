@@ -0,0 +1,6 @@
+#version 450
+
+// nothing to write, the depth is all the shadow pass keeps
+
+void main() {
+}
@@ -0,0 +1,14 @@
+#version 450
+
+// depth only, draws a mesh in to one layer of the shadow map array, see shadows.rs
+
+layout(location = 0) in vec3 position;
+
+// the cascade's light view projection times the mesh's model matrix
+layout(push_constant) uniform ShadowParams {
+    mat4 mvp;
+} params;
+
+void main() {
+    gl_Position = params.mvp * vec4(position, 1.0);
+}
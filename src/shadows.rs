@@ -0,0 +1,324 @@
+use std::sync::Arc;
+
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Rad, Transform, Vector3, Vector4};
+use vulkano::{device::{Device, Queue}, format::Format, instance::PhysicalDevice, sampler::Filter, image::{ImageCreateFlags, ImageDimensions, ImageUsage, StorageImage, view::{ImageView, ImageViewType}}, render_pass::{Framebuffer, FramebufferAbstract, RenderPass}};
+
+use crate::error::RendererError;
+use crate::renderer::{Camera, LightKind};
+
+//Cascaded shadow maps for directional lights
+//the part of the view frustum shadows reach gets cut in to slices (cascades) along the view direction,
+//each slice gets its own orthographic map from the light so close up shadows get most of the resolution
+//every cascade of every shadowed light is one layer of a single depth image array, the main pass picks the layer per pixel
+
+//Point and spot lights get perspective maps in a second array (the local maps) that only get redrawn when something changes
+//a spot light is one layer looking down its cone, a point light is six looking out the faces of a cube
+//they don't depend on the camera so unlike the cascades they can be kept from one frame to the next
+
+//the shader has room for this many, see the Shadows block in pbr_frag.glsl
+pub const MAX_SHADOW_LIGHTS: usize = 4;
+pub const MAX_CASCADES: usize = 4;
+//layers in the local maps, enough for 4 point lights or 24 spots, see the Shadows block again
+pub const MAX_LOCAL_SHADOW_LAYERS: usize = 24;
+
+//16 bits is the only depth format every GPU has to be able to render to and sample,
+//filtering it is optional, see shadow_filter
+pub const SHADOW_FORMAT: Format = Format::D16Unorm;
+
+//Linear gives a free 2x2 PCF on each compare lookup but the GPU doesn't have to support it for depth formats
+//without it every lookup is a hard in or out, the Poisson taps still soften the edge
+pub fn shadow_filter(physical: PhysicalDevice) -> Filter {
+    if SHADOW_FORMAT.properties(physical).optimal_tiling_features.sampled_image_filter_linear {
+        Filter::Linear
+    } else {
+        println!("WARNING: {} can't filter {:?} shadow maps, shadow edges will be harder", physical.name(), SHADOW_FORMAT);
+        Filter::Nearest
+    }
+}
+
+//How the shadows look and what they cost
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShadowSettings {
+    pub enabled: bool,
+    //width and height of each cascade's map in texels
+    pub resolution: u32,
+    //the same for each spot light's map and each face of a point light's cube
+    pub local_resolution: u32,
+    //how many slices the view gets cut in to, 1 to MAX_CASCADES
+    pub cascades: u32,
+    //shadows stop this far from the camera (or at its far plane if that's closer)
+    pub distance: f32,
+    //0 cuts the distance in to even slices, 1 makes each slice a fixed multiple of the last (lots of resolution close up)
+    pub split_lambda: f32,
+    //how much of the end of each cascade fades in to the next one, 0 to 1, hides the seams
+    pub blend: f32,
+    //radius of the PCF filter in texels, bigger is softer
+    pub softness: f32,
+    //in texels of the cascade being read, the depth bias grows on surfaces turned away from the light
+    //and the normal bias pushes the lookup off the surface, together they stop surfaces shadowing themselves (acne)
+    pub depth_bias: f32,
+    pub normal_bias: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            enabled: true,
+            resolution: 2048,
+            local_resolution: 1024,
+            cascades: 4,
+            distance: 20.0,
+            split_lambda: 0.75,
+            blend: 0.1,
+            softness: 1.5,
+            depth_bias: 1.0,
+            normal_bias: 1.5,
+        }
+    }
+}
+
+//One cascade of one light
+#[derive(Clone, Copy, Debug)]
+pub struct Cascade {
+    //world space to the map's clip space, z already 0 to 1 the way Vulkan wants it
+    pub view_proj: Matrix4<f32>,
+    //how big one texel is in world units and how much depth one world unit is, for the biases
+    pub texel_world: f32,
+    pub depth_per_world: f32,
+}
+
+//One layer of the local maps, a spot light or one face of a point light's cube
+#[derive(Clone, Copy, Debug)]
+pub struct LocalView {
+    //world space to the layer's clip space, z 0 to 1 like the cascades
+    pub view_proj: Matrix4<f32>,
+    //how big one texel is in world units one unit away from the light, it grows with the distance
+    pub texel_scale: f32,
+}
+
+//cgmath makes OpenGL projections with z from -1 to 1, Vulkan clips z to 0 to 1
+#[rustfmt::skip]
+const OPENGL_TO_VULKAN: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+//The depth only render pass the maps get drawn with
+//the image stays in the general layout outside of the pass, that's what a StorageImage expects
+//(ImageLayout is in scope inside the macro)
+pub fn shadow_render_pass(device: Arc<Device>) -> Result<Arc<RenderPass>, RendererError> {
+    let render_pass = vulkano::single_pass_renderpass!(device,
+        attachments: {
+            depth: {
+                load: Clear,
+                store: Store,
+                format: SHADOW_FORMAT,
+                samples: 1,
+                initial_layout: ImageLayout::General,
+                final_layout: ImageLayout::General,
+            }
+        },
+        pass: {
+            color: [],
+            depth_stencil: {depth}
+        }
+    )?;
+    Ok(Arc::new(render_pass))
+}
+
+//The depth image array and a framebuffer for each of its layers
+pub struct ShadowMaps {
+    pub resolution: u32,
+    pub layers: u32,
+    //every layer at once, what the main pass samples
+    pub array: Arc<ImageView<Arc<StorageImage>>>,
+    //one per layer, what the shadow pass draws in to
+    pub framebuffers: Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
+}
+
+impl ShadowMaps {
+    pub fn new(queue: &Arc<Queue>, render_pass: Arc<RenderPass>, resolution: u32, layers: u32) -> Result<ShadowMaps, RendererError> {
+        let layers = layers.max(1);
+        let image = StorageImage::with_usage(
+            queue.device().clone(),
+            ImageDimensions::Dim2d { width: resolution, height: resolution, array_layers: layers },
+            SHADOW_FORMAT,
+            ImageUsage { sampled: true, depth_stencil_attachment: true, ..ImageUsage::none() },
+            ImageCreateFlags::none(),
+            Some(queue.family()),
+        )?;
+        let array = ImageView::start(image.clone()).with_type(ImageViewType::Dim2dArray).build()?;
+        let framebuffers = (0..layers)
+            .map(|layer| {
+                let view = ImageView::start(image.clone()).with_type(ImageViewType::Dim2d).with_array_layers(layer..layer + 1).build()?;
+                Ok(Arc::new(Framebuffer::start(render_pass.clone()).add(view)?.build()?) as Arc<dyn FramebufferAbstract + Send + Sync>)
+            })
+            .collect::<Result<Vec<_>, RendererError>>()?;
+        Ok(ShadowMaps { resolution, layers, array, framebuffers })
+    }
+}
+
+//Where each cascade ends, as a distance along the view direction
+//the practical split scheme, a mix of even and logarithmic slices
+pub fn cascade_splits(near: f32, far: f32, count: usize, lambda: f32) -> Vec<f32> {
+    (1..=count)
+        .map(|i| {
+            let p = i as f32 / count as f32;
+            let log = near * (far / near).powf(p);
+            let even = near + (far - near) * p;
+            lambda * log + (1.0 - lambda) * even
+        })
+        .collect()
+}
+
+//The smallest sphere around the part of the view frustum between two distances, as a distance along the view and a radius
+//a sphere's size doesn't change when the camera turns, which keeps the shadow edges from crawling
+fn slice_sphere(near: f32, far: f32, tan_squared: f32) -> (f32, f32) {
+    //equally far from the near and far corners, or the middle of the far face if the slice is too wide for that
+    let center = ((far + near) * (1.0 + tan_squared) / 2.0).min(far);
+    let radius = ((far - center).powi(2) + far * far * tan_squared).sqrt();
+    (center, radius)
+}
+
+//Fits every cascade of one light to the camera, scene is a sphere around everything that can cast a shadow
+//direction is the way the light travels, the splits come back with the cascades
+pub fn fit_cascades(camera: &Camera, aspect_ratio: f32, direction: Vector3<f32>, settings: &ShadowSettings, scene: (Point3<f32>, f32)) -> (Vec<Cascade>, Vec<f32>) {
+    let count = (settings.cascades as usize).clamp(1, MAX_CASCADES);
+    let near = camera.near.max(1e-4);
+    let far = settings.distance.min(camera.far).max(near * 2.0);
+    let splits = cascade_splits(near, far, count, settings.split_lambda.clamp(0.0, 1.0));
+
+    let forward = (camera.target - camera.eye).normalize();
+    let tan_y = (camera.fov_y.0 / 2.0).tan();
+    let tan_squared = tan_y * tan_y * (1.0 + aspect_ratio * aspect_ratio);
+
+    //a rotation in to the light's space, looking the way the light travels
+    let direction = direction.normalize();
+    let up = if direction.y.abs() < 0.99 { Vector3::unit_y() } else { Vector3::unit_x() };
+    let light_view = Matrix4::look_at_rh(Point3::origin(), Point3::from_vec(direction), up);
+    let scene_center = light_view.transform_point(scene.0);
+
+    let resolution = settings.resolution.max(1) as f32;
+    let mut start = near;
+    let cascades = splits
+        .iter()
+        .map(|&end| {
+            let (along, radius) = slice_sphere(start, end, tan_squared);
+            start = end;
+            //rounded up so tiny changes in the camera don't change the texel size
+            let radius = (radius * 16.0).ceil() / 16.0;
+            let texel = 2.0 * radius / resolution;
+
+            //the center only moves in whole texels, so shadows stay put while the camera moves
+            let center = light_view.transform_point(camera.eye + forward * along);
+            let x = (center.x / texel).floor() * texel;
+            let y = (center.y / texel).floor() * texel;
+
+            //the light looks down -z, the near plane gets pulled back far enough to take in everything
+            //between the light and the slice so things outside the view still cast in to it
+            let near_plane = (-center.z - radius).min(-scene_center.z - scene.1);
+            let far_plane = -center.z + radius;
+            let proj = cgmath::ortho(x - radius, x + radius, y - radius, y + radius, near_plane, far_plane);
+            Cascade {
+                view_proj: OPENGL_TO_VULKAN * proj * light_view,
+                texel_world: texel,
+                depth_per_world: 1.0 / (far_plane - near_plane).max(1e-4),
+            }
+        })
+        .collect();
+    (cascades, splits)
+}
+
+//A sphere around a mesh's vertices, the middle of their bounding box and the furthest one from it
+pub fn bounding_sphere<I: Iterator<Item = Point3<f32>>>(points: I) -> (Point3<f32>, f32) {
+    let points = points.collect::<Vec<_>>();
+    if points.is_empty() {
+        return (Point3::origin(), 0.0);
+    }
+    let mut min = points[0];
+    let mut max = points[0];
+    for p in points.iter() {
+        min = Point3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+        max = Point3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+    }
+    let center = min.midpoint(max);
+    let radius = points.iter().map(|p| (p - center).magnitude()).fold(0.0, f32::max);
+    (center, radius)
+}
+
+//a sphere moved by a model matrix, scaled by its largest axis so it always still covers the mesh
+pub fn transform_sphere(sphere: (Point3<f32>, f32), transform: &Matrix4<f32>) -> (Point3<f32>, f32) {
+    let scale = [transform.x, transform.y, transform.z]
+        .iter()
+        .map(|axis: &Vector4<f32>| axis.truncate().magnitude())
+        .fold(0.0, f32::max);
+    (transform.transform_point(sphere.0), sphere.1 * scale)
+}
+
+//one sphere around a set of them
+pub fn merge_spheres<I: Iterator<Item = (Point3<f32>, f32)>>(spheres: I) -> Option<(Point3<f32>, f32)> {
+    spheres.fold(None, |acc, (center, radius)| match acc {
+        None => Some((center, radius)),
+        Some((c, r)) => {
+            let d = (center - c).magnitude();
+            if d + radius <= r {
+                Some((c, r))
+            } else if d + r <= radius {
+                Some((center, radius))
+            } else {
+                let new_radius = (d + r + radius) / 2.0;
+                Some((c + (center - c) * ((new_radius - r) / d), new_radius))
+            }
+        }
+    })
+}
+
+//The views a point or spot light's shadows get drawn from, directional lights don't have any
+//without a range the maps reach just past the far side of the scene, near as far in as the scene starts
+pub fn local_views(light: &LightKind, settings: &ShadowSettings, scene: (Point3<f32>, f32)) -> Option<Vec<LocalView>> {
+    let (position, range) = match *light {
+        LightKind::Directional { .. } => return None,
+        LightKind::Point { position, range } | LightKind::Spot { position, range, .. } => (position, range),
+    };
+    let to_scene = (scene.0 - position).magnitude();
+    let far = if range > 0.0 { range } else { (to_scene + scene.1).max(0.1) };
+    //16 bits doesn't go far with a perspective divide, keeping the near plane out as far as it can go helps a lot
+    let near = (to_scene - scene.1).max(far * 0.002).min(far * 0.5);
+
+    //a few texels wider than they need to be so the PCF filter doesn't run off the edge at a cube's seams
+    let resolution = settings.local_resolution.max(1) as f32;
+    let margin = 1.0 + 2.0 * (settings.softness.max(0.0) + 2.0) / resolution;
+    let view = |direction: Vector3<f32>, up: Vector3<f32>, tan_half: f32| {
+        let proj = cgmath::perspective(Rad(2.0 * tan_half.atan()), 1.0, near, far);
+        LocalView {
+            view_proj: OPENGL_TO_VULKAN * proj * Matrix4::look_at_rh(position, position + direction, up),
+            texel_scale: 2.0 * tan_half / resolution,
+        }
+    };
+
+    match *light {
+        LightKind::Spot { direction, outer_angle, .. } => {
+            let direction = if direction.magnitude2() > 0.0 { direction.normalize() } else { -Vector3::unit_y() };
+            let up = if direction.y.abs() < 0.99 { Vector3::unit_y() } else { Vector3::unit_x() };
+            //past 80 degrees a single map gets too stretched, the edges of wider cones just don't get shadows
+            let outer = outer_angle.0.clamp(0.01, 80f32.to_radians());
+            Some(vec![view(direction, up, outer.tan() * margin)])
+        }
+        //same order the shader picks the face in, +X -X +Y -Y +Z -Z
+        _ => Some(
+            [
+                (Vector3::unit_x(), -Vector3::unit_y()),
+                (-Vector3::unit_x(), -Vector3::unit_y()),
+                (Vector3::unit_y(), Vector3::unit_z()),
+                (-Vector3::unit_y(), -Vector3::unit_z()),
+                (Vector3::unit_z(), -Vector3::unit_y()),
+                (-Vector3::unit_z(), -Vector3::unit_y()),
+            ]
+            .iter()
+            .map(|&(direction, up)| view(direction, up, margin))
+            .collect(),
+        ),
+    }
+}
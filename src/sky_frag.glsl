@@ -0,0 +1,51 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+// the tonemapper specialization constant and its curves, the environment gets tonemapped the same as everything else
+#include "tonemap.glsl"
+
+layout(location = 0) in vec2 ndc_in;
+
+// the full resolution environment for a sharp background and the prefiltered one for a blurred one, see ibl.rs
+layout(set = 0, binding = 0) uniform samplerCube radiance_t;
+layout(set = 0, binding = 1) uniform samplerCube prefiltered_t;
+
+// see Background in renderer.rs, mode 0 = environment, 1 = solid color (top), 2 = gradient
+// inverse_view_proj has no translation in it so it turns a point on the screen straight in to a direction
+// lod 0 reads the sharp map, rotation turns the environment around +Y like it does in pbr_frag.glsl
+layout(push_constant) uniform Sky {
+    mat4 inverse_view_proj;
+    vec4 top;
+    vec4 bottom;
+    uint mode;
+    float intensity;
+    float lod;
+    float rotation_cos;
+    float rotation_sin;
+} sky;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    vec4 far = sky.inverse_view_proj * vec4(ndc_in, 1.0, 1.0);
+    vec3 dir = normalize(far.xyz / far.w);
+
+    // the colors are already what should end up on screen, so they skip the tonemapper and gamma
+    if (sky.mode == 1) {
+        f_color = vec4(sky.top.rgb, 1.0);
+        return;
+    } else if (sky.mode == 2) {
+        f_color = vec4(mix(sky.bottom.rgb, sky.top.rgb, clamp(dir.y * 0.5 + 0.5, 0.0, 1.0)), 1.0);
+        return;
+    }
+
+    vec3 d = vec3(sky.rotation_cos * dir.x - sky.rotation_sin * dir.z, dir.y, sky.rotation_sin * dir.x + sky.rotation_cos * dir.z);
+    vec3 color;
+    if (sky.lod > 0.0) {
+        color = textureLod(prefiltered_t, d, sky.lod).rgb;
+    } else {
+        color = texture(radiance_t, d).rgb;
+    }
+    color = tonemap(color * sky.intensity);
+    f_color = vec4(pow(color, vec3(1.0/2.2)), 1.0);
+}
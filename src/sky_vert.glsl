@@ -0,0 +1,12 @@
+#version 450
+
+// one triangle that covers the whole screen, no vertex buffer needed
+// the vertices land on (-1,-1), (3,-1) and (-1,3), the parts off screen get clipped
+
+layout(location = 0) out vec2 ndc_out;
+
+void main() {
+    vec2 ndc = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2) * 2.0 - 1.0;
+    ndc_out = ndc;
+    gl_Position = vec4(ndc, 1.0, 1.0);
+}
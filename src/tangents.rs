@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+use crate::vertex::Vertex;
+
+//mikktspace works face corner by face corner, so we hand it the index buffer and let it
+//write a tangent for each corner, then sort out which vertices need splitting afterwards
+struct MikkGeometry<'a> {
+    vertices: &'a [Vertex],
+    indices: &'a [u32],
+    corner_tangents: Vec<[f32; 4]>,
+}
+
+impl<'a> MikkGeometry<'a> {
+    fn vertex(&self, face: usize, vert: usize) -> &Vertex {
+        &self.vertices[self.indices[face * 3 + vert] as usize]
+    }
+}
+
+impl<'a> mikktspace::Geometry for MikkGeometry<'a> {
+    fn num_faces(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        let p = self.vertex(face, vert).position;
+        [p.0, p.1, p.2]
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        let n = self.vertex(face, vert).normal;
+        [n.0, n.1, n.2]
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        let uv = self.vertex(face, vert).uv;
+        [uv.0, uv.1]
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.corner_tangents[face * 3 + vert] = tangent;
+    }
+}
+
+//Fills in the tangent of every vertex with the same results Blender and Substance bake their normal maps against
+//the w component is the handedness of the bitangent, the shader rebuilds it as cross(N, T) * w
+//vertices shared by faces that disagree on the tangent (mirrored UVs and seams) get split so each side keeps its own
+pub fn generate_tangents(vertices: &mut Vec<Vertex>, indices: &mut [u32]) -> bool {
+    let mut geometry = MikkGeometry {
+        vertices: &vertices[..],
+        indices: &indices[..],
+        corner_tangents: vec![[0.0; 4]; indices.len()],
+    };
+
+    if !mikktspace::generate_tangents(&mut geometry) {
+        println!("mikktspace failed to generate tangents, normal mapping will fall back to screen space derivatives");
+        return false;
+    }
+
+    let corner_tangents = geometry.corner_tangents;
+
+    //first corner to claim a vertex sets its tangent, any corner that disagrees gets a copy of the vertex
+    let mut claimed = vec![false; vertices.len()];
+    let mut splits = HashMap::<(u32, [u32; 4]), u32>::new();
+    for (idx, tangent) in indices.iter_mut().zip(corner_tangents.iter()) {
+        let v = *idx as usize;
+        let t = (tangent[0], tangent[1], tangent[2], tangent[3]);
+
+        if !claimed[v] {
+            claimed[v] = true;
+            vertices[v].tangent = t;
+        } else if vertices[v].tangent != t {
+            let key = (*idx, [t.0.to_bits(), t.1.to_bits(), t.2.to_bits(), t.3.to_bits()]);
+            *idx = *splits.entry(key).or_insert_with(|| {
+                let mut split = vertices[v].clone();
+                split.tangent = t;
+                vertices.push(split);
+                (vertices.len() - 1) as u32
+            });
+        }
+    }
+
+    true
+}
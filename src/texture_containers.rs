@@ -0,0 +1,394 @@
+use std::{convert::TryFrom, fs, path::Path};
+use std::io::Write;
+
+use vulkano::format::Format;
+
+use crate::error::RendererError;
+use crate::mipmaps::mip_count;
+use crate::render_helpers::TextureRole;
+
+//KTX2 and DDS, the formats textures actually ship in
+//they hold data that's ready for the GPU as is, usually block compressed and with every mip already made
+//only plain 2D textures are handled, no arrays, cubemaps or supercompression (Basis/zstd)
+
+//file types load_container knows how to read
+pub const CONTAINER_EXTENSIONS: &[&str] = &["ktx2", "dds"];
+
+//A texture straight out of a container, levels[0] is full size and each one after is half the last
+pub struct ContainerImage {
+    pub format: Format,
+    pub width: u32,
+    pub height: u32,
+    pub levels: Vec<Vec<u8>>,
+}
+
+pub fn is_container(path: &str) -> bool {
+    let ext = Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    CONTAINER_EXTENSIONS.contains(&ext.as_str())
+}
+
+pub fn load_container(path: &str, role: TextureRole) -> Result<ContainerImage, RendererError> {
+    let bytes = fs::read(path).map_err(|e| RendererError::io(path, e))?;
+    let image = if bytes.starts_with(KTX2_IDENTIFIER) {
+        parse_ktx2(&bytes)
+    } else if bytes.starts_with(b"DDS ") {
+        parse_dds(&bytes, role)
+    } else {
+        Err("not a KTX2 or DDS file".to_string())
+    };
+    let image = image.map_err(|msg| RendererError::decode(path, msg))?;
+    println!("Loaded {:?}: {:?}, {}x{}, {} mip levels", path, image.format, image.width, image.height, image.levels.len());
+    Ok(image)
+}
+
+//bytes for one mip level of the given size, block formats round up to whole 4x4 blocks
+pub fn level_size(format: Format, width: u32, height: u32) -> Option<usize> {
+    let (bw, bh) = format.block_dimensions();
+    let blocks = (width.div_ceil(bw) as usize).checked_mul(height.div_ceil(bh) as usize)?;
+    format.size().and_then(|size| blocks.checked_mul(size))
+}
+
+//the formats we'll take out of a container, by their Vulkan number (what KTX2 stores)
+const VK_FORMATS: &[(u32, Format)] = &[
+    (9, Format::R8Unorm),
+    (16, Format::R8G8Unorm),
+    (37, Format::R8G8B8A8Unorm),
+    (43, Format::R8G8B8A8Srgb),
+    (97, Format::R16G16B16A16Sfloat),
+    (109, Format::R32G32B32A32Sfloat),
+    (131, Format::BC1_RGBUnormBlock),
+    (132, Format::BC1_RGBSrgbBlock),
+    (133, Format::BC1_RGBAUnormBlock),
+    (134, Format::BC1_RGBASrgbBlock),
+    (135, Format::BC2UnormBlock),
+    (136, Format::BC2SrgbBlock),
+    (137, Format::BC3UnormBlock),
+    (138, Format::BC3SrgbBlock),
+    (139, Format::BC4UnormBlock),
+    (140, Format::BC4SnormBlock),
+    (141, Format::BC5UnormBlock),
+    (142, Format::BC5SnormBlock),
+    (143, Format::BC6HUfloatBlock),
+    (144, Format::BC6HSfloatBlock),
+    (145, Format::BC7UnormBlock),
+    (146, Format::BC7SrgbBlock),
+];
+
+fn u32_at(bytes: &[u8], at: usize) -> Result<u32, String> {
+    bytes.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).ok_or_else(|| "file is cut short".to_string())
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> Result<u64, String> {
+    Ok(u32_at(bytes, at)? as u64 | (u32_at(bytes, at + 4)? as u64) << 32)
+}
+
+//the header's size and level count, before anything gets allocated for them
+//a broken or hostile file can ask for billions of levels, past the 1x1 one the shifts stop making sense
+fn check_dimensions(width: u32, height: u32, levels: u32) -> Result<(), String> {
+    if width == 0 || height == 0 {
+        return Err(format!("{}x{} isn't a valid texture size", width, height));
+    }
+    if levels > mip_count(width, height) {
+        return Err(format!("{} mip levels is more than a {}x{} texture can have", levels, width, height));
+    }
+    Ok(())
+}
+
+//pulls each level out of the file, checking each one is as big as the format says it should be
+fn read_levels(bytes: &[u8], format: Format, width: u32, height: u32, ranges: &[(usize, usize)]) -> Result<Vec<Vec<u8>>, String> {
+    ranges
+        .iter()
+        .enumerate()
+        .map(|(level, (offset, len))| {
+            let shift = |size: u32| size.checked_shr(level as u32).unwrap_or(0).max(1);
+            let (w, h) = (shift(width), shift(height));
+            let expected = level_size(format, w, h).ok_or_else(|| format!("{:?} has no fixed size or {}x{} is too big", format, w, h))?;
+            if *len < expected {
+                return Err(format!("mip level {} is {} bytes, {}x{} {:?} needs {}", level, len, w, h, format, expected));
+            }
+            let end = offset.checked_add(expected).ok_or_else(|| format!("mip level {} runs past the end of the file", level))?;
+            bytes
+                .get(*offset..end)
+                .map(|b| b.to_vec())
+                .ok_or_else(|| format!("mip level {} runs past the end of the file", level))
+        })
+        .collect()
+}
+
+//KTX2
+const KTX2_IDENTIFIER: &[u8] = &[0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A];
+
+fn parse_ktx2(bytes: &[u8]) -> Result<ContainerImage, String> {
+    let vk_format = u32_at(bytes, 12)?;
+    let width = u32_at(bytes, 20)?;
+    //1D textures store a height of 0
+    let height = u32_at(bytes, 24)?.max(1);
+    let depth = u32_at(bytes, 28)?;
+    let layers = u32_at(bytes, 32)?;
+    let faces = u32_at(bytes, 36)?;
+    let level_count = u32_at(bytes, 40)?.max(1);
+    let supercompression = u32_at(bytes, 44)?;
+
+    if vk_format == 0 || supercompression != 0 {
+        return Err("Basis/zstd supercompressed KTX2 isn't supported, re-export it as plain BC".to_string());
+    }
+    if depth > 1 || layers > 1 || faces != 1 {
+        return Err("only 2D KTX2 textures are supported (no arrays, cubemaps or 3D)".to_string());
+    }
+    let format = VK_FORMATS
+        .iter()
+        .find(|(n, _)| *n == vk_format)
+        .map(|(_, f)| *f)
+        .ok_or_else(|| format!("unsupported KTX2 vkFormat {}", vk_format))?;
+    check_dimensions(width, height, level_count)?;
+
+    //the level index comes right after the header, 3 u64s a level: offset, length, uncompressed length
+    let ranges = (0..level_count as usize)
+        .map(|level| {
+            let offset = usize::try_from(u64_at(bytes, 80 + level * 24)?).map_err(|_| format!("mip level {} starts past the end of the file", level))?;
+            let len = usize::try_from(u64_at(bytes, 88 + level * 24)?).map_err(|_| format!("mip level {} is too big", level))?;
+            Ok((offset, len))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let levels = read_levels(bytes, format, width, height, &ranges)?;
+
+    Ok(ContainerImage { format, width, height, levels })
+}
+
+//the Vulkan number for a format, for writing KTX2
+fn vk_format_number(format: Format) -> Option<u32> {
+    VK_FORMATS.iter().find(|(_, f)| *f == format).map(|(n, _)| *n)
+}
+
+//Data Format Descriptor color models and how many channels each block holds, only the formats the processor writes
+//see the Khronos Data Format spec, KTX2 requires one of these even though the vkFormat already says it all
+fn dfd_model(format: Format) -> Option<(u32, u32)> {
+    match format {
+        Format::BC4UnormBlock => Some((131, 1)),
+        Format::BC5UnormBlock => Some((132, 2)),
+        Format::BC7UnormBlock | Format::BC7SrgbBlock => Some((134, 1)),
+        _ => None,
+    }
+}
+
+fn basic_dfd(format: Format) -> Option<Vec<u8>> {
+    let (model, samples) = dfd_model(format)?;
+    let block_bytes = format.size()? as u32;
+    //transfer function 1 is linear, 2 is sRGB, primaries 1 is BT.709
+    let transfer = if format == Format::BC7SrgbBlock { 2 } else { 1 };
+    let block_size = 24 + 16 * samples;
+
+    let mut words = vec![
+        block_size + 4,
+        0,
+        2 | block_size << 16,
+        model | 1 << 8 | transfer << 16,
+        //4x4x1x1 blocks, stored as size - 1
+        3 | 3 << 8,
+        block_bytes,
+        0,
+    ];
+    //BC5 is two BC4 blocks back to back, red then green
+    let bits_per_sample = block_bytes * 8 / samples;
+    for channel in 0..samples {
+        words.push((channel * bits_per_sample) | ((bits_per_sample - 1) << 16) | (channel << 24));
+        words.push(0);
+        words.push(0);
+        words.push(u32::MAX);
+    }
+    Some(words.iter().flat_map(|w| w.to_le_bytes()).collect())
+}
+
+//Writes a plain 2D KTX2, levels[0] is full size like ContainerImage
+//levels go in the file smallest first like the spec asks for, so a streaming loader gets something to show early
+pub fn write_ktx2<P: AsRef<Path>>(path: P, image: &ContainerImage) -> Result<(), RendererError> {
+    let path = path.as_ref();
+    let vk_format = vk_format_number(image.format).ok_or_else(|| RendererError::decode(path, format!("can't write {:?} to KTX2", image.format)))?;
+    let dfd = basic_dfd(image.format).ok_or_else(|| RendererError::decode(path, format!("no data format descriptor for {:?}", image.format)))?;
+    let level_count = image.levels.len();
+
+    let dfd_offset = 80 + level_count * 24;
+    let mut data_offset = dfd_offset + dfd.len();
+    //every level has to start on a multiple of the block size
+    let align = image.format.size().unwrap_or(16);
+    let mut offsets = vec![0; level_count];
+    for level in (0..level_count).rev() {
+        data_offset = data_offset.div_ceil(align) * align;
+        offsets[level] = data_offset;
+        data_offset += image.levels[level].len();
+    }
+
+    let mut out = Vec::with_capacity(data_offset);
+    out.extend_from_slice(KTX2_IDENTIFIER);
+    //vkFormat, typeSize, width, height, depth, layers, faces, levels, supercompression
+    for v in [vk_format, 1, image.width, image.height, 0, 0, 1, level_count as u32, 0] {
+        out.extend_from_slice(&v.to_le_bytes());
+    }
+    //where the DFD, key/value data and supercompression data are, we only have a DFD
+    out.extend_from_slice(&(dfd_offset as u32).to_le_bytes());
+    out.extend_from_slice(&(dfd.len() as u32).to_le_bytes());
+    out.extend_from_slice(&[0; 24]);
+    for (level, data) in image.levels.iter().enumerate() {
+        out.extend_from_slice(&(offsets[level] as u64).to_le_bytes());
+        out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    }
+    out.extend_from_slice(&dfd);
+    for level in (0..level_count).rev() {
+        out.resize(offsets[level], 0);
+        out.extend_from_slice(&image.levels[level]);
+    }
+
+    let mut file = fs::File::create(path).map_err(|e| RendererError::io(path, e))?;
+    file.write_all(&out).map_err(|e| RendererError::io(path, e))
+}
+
+//DDS
+const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+const DDPF_ALPHAPIXELS: u32 = 0x1;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_VOLUME: u32 = 0x200000;
+const DDS_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+
+//DXGI formats from the DX10 extended header, typeless ones get resolved by the role
+fn dxgi_format(dxgi: u32, role: TextureRole) -> Option<Format> {
+    let srgb = role == TextureRole::Color;
+    Some(match dxgi {
+        2 => Format::R32G32B32A32Sfloat,
+        10 => Format::R16G16B16A16Sfloat,
+        28 => Format::R8G8B8A8Unorm,
+        29 => Format::R8G8B8A8Srgb,
+        27 if srgb => Format::R8G8B8A8Srgb,
+        27 => Format::R8G8B8A8Unorm,
+        70 if srgb => Format::BC1_RGBASrgbBlock,
+        70 | 71 => Format::BC1_RGBAUnormBlock,
+        72 => Format::BC1_RGBASrgbBlock,
+        73 if srgb => Format::BC2SrgbBlock,
+        73 | 74 => Format::BC2UnormBlock,
+        75 => Format::BC2SrgbBlock,
+        76 if srgb => Format::BC3SrgbBlock,
+        76 | 77 => Format::BC3UnormBlock,
+        78 => Format::BC3SrgbBlock,
+        79 | 80 => Format::BC4UnormBlock,
+        81 => Format::BC4SnormBlock,
+        82 | 83 => Format::BC5UnormBlock,
+        84 => Format::BC5SnormBlock,
+        94 | 95 => Format::BC6HUfloatBlock,
+        96 => Format::BC6HSfloatBlock,
+        97 if srgb => Format::BC7SrgbBlock,
+        97 | 98 => Format::BC7UnormBlock,
+        99 => Format::BC7SrgbBlock,
+        _ => return None,
+    })
+}
+
+//old style DDS files only have a four letter code, which doesn't say if it's sRGB so the role decides
+fn fourcc_format(fourcc: &[u8], role: TextureRole) -> Option<Format> {
+    let srgb = role == TextureRole::Color;
+    Some(match (fourcc, srgb) {
+        (b"DXT1", true) => Format::BC1_RGBASrgbBlock,
+        (b"DXT1", false) => Format::BC1_RGBAUnormBlock,
+        (b"DXT2", true) | (b"DXT3", true) => Format::BC2SrgbBlock,
+        (b"DXT2", false) | (b"DXT3", false) => Format::BC2UnormBlock,
+        (b"DXT4", true) | (b"DXT5", true) => Format::BC3SrgbBlock,
+        (b"DXT4", false) | (b"DXT5", false) => Format::BC3UnormBlock,
+        (b"ATI1", _) | (b"BC4U", _) => Format::BC4UnormBlock,
+        (b"BC4S", _) => Format::BC4SnormBlock,
+        (b"ATI2", _) | (b"BC5U", _) => Format::BC5UnormBlock,
+        (b"BC5S", _) => Format::BC5SnormBlock,
+        _ => return None,
+    })
+}
+
+fn parse_dds(bytes: &[u8], role: TextureRole) -> Result<ContainerImage, String> {
+    let flags = u32_at(bytes, 8)?;
+    let height = u32_at(bytes, 12)?;
+    let width = u32_at(bytes, 16)?;
+    let mip_count = if flags & DDSD_MIPMAPCOUNT != 0 { u32_at(bytes, 28)?.max(1) } else { 1 };
+    let pf_flags = u32_at(bytes, 80)?;
+    let fourcc = bytes.get(84..88).ok_or("file is cut short")?;
+    let caps2 = u32_at(bytes, 112)?;
+
+    if caps2 & (DDSCAPS2_CUBEMAP | DDSCAPS2_VOLUME) != 0 {
+        return Err("only 2D DDS textures are supported (no cubemaps or volumes)".to_string());
+    }
+
+    let mut data_start: usize = 128;
+    //BGRA pixels have to be swizzled in to RGBA since that's the format we upload them as
+    let mut swap_red_blue = false;
+    let mut opaque = false;
+    let format = if pf_flags & DDPF_FOURCC != 0 && fourcc == b"DX10" {
+        let dxgi = u32_at(bytes, 128)?;
+        let misc = u32_at(bytes, 136)?;
+        let array_size = u32_at(bytes, 140)?;
+        if misc & DDS_RESOURCE_MISC_TEXTURECUBE != 0 || array_size > 1 {
+            return Err("only 2D DDS textures are supported (no arrays or cubemaps)".to_string());
+        }
+        data_start = 148;
+        dxgi_format(dxgi, role).ok_or_else(|| format!("unsupported DXGI format {}", dxgi))?
+    } else if pf_flags & DDPF_FOURCC != 0 {
+        fourcc_format(fourcc, role).ok_or_else(|| format!("unsupported DDS format {:?}", String::from_utf8_lossy(fourcc)))?
+    } else if pf_flags & DDPF_RGB != 0 && u32_at(bytes, 88)? == 32 {
+        let (red_mask, blue_mask) = (u32_at(bytes, 92)?, u32_at(bytes, 100)?);
+        swap_red_blue = red_mask == 0x00ff0000 && blue_mask == 0x000000ff;
+        //X8R8G8B8 has junk where the alpha would be
+        opaque = pf_flags & DDPF_ALPHAPIXELS == 0;
+        if role == TextureRole::Color { Format::R8G8B8A8Srgb } else { Format::R8G8B8A8Unorm }
+    } else {
+        return Err("unsupported uncompressed DDS layout, only 32 bit RGBA/BGRA is read".to_string());
+    };
+    check_dimensions(width, height, mip_count)?;
+
+    //levels are just packed one after another
+    let mut ranges = Vec::new();
+    let mut offset = data_start;
+    for level in 0..mip_count {
+        let size = level_size(format, (width >> level).max(1), (height >> level).max(1)).unwrap_or(0);
+        ranges.push((offset, size));
+        offset = offset.checked_add(size).ok_or("mip levels add up to more than fits in memory")?;
+    }
+    let mut levels = read_levels(bytes, format, width, height, &ranges)?;
+
+    if swap_red_blue || opaque {
+        for level in levels.iter_mut() {
+            for px in level.chunks_exact_mut(4) {
+                if swap_red_blue {
+                    px.swap(0, 2);
+                }
+                if opaque {
+                    px[3] = 255;
+                }
+            }
+        }
+    }
+
+    Ok(ContainerImage { format, width, height, levels })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ktx2_round_trips() {
+        //6x5 BC7 with its whole chain, 2x2 blocks down to 1 block, each level filled with its own number
+        let levels = [(6, 5), (3, 2), (1, 1)]
+            .iter()
+            .enumerate()
+            .map(|(i, &(w, h))| vec![i as u8 + 1; level_size(Format::BC7SrgbBlock, w, h).unwrap()])
+            .collect::<Vec<_>>();
+        let image = ContainerImage { format: Format::BC7SrgbBlock, width: 6, height: 5, levels };
+
+        let path = std::env::temp_dir().join(format!("project_g_round_trip_{}.ktx2", std::process::id()));
+        write_ktx2(&path, &image).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).ok();
+
+        let read = parse_ktx2(&bytes).unwrap();
+        assert_eq!(read.format, image.format);
+        assert_eq!((read.width, read.height), (6, 5));
+        assert_eq!(read.levels, image.levels);
+    }
+}
@@ -0,0 +1,193 @@
+use half::f16;
+use vulkano::{format::Format, instance::PhysicalDevice};
+
+use crate::error::RendererError;
+
+//Uncompressed texture formats and turning them in to ones the GPU can actually sample
+//3 channel formats are the usual problem, desktop drivers mostly only do 1, 2 and 4 channels
+
+//How a single channel of a pixel is stored
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleType {
+    Unorm8,
+    //8 bit with the sRGB curve, the GPU decodes it when sampling
+    Srgb8,
+    Unorm16,
+    Float16,
+    Float32,
+}
+
+impl SampleType {
+    pub fn bytes(self) -> usize {
+        match self {
+            SampleType::Unorm8 | SampleType::Srgb8 => 1,
+            SampleType::Unorm16 | SampleType::Float16 => 2,
+            SampleType::Float32 => 4,
+        }
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, SampleType::Float16 | SampleType::Float32)
+    }
+}
+
+//channel count and sample type for the uncompressed formats we upload
+pub fn format_layout(format: Format) -> Option<(usize, SampleType)> {
+    match format {
+        Format::R8Unorm => Some((1, SampleType::Unorm8)),
+        Format::R8G8Unorm => Some((2, SampleType::Unorm8)),
+        Format::R8G8B8Unorm => Some((3, SampleType::Unorm8)),
+        Format::R8G8B8A8Unorm => Some((4, SampleType::Unorm8)),
+        Format::R8Srgb => Some((1, SampleType::Srgb8)),
+        Format::R8G8Srgb => Some((2, SampleType::Srgb8)),
+        Format::R8G8B8Srgb => Some((3, SampleType::Srgb8)),
+        Format::R8G8B8A8Srgb => Some((4, SampleType::Srgb8)),
+        Format::R16Unorm => Some((1, SampleType::Unorm16)),
+        Format::R16G16Unorm => Some((2, SampleType::Unorm16)),
+        Format::R16G16B16Unorm => Some((3, SampleType::Unorm16)),
+        Format::R16G16B16A16Unorm => Some((4, SampleType::Unorm16)),
+        Format::R16Sfloat => Some((1, SampleType::Float16)),
+        Format::R16G16Sfloat => Some((2, SampleType::Float16)),
+        Format::R16G16B16Sfloat => Some((3, SampleType::Float16)),
+        Format::R16G16B16A16Sfloat => Some((4, SampleType::Float16)),
+        Format::R32Sfloat => Some((1, SampleType::Float32)),
+        Format::R32G32Sfloat => Some((2, SampleType::Float32)),
+        Format::R32G32B32Sfloat => Some((3, SampleType::Float32)),
+        Format::R32G32B32A32Sfloat => Some((4, SampleType::Float32)),
+        _ => None,
+    }
+}
+
+//the other way around, every combination format_layout knows about has one
+pub fn format_for(channels: usize, sample: SampleType) -> Option<Format> {
+    match (channels, sample) {
+        (1, SampleType::Unorm8) => Some(Format::R8Unorm),
+        (2, SampleType::Unorm8) => Some(Format::R8G8Unorm),
+        (3, SampleType::Unorm8) => Some(Format::R8G8B8Unorm),
+        (4, SampleType::Unorm8) => Some(Format::R8G8B8A8Unorm),
+        (1, SampleType::Srgb8) => Some(Format::R8Srgb),
+        (2, SampleType::Srgb8) => Some(Format::R8G8Srgb),
+        (3, SampleType::Srgb8) => Some(Format::R8G8B8Srgb),
+        (4, SampleType::Srgb8) => Some(Format::R8G8B8A8Srgb),
+        (1, SampleType::Unorm16) => Some(Format::R16Unorm),
+        (2, SampleType::Unorm16) => Some(Format::R16G16Unorm),
+        (3, SampleType::Unorm16) => Some(Format::R16G16B16Unorm),
+        (4, SampleType::Unorm16) => Some(Format::R16G16B16A16Unorm),
+        (1, SampleType::Float16) => Some(Format::R16Sfloat),
+        (2, SampleType::Float16) => Some(Format::R16G16Sfloat),
+        (3, SampleType::Float16) => Some(Format::R16G16B16Sfloat),
+        (4, SampleType::Float16) => Some(Format::R16G16B16A16Sfloat),
+        (1, SampleType::Float32) => Some(Format::R32Sfloat),
+        (2, SampleType::Float32) => Some(Format::R32G32Sfloat),
+        (3, SampleType::Float32) => Some(Format::R32G32B32Sfloat),
+        (4, SampleType::Float32) => Some(Format::R32G32B32A32Sfloat),
+        _ => None,
+    }
+}
+
+pub fn is_srgb(format: Format) -> bool {
+    matches!(format_layout(format), Some((_, SampleType::Srgb8)))
+}
+
+//Reads one sample in native byte order, integer types come back in [0, 1] (sRGB is left encoded)
+pub fn read_sample(bytes: &[u8], sample: SampleType) -> f32 {
+    match sample {
+        SampleType::Unorm8 | SampleType::Srgb8 => bytes[0] as f32 / 255.0,
+        SampleType::Unorm16 => u16::from_ne_bytes([bytes[0], bytes[1]]) as f32 / 65535.0,
+        SampleType::Float16 => f16::from_ne_bytes([bytes[0], bytes[1]]).to_f32(),
+        SampleType::Float32 => f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+    }
+}
+
+//the opposite of read_sample, integer types get clamped in to range
+pub fn write_sample(v: f32, sample: SampleType, out: &mut Vec<u8>) {
+    match sample {
+        SampleType::Unorm8 | SampleType::Srgb8 => out.push((v.clamp(0.0, 1.0) * 255.0).round() as u8),
+        SampleType::Unorm16 => out.extend_from_slice(&((v.clamp(0.0, 1.0) * 65535.0).round() as u16).to_ne_bytes()),
+        SampleType::Float16 => out.extend_from_slice(&f16::from_f32(v).to_ne_bytes()),
+        SampleType::Float32 => out.extend_from_slice(&v.to_ne_bytes()),
+    }
+}
+
+//we sample everything with linear filtering so that has to work too
+pub fn can_sample(physical: PhysicalDevice, format: Format) -> bool {
+    let features = format.properties(physical).optimal_tiling_features;
+    features.sampled_image && features.sampled_image_filter_linear
+}
+
+//Widens pixels to RGBA, grey gets copied in to all three color channels and a missing alpha is fully opaque
+pub fn expand_to_rgba(pixels: &[u8], channels: usize, sample: SampleType) -> Vec<u8> {
+    expand(pixels, channels, sample, false)
+}
+
+//Same, except two channel pixels are red and green (normal maps, packed data) instead of grey and alpha
+//blue comes out as zero
+pub fn expand_rg_to_rgba(pixels: &[u8], channels: usize, sample: SampleType) -> Vec<u8> {
+    expand(pixels, channels, sample, true)
+}
+
+fn expand(pixels: &[u8], channels: usize, sample: SampleType, rg: bool) -> Vec<u8> {
+    if channels == 4 {
+        return pixels.to_vec();
+    }
+    let size = sample.bytes();
+    let mut opaque = Vec::new();
+    write_sample(1.0, sample, &mut opaque);
+    let mut zero = Vec::new();
+    write_sample(0.0, sample, &mut zero);
+    let mut out = Vec::with_capacity(pixels.len() / channels * 4);
+    for px in pixels.chunks_exact(channels * size) {
+        let s = |c: usize| &px[c * size..(c + 1) * size];
+        let (rgb, alpha) = match channels {
+            1 => ([s(0), s(0), s(0)], &opaque[..]),
+            2 if rg => ([s(0), s(1), &zero[..]], &opaque[..]),
+            2 => ([s(0), s(0), s(0)], s(1)),
+            _ => ([s(0), s(1), s(2)], &opaque[..]),
+        };
+        for c in rgb.iter() {
+            out.extend_from_slice(c);
+        }
+        out.extend_from_slice(alpha);
+    }
+    out
+}
+
+//re-stores every sample as a different type, values outside what the new type can hold get clamped
+pub fn convert_samples(pixels: &[u8], from: SampleType, to: SampleType) -> Vec<u8> {
+    let mut out = Vec::with_capacity(pixels.len() / from.bytes() * to.bytes());
+    for s in pixels.chunks_exact(from.bytes()) {
+        write_sample(read_sample(s, from), to, &mut out);
+    }
+    out
+}
+
+//Makes sure the GPU can sample the texture, converting the pixels if it can't
+//tries the format as is, then with four channels, then four channels of a smaller type
+pub fn make_sampleable(pixels: Vec<u8>, format: Format, physical: PhysicalDevice) -> Result<(Vec<u8>, Format), RendererError> {
+    if can_sample(physical, format) {
+        return Ok((pixels, format));
+    }
+    let (channels, sample) = format_layout(format).ok_or(RendererError::TextureFormat(format))?;
+
+    if let Some(wide) = format_for(4, sample).filter(|f| can_sample(physical, *f)) {
+        println!("{:?} can't be sampled on {}, converting to {:?}", format, physical.name(), wide);
+        return Ok((expand_to_rgba(&pixels, channels, sample), wide));
+    }
+
+    //32 bit floats can't always be filtered, 16 bit unorm is optional
+    let smaller = match sample {
+        SampleType::Float32 => Some(SampleType::Float16),
+        SampleType::Unorm16 => Some(SampleType::Unorm8),
+        _ => None,
+    };
+    if let Some(smaller) = smaller {
+        let narrow = format_for(4, smaller).expect("every sample type has an RGBA format");
+        if can_sample(physical, narrow) {
+            println!("WARNING: {:?} can't be sampled on {}, cutting it down to {:?}", format, physical.name(), narrow);
+            let rgba = expand_to_rgba(&pixels, channels, sample);
+            return Ok((convert_samples(&rgba, sample, smaller), narrow));
+        }
+    }
+
+    Err(RendererError::TextureFormat(format))
+}
@@ -0,0 +1,30 @@
+// picked when the pipeline is built, see Tonemapper in renderer.rs
+// 0 = Reinhard, 1 = ACES, 2 = Uncharted 2 filmic, 3 = none (just clamp)
+layout(constant_id = 0) const int tonemapper = 0;
+
+// Narkowicz's fit of the ACES reference curve
+vec3 tonemapACES(vec3 x) {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+// John Hable's filmic curve from Uncharted 2
+vec3 hable(vec3 x) {
+    const float A = 0.15, B = 0.50, C = 0.10, D = 0.20, E = 0.02, F = 0.30;
+    return ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F;
+}
+
+vec3 tonemapUncharted2(vec3 x) {
+    const float W = 11.2;
+    return hable(2.0 * x) / hable(vec3(W));
+}
+
+vec3 tonemap(vec3 color) {
+    if (tonemapper == 1) {
+        return tonemapACES(color);
+    } else if (tonemapper == 2) {
+        return tonemapUncharted2(color);
+    } else if (tonemapper == 3) {
+        return clamp(color, 0.0, 1.0);
+    }
+    return color / (color + vec3(1.0));
+}
@@ -5,9 +5,13 @@ pub struct Vertex {
     pub position: (f32, f32, f32),
     pub normal: (f32, f32, f32),
     pub uv: (f32, f32),
-    
+    //xyz is the tangent, w is the sign of the bitangent (MikkTSpace convention)
+    pub tangent: (f32, f32, f32, f32),
+    //which way tessellation pushes the vertex, the normal averaged over every vertex at the same position
+    //so the copies on either side of a hard edge or UV seam move together, filled in by the renderer on upload
+    pub displace: (f32, f32, f32),
 }
-vulkano::impl_vertex!(Vertex, position, normal, uv);
+vulkano::impl_vertex!(Vertex, position, normal, uv, tangent, displace);
 
 
 